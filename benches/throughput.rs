@@ -0,0 +1,68 @@
+// =================
+// THROUGHPUT
+// =================
+
+// timings that are too noisy to assert on in the tests, which cargo test
+// runs in parallel. run them with cargo bench
+
+use std::time::{Duration, Instant};
+
+use nasty_fun_automatas::nfa::NFA;
+
+fn main() {
+    program_is_match();
+    program_find();
+    literal_prefix();
+}
+
+// the fastest of a few runs, the one least disturbed by whatever else the
+// machine is doing
+fn time(mut run: impl FnMut()) -> Duration {
+    (0..5)
+        .map(|_| {
+            let started = Instant::now();
+            run();
+            started.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn report(what: &str, fast: Duration, slow: Duration) {
+    println!(
+        "{what}: {fast:?} vs {slow:?} ({:.1}x)",
+        slow.as_secs_f64() / fast.as_secs_f64()
+    );
+}
+
+// an input that keeps many threads alive: the VM walks flat instructions
+// with a flag per pc instead of sets of states
+fn program_is_match() {
+    let nfa = NFA::from_regex("(a|aa|aaa)*b").unwrap();
+    let program = nfa.to_program();
+    let input = "a".repeat(20_000);
+    let searched = time(|| assert!(!nfa.is_match(&input)));
+    let ran = time(|| assert!(!program.is_match(&input)));
+    report("Program::is_match vs NFA::is_match", ran, searched);
+}
+
+// NFA::find starts a search at every position that each reads to the end,
+// the unanchored program reads the input once
+fn program_find() {
+    let nfa = NFA::from_regex("a*b").unwrap();
+    let program = nfa.to_unanchored_program();
+    let input = "a".repeat(500);
+    let searched = time(|| assert_eq!(nfa.find(&input), None));
+    let ran = time(|| assert_eq!(program.find(&input), None));
+    report("Program::find vs NFA::find", ran, searched);
+}
+
+// contains_match skips to where the literal prefix occurs, find searches
+// from every position, and the prefix's first char is everywhere
+fn literal_prefix() {
+    let nfa = NFA::from_regex("abc.*x").unwrap();
+    let input = "ab".repeat(5_000) + "abcx";
+    let skipped = time(|| assert!(nfa.contains_match(&input)));
+    let searched = time(|| assert!(nfa.find(&input).is_some()));
+    report("NFA::contains_match vs NFA::find", skipped, searched);
+}
@@ -0,0 +1,19 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nasty_fun_automatas::nfa::NFA;
+use nasty_fun_automatas::{compile, parse_with_spans};
+
+// any pattern, however malformed, has to come back as Ok or Err. the
+// postfix and NFA construction unwrap on the assumption that validation
+// caught every bad pattern, so a panic here means it didn't
+fuzz_target!(|re: &str| {
+    let _ = parse_with_spans(re);
+    let _ = compile(re);
+    if let Ok(nfa) = NFA::from_regex(re) {
+        // the pattern itself makes a handy input
+        let _ = nfa.is_match(re);
+        let _ = nfa.find_iter(re);
+        let _ = nfa.captures(re);
+    }
+});
@@ -0,0 +1,378 @@
+// =================
+// AST
+// =================
+
+use crate::class::CharClass;
+use crate::error::RegexError;
+use crate::nfa::DEFAULT_MAX_DEPTH;
+use crate::parse::{calc_spanned_postfix, parse_re_to_spanned_tokens, Span, Token};
+
+// the parse tree of a regex. every node knows which chars of the regex
+// it came from (see Span), so editors can e.g. highlight the
+// subexpression under the cursor
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ast {
+    pub kind: AstKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AstKind {
+    Letter(char),
+    Wildcard,
+    Class(CharClass),
+    StartAnchor,
+    EndAnchor,
+    Concatenation(Box<Ast>, Box<Ast>),
+    Union(Box<Ast>, Box<Ast>),
+    KleeneQuantifier(Box<Ast>),
+    PositiveQuantifier(Box<Ast>),
+    OptionalQuantifier(Box<Ast>),
+    // {min}, {min,} or {min,max}
+    RepetitionQuantifier(Box<Ast>, usize, Option<usize>),
+    // a quantifier made lazy by a ? after it, e.g. a*?
+    Lazy(Box<Ast>),
+    // a parenthesized group and its index (counting open parentheses from 0)
+    Group(usize, Box<Ast>),
+    // matches only the empty string, like an empty side of a union (see
+    // CompileOptions::empty_alternatives) or what is left of a once the
+    // a is matched (see derivative)
+    Empty,
+    // matches nothing at all, e.g. what is left of a after a b
+    Nothing,
+}
+
+// cloned and compared with a stack too, see Ast::fold
+impl Clone for Ast {
+    fn clone(&self) -> Ast {
+        self.fold(|ast, children| Ast {
+            kind: ast.kind.with_children(children),
+            span: ast.span,
+        })
+    }
+}
+
+impl PartialEq for Ast {
+    fn eq(&self, other: &Ast) -> bool {
+        let mut pending = vec![(self, other)];
+        while let Some((a, b)) = pending.pop() {
+            if a.span != b.span || !a.kind.same_node(&b.kind) {
+                return false;
+            }
+            pending.extend(a.children().into_iter().zip(b.children()));
+        }
+        true
+    }
+}
+
+// dropping the boxes one by one, rather than letting each drop the ones
+// below it, which would overflow on a long literal like to_json would
+impl Drop for Ast {
+    fn drop(&mut self) {
+        let mut pending = vec![std::mem::replace(&mut self.kind, AstKind::Empty)];
+        while let Some(kind) = pending.pop() {
+            match kind {
+                AstKind::Concatenation(mut left, mut right)
+                | AstKind::Union(mut left, mut right) => {
+                    pending.push(std::mem::replace(&mut left.kind, AstKind::Empty));
+                    pending.push(std::mem::replace(&mut right.kind, AstKind::Empty));
+                }
+                AstKind::KleeneQuantifier(mut inner)
+                | AstKind::PositiveQuantifier(mut inner)
+                | AstKind::OptionalQuantifier(mut inner)
+                | AstKind::RepetitionQuantifier(mut inner, _, _)
+                | AstKind::Lazy(mut inner)
+                | AstKind::Group(_, mut inner) => {
+                    pending.push(std::mem::replace(&mut inner.kind, AstKind::Empty));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+pub fn parse_with_spans(re: &str) -> Result<Ast, RegexError> {
+    let postfix = calc_spanned_postfix(parse_re_to_spanned_tokens(re, DEFAULT_MAX_DEPTH)?)?;
+    Ok(Ast::from_postfix(postfix))
+}
+
+impl Ast {
+    // the postfix was validated, so every operator has its operands
+    fn from_postfix(postfix: Vec<(Token, Span)>) -> Ast {
+        let mut stack: Vec<Ast> = vec![];
+        for (token, span) in postfix {
+            let ast = match token {
+                Token::Letter(c) => Ast {
+                    kind: AstKind::Letter(c),
+                    span,
+                },
+                Token::Wildcard => Ast {
+                    kind: AstKind::Wildcard,
+                    span,
+                },
+                Token::Class(class) => Ast {
+                    kind: AstKind::Class(class),
+                    span,
+                },
+                Token::StartAnchor => Ast {
+                    kind: AstKind::StartAnchor,
+                    span,
+                },
+                Token::EndAnchor => Ast {
+                    kind: AstKind::EndAnchor,
+                    span,
+                },
+                Token::Empty => Ast {
+                    kind: AstKind::Empty,
+                    span,
+                },
+                Token::Concatenation | Token::Union => {
+                    let right = Box::new(stack.pop().unwrap());
+                    let left = Box::new(stack.pop().unwrap());
+                    let span = (left.span.0, right.span.1);
+                    let kind = if token == Token::Union {
+                        AstKind::Union(left, right)
+                    } else {
+                        AstKind::Concatenation(left, right)
+                    };
+                    Ast { kind, span }
+                }
+                Token::KleeneQuantifier | Token::PositiveQuantifier | Token::OptionalQuantifier => {
+                    let inner = Box::new(stack.pop().unwrap());
+                    // from the start of what is quantified to the quantifier
+                    let span = (inner.span.0, span.1);
+                    let kind = match token {
+                        Token::KleeneQuantifier => AstKind::KleeneQuantifier(inner),
+                        Token::PositiveQuantifier => AstKind::PositiveQuantifier(inner),
+                        _ => AstKind::OptionalQuantifier(inner),
+                    };
+                    Ast { kind, span }
+                }
+                Token::RepetitionQuantifier(min, max) => {
+                    let inner = Box::new(stack.pop().unwrap());
+                    let span = (inner.span.0, span.1);
+                    Ast {
+                        kind: AstKind::RepetitionQuantifier(inner, min, max),
+                        span,
+                    }
+                }
+                Token::Lazy => {
+                    let inner = Box::new(stack.pop().unwrap());
+                    let span = (inner.span.0, span.1);
+                    Ast {
+                        kind: AstKind::Lazy(inner),
+                        span,
+                    }
+                }
+                Token::Group(n) => {
+                    let inner = Box::new(stack.pop().unwrap());
+                    Ast {
+                        kind: AstKind::Group(n, inner),
+                        span,
+                    }
+                }
+                // parentheses should not be in the postfix
+                _ => unreachable!(),
+            };
+            stack.push(ast);
+        }
+        stack.pop().unwrap()
+    }
+    pub fn children(&self) -> Vec<&Ast> {
+        match &self.kind {
+            AstKind::Letter(_)
+            | AstKind::Wildcard
+            | AstKind::Class(_)
+            | AstKind::StartAnchor
+            | AstKind::EndAnchor
+            | AstKind::Empty
+            | AstKind::Nothing => vec![],
+            AstKind::Concatenation(left, right) | AstKind::Union(left, right) => {
+                vec![left, right]
+            }
+            AstKind::KleeneQuantifier(inner)
+            | AstKind::PositiveQuantifier(inner)
+            | AstKind::OptionalQuantifier(inner)
+            | AstKind::RepetitionQuantifier(inner, _, _)
+            | AstKind::Lazy(inner)
+            | AstKind::Group(_, inner) => vec![inner],
+        }
+    }
+    // combines what combine made of the children of every node into what
+    // it makes of the node, from the leaves up. a stack of results rather
+    // than recursion over the tree, which a long literal makes deep enough
+    // to overflow
+    pub(crate) fn fold<T>(&self, mut combine: impl FnMut(&Ast, Vec<T>) -> T) -> T {
+        let mut pending = vec![(self, false)];
+        let mut results: Vec<T> = vec![];
+        while let Some((ast, children_done)) = pending.pop() {
+            let children = ast.children();
+            if children_done || children.is_empty() {
+                let children = results.split_off(results.len() - children.len());
+                results.push(combine(ast, children));
+            } else {
+                pending.push((ast, true));
+                pending.extend(children.into_iter().rev().map(|child| (child, false)));
+            }
+        }
+        results.pop().unwrap()
+    }
+    // the smallest node whose span contains the char at index pos
+    pub fn node_at(&self, pos: usize) -> Option<&Ast> {
+        let contains = |node: &Ast| node.span.0 <= pos && pos < node.span.1;
+        if !contains(self) {
+            return None;
+        }
+        // down the tree rather than recursing, see to_json
+        let mut node = self;
+        while let Some(child) = node.children().into_iter().find(|child| contains(child)) {
+            node = child;
+        }
+        Some(node)
+    }
+    // the tree as JSON, e.g. for drawing it: every node is
+    // {"type":...,"span":[start,end],"children":[...]}, where the type is
+    // the AstKind in camelCase (concatenations are nodes of their own) and
+    // the span is in chars. letters also have their "value", classes their
+    // "ranges", repetitions their "min" and "max" (null for none) and
+    // groups their "index"
+    pub fn to_json(&self) -> String {
+        self.to_json_with(&|offset| offset)
+    }
+    // to_json with every offset of the spans passed through offset first,
+    // e.g. to turn them into utf-16 code units for JS
+    pub(crate) fn to_json_with(&self, offset: &dyn Fn(usize) -> usize) -> String {
+        // what's left to write, as a stack rather than recursion over the
+        // tree, which a long literal makes deep enough to overflow
+        enum Step<'a> {
+            Node(&'a Ast),
+            Comma,
+            Close,
+        }
+        let mut json = String::new();
+        let mut steps = vec![Step::Node(self)];
+        while let Some(step) = steps.pop() {
+            let node = match step {
+                Step::Node(node) => node,
+                Step::Comma => {
+                    json.push(',');
+                    continue;
+                }
+                Step::Close => {
+                    json.push_str("]}");
+                    continue;
+                }
+            };
+            let (kind, extra) = node.json_fields();
+            json.push_str(&format!(
+                r#"{{"type":"{kind}","span":[{},{}]{extra},"children":["#,
+                offset(node.span.0),
+                offset(node.span.1),
+            ));
+            steps.push(Step::Close);
+            for (i, child) in node.children().into_iter().enumerate().rev() {
+                steps.push(Step::Node(child));
+                if i > 0 {
+                    steps.push(Step::Comma);
+                }
+            }
+        }
+        json
+    }
+    // the type of the node for to_json, and its fields besides the span
+    // and children
+    fn json_fields(&self) -> (&'static str, String) {
+        match &self.kind {
+            AstKind::Letter(c) => ("letter", format!(r#","value":{}"#, json_string(*c))),
+            AstKind::Wildcard => ("wildcard", String::new()),
+            AstKind::Class(class) => {
+                let ranges: Vec<String> = class
+                    .ranges()
+                    .iter()
+                    .map(|&(low, high)| format!("[{},{}]", json_string(low), json_string(high)))
+                    .collect();
+                ("class", format!(r#","ranges":[{}]"#, ranges.join(",")))
+            }
+            AstKind::StartAnchor => ("startAnchor", String::new()),
+            AstKind::EndAnchor => ("endAnchor", String::new()),
+            AstKind::Concatenation(_, _) => ("concatenation", String::new()),
+            AstKind::Union(_, _) => ("union", String::new()),
+            AstKind::KleeneQuantifier(_) => ("kleeneQuantifier", String::new()),
+            AstKind::PositiveQuantifier(_) => ("positiveQuantifier", String::new()),
+            AstKind::OptionalQuantifier(_) => ("optionalQuantifier", String::new()),
+            AstKind::RepetitionQuantifier(_, min, max) => {
+                let max = max.map_or("null".to_string(), |max| max.to_string());
+                (
+                    "repetitionQuantifier",
+                    format!(r#","min":{min},"max":{max}"#),
+                )
+            }
+            AstKind::Lazy(_) => ("lazy", String::new()),
+            AstKind::Group(index, _) => ("group", format!(r#","index":{index}"#)),
+            AstKind::Empty => ("empty", String::new()),
+            AstKind::Nothing => ("nothing", String::new()),
+        }
+    }
+}
+
+impl AstKind {
+    // the same kind of node with other children, in the order of children
+    pub(crate) fn with_children(&self, children: Vec<Ast>) -> AstKind {
+        let mut children = children.into_iter().map(Box::new);
+        let mut child = || children.next().unwrap();
+        match self {
+            AstKind::Letter(c) => AstKind::Letter(*c),
+            AstKind::Wildcard => AstKind::Wildcard,
+            AstKind::Class(class) => AstKind::Class(class.clone()),
+            AstKind::StartAnchor => AstKind::StartAnchor,
+            AstKind::EndAnchor => AstKind::EndAnchor,
+            AstKind::Concatenation(_, _) => AstKind::Concatenation(child(), child()),
+            AstKind::Union(_, _) => AstKind::Union(child(), child()),
+            AstKind::KleeneQuantifier(_) => AstKind::KleeneQuantifier(child()),
+            AstKind::PositiveQuantifier(_) => AstKind::PositiveQuantifier(child()),
+            AstKind::OptionalQuantifier(_) => AstKind::OptionalQuantifier(child()),
+            AstKind::RepetitionQuantifier(_, min, max) => {
+                AstKind::RepetitionQuantifier(child(), *min, *max)
+            }
+            AstKind::Lazy(_) => AstKind::Lazy(child()),
+            AstKind::Group(index, _) => AstKind::Group(*index, child()),
+            AstKind::Empty => AstKind::Empty,
+            AstKind::Nothing => AstKind::Nothing,
+        }
+    }
+    // whether the nodes are the same, not counting their children
+    fn same_node(&self, other: &AstKind) -> bool {
+        match (self, other) {
+            (AstKind::Letter(a), AstKind::Letter(b)) => a == b,
+            (AstKind::Class(a), AstKind::Class(b)) => a == b,
+            (
+                AstKind::RepetitionQuantifier(_, a_min, a_max),
+                AstKind::RepetitionQuantifier(_, b_min, b_max),
+            ) => (a_min, a_max) == (b_min, b_max),
+            (AstKind::Group(a, _), AstKind::Group(b, _)) => a == b,
+            (a, b) => std::mem::discriminant(a) == std::mem::discriminant(b),
+        }
+    }
+}
+
+// the char as a JSON string, escaping what JSON doesn't allow as is
+fn json_string(c: char) -> String {
+    json_str(&c.to_string())
+}
+
+// the text as a JSON string, see json_string
+pub(crate) fn json_str(text: &str) -> String {
+    let mut json = "\"".to_string();
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str(r#"\""#),
+            '\\' => json.push_str(r"\\"),
+            c if c.is_control() => json.push_str(&format!(r"\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
@@ -0,0 +1,210 @@
+// =================
+// CHARACTER CLASSES
+// =================
+
+use crate::error::RegexError;
+use crate::parse::parse_octal;
+use crate::unicode::parse_property;
+
+// a set of chars, like the [a-z] in a regex. kept as sorted inclusive
+// ranges that don't overlap or touch, so two classes with the same chars
+// are always equal
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CharClass {
+    ranges: Vec<(char, char)>,
+}
+
+// the chars right after and before c, skipping the surrogates which
+// aren't chars
+pub(crate) fn next_char(c: char) -> Option<char> {
+    match c {
+        '\u{D7FF}' => Some('\u{E000}'),
+        _ => char::from_u32(c as u32 + 1),
+    }
+}
+pub(crate) fn prev_char(c: char) -> Option<char> {
+    match c {
+        '\u{E000}' => Some('\u{D7FF}'),
+        _ => char::from_u32((c as u32).checked_sub(1)?),
+    }
+}
+
+impl CharClass {
+    pub fn new(mut ranges: Vec<(char, char)>) -> CharClass {
+        ranges.retain(|(low, high)| low <= high);
+        ranges.sort();
+        let mut merged: Vec<(char, char)> = vec![];
+        for (low, high) in ranges {
+            match merged.last_mut() {
+                Some((_, last_high)) if next_char(*last_high).is_none_or(|next| low <= next) => {
+                    *last_high = (*last_high).max(high);
+                }
+                _ => merged.push((low, high)),
+            }
+        }
+        CharClass { ranges: merged }
+    }
+    pub fn ranges(&self) -> &[(char, char)] {
+        &self.ranges
+    }
+    pub fn contains(&self, c: char) -> bool {
+        self.ranges
+            .binary_search_by(|&(low, high)| {
+                if high < c {
+                    std::cmp::Ordering::Less
+                } else if low > c {
+                    std::cmp::Ordering::Greater
+                } else {
+                    std::cmp::Ordering::Equal
+                }
+            })
+            .is_ok()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+    // the smallest char in the class
+    pub fn first(&self) -> Option<char> {
+        self.ranges.first().map(|&(low, _)| low)
+    }
+    // every char in the class, in order
+    pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+        self.ranges.iter().flat_map(|&(low, high)| low..=high)
+    }
+    // every char that is not in the class
+    pub fn complement(&self) -> CharClass {
+        let mut ranges = vec![];
+        let mut low = Some('\0');
+        for &(range_low, range_high) in &self.ranges {
+            if let Some(gap_low) = low {
+                if let Some(gap_high) = prev_char(range_low).filter(|&high| gap_low <= high) {
+                    ranges.push((gap_low, gap_high));
+                }
+            }
+            low = next_char(range_high);
+        }
+        if let Some(low) = low {
+            ranges.push((low, char::MAX));
+        }
+        CharClass { ranges }
+    }
+    pub fn union(&self, other: &CharClass) -> CharClass {
+        CharClass::new([self.ranges.clone(), other.ranges.clone()].concat())
+    }
+    pub fn intersection(&self, other: &CharClass) -> CharClass {
+        self.complement().union(&other.complement()).complement()
+    }
+    pub fn difference(&self, other: &CharClass) -> CharClass {
+        self.intersection(&other.complement())
+    }
+}
+
+// parses a class at the start of chars (which is a [), returning it and
+// how many chars it takes up. inside the brackets:
+//   a-z      a range of chars
+//   [...]    a nested class, e.g. [a[0-9]] is the same as [a0-9]
+//   ^        at the start, negates the whole class
+//   x&&y     the chars in both x and y, e.g. [a-z&&[^aeiou]]
+//   x--y     the chars in x but not y, e.g. [a-z--aeiou]
+// && and -- go left to right, and \ escapes any char. classes can nest
+// max_depth deep, counting this one
+pub fn parse_class(chars: &[char], max_depth: usize) -> Result<(CharClass, usize), RegexError> {
+    if max_depth == 0 {
+        return Err(RegexError::TooDeep);
+    }
+    let mut i = 1;
+    let negated = chars.get(i) == Some(&'^');
+    if negated {
+        i += 1;
+    }
+
+    let mut class = parse_class_items(chars, &mut i, max_depth)?;
+    loop {
+        match (chars.get(i), chars.get(i + 1)) {
+            (Some(']'), _) => break,
+            (Some('&'), Some('&')) => {
+                i += 2;
+                class = class.intersection(&parse_class_items(chars, &mut i, max_depth)?);
+            }
+            (Some('-'), Some('-')) => {
+                i += 2;
+                class = class.difference(&parse_class_items(chars, &mut i, max_depth)?);
+            }
+            _ => return Err(RegexError::InvalidClass),
+        }
+    }
+
+    if negated {
+        class = class.complement();
+    }
+    Ok((class, i + 1))
+}
+
+// parses chars, ranges and nested classes from chars[*i] up to the next
+// ], && or --. there has to be at least one
+fn parse_class_items(
+    chars: &[char],
+    i: &mut usize,
+    max_depth: usize,
+) -> Result<CharClass, RegexError> {
+    let mut ranges = vec![];
+    loop {
+        // a \p{...} property class, the same as a nested class
+        if chars.get(*i) == Some(&'\\') {
+            if let Some(property) = parse_property(&chars[*i + 1..]) {
+                let (class, len) = property?;
+                ranges.extend_from_slice(class.ranges());
+                *i += 1 + len;
+                continue;
+            }
+        }
+        match (chars.get(*i), chars.get(*i + 1)) {
+            (None, _) => return Err(RegexError::InvalidClass),
+            (Some(']'), _) | (Some('&'), Some('&')) | (Some('-'), Some('-')) => break,
+            (Some('['), _) => {
+                let (nested, len) = parse_class(&chars[*i..], max_depth - 1)?;
+                ranges.extend_from_slice(nested.ranges());
+                *i += len;
+            }
+            _ => {
+                let low = parse_class_char(chars, i)?;
+                // a - that can't be a range (at the end, or before a
+                // nested class) is just a -
+                let is_range = chars.get(*i) == Some(&'-')
+                    && !matches!(chars.get(*i + 1), None | Some(']') | Some('[') | Some('-'));
+                if is_range {
+                    *i += 1;
+                    let high = parse_class_char(chars, i)?;
+                    if low > high {
+                        return Err(RegexError::InvalidClass);
+                    }
+                    ranges.push((low, high));
+                } else {
+                    ranges.push((low, low));
+                }
+            }
+        }
+    }
+    if ranges.is_empty() {
+        return Err(RegexError::InvalidClass);
+    }
+    Ok(CharClass::new(ranges))
+}
+
+fn parse_class_char(chars: &[char], i: &mut usize) -> Result<char, RegexError> {
+    let c = match chars.get(*i) {
+        Some('\\') => {
+            *i += 1;
+            if let Some((c, len)) = parse_octal(&chars[*i..]) {
+                *i += len;
+                return Ok(c);
+            }
+            *chars.get(*i).ok_or(RegexError::InvalidClass)?
+        }
+        Some(&c) => c,
+        None => return Err(RegexError::InvalidClass),
+    };
+    *i += 1;
+    Ok(c)
+}
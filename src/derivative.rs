@@ -0,0 +1,348 @@
+// =================
+// DERIVATIVES
+// =================
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::ast::{Ast, AstKind};
+use crate::class::{next_char, prev_char, CharClass};
+use crate::error::RegexError;
+use crate::nfa::{DEFAULT_MAX_STATES, DFA};
+
+// the node of the given kind, with the span of the node it was derived from
+fn node(kind: AstKind, span: (usize, usize)) -> Ast {
+    Ast { kind, span }
+}
+
+// the union of a and b, flattened so that nested unions are one list of
+// alternatives, without Nothing and without the same alternative twice.
+// without this the derivatives of a loop like (a|b)* grow with every char
+fn union(a: Ast, b: Ast) -> Ast {
+    let span = (a.span.0.min(b.span.0), a.span.1.max(b.span.1));
+    let mut alternatives: Vec<Ast> = vec![];
+    let mut stack = vec![b, a];
+    while let Some(mut ast) = stack.pop() {
+        match std::mem::replace(&mut ast.kind, AstKind::Nothing) {
+            AstKind::Union(left, right) => {
+                stack.push(*right);
+                stack.push(*left);
+            }
+            AstKind::Nothing => {}
+            kind => {
+                ast.kind = kind;
+                if !alternatives.iter().any(|other| other.kind == ast.kind) {
+                    alternatives.push(ast);
+                }
+            }
+        }
+    }
+    alternatives
+        .into_iter()
+        .reduce(|left, right| {
+            let span = (left.span.0, right.span.1.max(left.span.1));
+            node(AstKind::Union(Box::new(left), Box::new(right)), span)
+        })
+        .unwrap_or(node(AstKind::Nothing, span))
+}
+
+// a followed by b, where nothing after Nothing matches and Empty adds
+// nothing to what it's next to
+fn concat(a: Ast, b: Ast) -> Ast {
+    match (&a.kind, &b.kind) {
+        (AstKind::Nothing, _) => a,
+        (_, AstKind::Nothing) => b,
+        (AstKind::Empty, _) => b,
+        (_, AstKind::Empty) => a,
+        _ => {
+            let span = (a.span.0.min(b.span.0), a.span.1.max(b.span.1));
+            node(AstKind::Concatenation(Box::new(a), Box::new(b)), span)
+        }
+    }
+}
+
+// splits the chars into ranges that every set contains all or none of, and
+// numbers the classes of ranges that are in the same sets, like
+// NFA::char_classes. chars in no set are left out
+fn char_classes(sets: &[CharClass]) -> (Vec<(char, char, usize)>, usize) {
+    let mut starts = BTreeSet::from(['\0']);
+    for set in sets {
+        for &(low, high) in set.ranges() {
+            starts.insert(low);
+            starts.extend(next_char(high));
+        }
+    }
+
+    let starts: Vec<char> = starts.into_iter().collect();
+    let mut classes: BTreeMap<Vec<bool>, usize> = BTreeMap::new();
+    let mut ranges: Vec<(char, char, usize)> = vec![];
+    for (i, &low) in starts.iter().enumerate() {
+        let high = match starts.get(i + 1) {
+            Some(&next) => prev_char(next).unwrap(),
+            None => char::MAX,
+        };
+        let signature: Vec<bool> = sets.iter().map(|set| set.contains(low)).collect();
+        if !signature.contains(&true) {
+            continue;
+        }
+        let next_class = classes.len();
+        let class = *classes.entry(signature).or_insert(next_class);
+        match ranges.last_mut() {
+            Some((_, last_high, last_class))
+                if *last_class == class && next_char(*last_high) == Some(low) =>
+            {
+                *last_high = high
+            }
+            _ => ranges.push((low, high, class)),
+        }
+    }
+    (ranges, classes.len())
+}
+
+// whether a node matches the empty string at a position, given whether
+// its children do, see Ast::nullable_between
+fn nullable_node(kind: &AstKind, children: &[bool], at_start: bool, at_end: bool) -> bool {
+    match kind {
+        AstKind::Letter(_) | AstKind::Wildcard | AstKind::Class(_) | AstKind::Nothing => false,
+        AstKind::Empty => true,
+        AstKind::StartAnchor => at_start,
+        AstKind::EndAnchor => at_end,
+        AstKind::Concatenation(_, _) => children[0] && children[1],
+        AstKind::Union(_, _) => children[0] || children[1],
+        AstKind::KleeneQuantifier(_) | AstKind::OptionalQuantifier(_) => true,
+        AstKind::RepetitionQuantifier(_, min, _) => *min == 0 || children[0],
+        AstKind::PositiveQuantifier(_) | AstKind::Lazy(_) | AstKind::Group(_, _) => children[0],
+    }
+}
+
+// a canonical_key being built, with the operands of a chain of
+// concatenations or unions kept apart so the chain above can take them
+enum Key {
+    Concatenation(Vec<String>),
+    Union(BTreeSet<String>),
+    Other(String),
+}
+
+impl Key {
+    fn write(self) -> String {
+        match self {
+            Key::Concatenation(keys) => format!("cat({})", keys.join(",")),
+            Key::Union(keys) => {
+                let keys: Vec<String> = keys.into_iter().collect();
+                format!("alt({})", keys.join(","))
+            }
+            Key::Other(key) => key,
+        }
+    }
+}
+
+impl Ast {
+    // whether the regex matches the empty string, as the whole input, so
+    // ^ and $ both hold
+    pub fn nullable(&self) -> bool {
+        self.nullable_between(true, true)
+    }
+    // whether the regex matches the empty string at a position, knowing
+    // whether the position is at the start and at the end of the input
+    fn nullable_between(&self, at_start: bool, at_end: bool) -> bool {
+        self.fold(|ast, children: Vec<bool>| nullable_node(&ast.kind, &children, at_start, at_end))
+    }
+    // the brzozowski derivative by c: the regex matching whatever can
+    // follow c in a match of this one, so this regex matches cw exactly
+    // when the derivative matches w. the regex is matched from the start
+    // of the input, and since what is left never is, the ^ left in the
+    // derivative can't hold anymore and become Nothing. groups and
+    // laziness don't change what matches, so they are dropped on the way
+    pub fn derivative(&self, c: char) -> Ast {
+        self.derivative_at(c, true).without_start_anchors()
+    }
+    fn derivative_at(&self, c: char, at_start: bool) -> Ast {
+        // the derivative of every node along with whether it matches the
+        // empty string here, which a concatenation needs of its left side.
+        // there is a char after the position, so $ doesn't hold
+        let (derivative, _) = self.fold(|ast, children: Vec<(Ast, bool)>| {
+            let nullables: Vec<bool> = children.iter().map(|&(_, nullable)| nullable).collect();
+            let nullable = nullable_node(&ast.kind, &nullables, at_start, false);
+            let mut children = children.into_iter().map(|(derivative, _)| derivative);
+            let mut child = || children.next().unwrap();
+            // the derivative followed by rest, which is only copied when
+            // the derivative isn't Nothing
+            let followed_by = |derivative: Ast, rest: &dyn Fn() -> Ast| match derivative.kind {
+                AstKind::Nothing => derivative,
+                _ => concat(derivative, rest()),
+            };
+            let span = ast.span;
+            let derivative = match &ast.kind {
+                AstKind::Letter(letter) if *letter == c => node(AstKind::Empty, span),
+                AstKind::Wildcard => node(AstKind::Empty, span),
+                AstKind::Class(class) if class.contains(c) => node(AstKind::Empty, span),
+                AstKind::Letter(_)
+                | AstKind::Class(_)
+                | AstKind::StartAnchor
+                | AstKind::EndAnchor
+                | AstKind::Empty
+                | AstKind::Nothing => node(AstKind::Nothing, span),
+                // d(rs) = d(r)s, or d(s) too when r can match nothing here
+                AstKind::Concatenation(_, right) => {
+                    let (left_derivative, right_derivative) = (child(), child());
+                    let first = followed_by(left_derivative, &|| (**right).clone());
+                    match nullables[0] {
+                        true => union(first, right_derivative),
+                        false => first,
+                    }
+                }
+                AstKind::Union(_, _) => union(child(), child()),
+                // d(r*) = d(r)r*, and r+ is rr*
+                AstKind::KleeneQuantifier(inner) | AstKind::PositiveQuantifier(inner) => {
+                    followed_by(child(), &|| {
+                        node(AstKind::KleeneQuantifier(inner.clone()), span)
+                    })
+                }
+                AstKind::OptionalQuantifier(_) | AstKind::Lazy(_) | AstKind::Group(_, _) => child(),
+                // d(r{min,max}) = d(r)r{min-1,max-1}
+                AstKind::RepetitionQuantifier(_, _, Some(0)) => node(AstKind::Nothing, span),
+                AstKind::RepetitionQuantifier(inner, min, max) => followed_by(child(), &|| {
+                    node(
+                        AstKind::RepetitionQuantifier(
+                            inner.clone(),
+                            min.saturating_sub(1),
+                            max.map(|max| max - 1),
+                        ),
+                        span,
+                    )
+                }),
+            };
+            (derivative, nullable)
+        });
+        derivative
+    }
+    // the regex with every ^ replaced by Nothing, for what is left after
+    // the first char
+    fn without_start_anchors(&self) -> Ast {
+        self.fold(|ast, children: Vec<Ast>| {
+            let mut children = children;
+            match &ast.kind {
+                AstKind::StartAnchor => node(AstKind::Nothing, ast.span),
+                AstKind::Concatenation(_, _) => {
+                    let right = children.pop().unwrap();
+                    concat(children.pop().unwrap(), right)
+                }
+                AstKind::Union(_, _) => {
+                    let right = children.pop().unwrap();
+                    union(children.pop().unwrap(), right)
+                }
+                kind => node(kind.with_children(children), ast.span),
+            }
+        })
+    }
+    // whether the whole input matches, by taking the derivative by each
+    // char in turn and checking if what is left matches the empty string.
+    // gives the same answer as NFA::is_match, without building an automaton
+    pub fn matches_by_derivative(&self, input: &str) -> bool {
+        let mut ast = self.clone();
+        for c in input.chars() {
+            ast = ast.derivative(c);
+            if ast.kind == AstKind::Nothing {
+                return false;
+            }
+        }
+        ast.nullable()
+    }
+    // the chars each letter, class and wildcard of the regex takes
+    fn char_sets(&self) -> Vec<CharClass> {
+        let mut sets = vec![];
+        let mut pending = vec![self];
+        while let Some(ast) = pending.pop() {
+            match &ast.kind {
+                AstKind::Letter(c) => sets.push(CharClass::new(vec![(*c, *c)])),
+                AstKind::Wildcard => sets.push(CharClass::new(vec![('\0', char::MAX)])),
+                AstKind::Class(class) => sets.push(class.clone()),
+                _ => pending.extend(ast.children().into_iter().rev()),
+            }
+        }
+        sets
+    }
+    // a string that two regexes share when they are the same up to the
+    // order, nesting and repeats of the alternatives of their unions and
+    // the nesting of their concatenations. spans, groups and laziness
+    // don't change what matches, so they are left out
+    fn canonical_key(&self) -> String {
+        let key = self.fold(|ast, children: Vec<Key>| {
+            let mut children = children.into_iter();
+            let mut child = || children.next().unwrap();
+            match &ast.kind {
+                AstKind::Letter(c) => Key::Other(format!("{c:?}")),
+                AstKind::Wildcard => Key::Other(".".to_string()),
+                AstKind::Class(class) => Key::Other(format!("{:?}", class.ranges())),
+                AstKind::StartAnchor => Key::Other("^".to_string()),
+                AstKind::EndAnchor => Key::Other("$".to_string()),
+                AstKind::Empty => Key::Other("empty".to_string()),
+                AstKind::Nothing => Key::Other("nothing".to_string()),
+                AstKind::Concatenation(_, _) => {
+                    let mut keys = vec![];
+                    for operand in [child(), child()] {
+                        match operand {
+                            Key::Concatenation(operands) => keys.extend(operands),
+                            operand => keys.push(operand.write()),
+                        }
+                    }
+                    Key::Concatenation(keys)
+                }
+                AstKind::Union(_, _) => {
+                    let mut keys = BTreeSet::new();
+                    for operand in [child(), child()] {
+                        match operand {
+                            Key::Union(operands) => keys.extend(operands),
+                            operand => {
+                                keys.insert(operand.write());
+                            }
+                        }
+                    }
+                    Key::Union(keys)
+                }
+                AstKind::KleeneQuantifier(_) => Key::Other(format!("star({})", child().write())),
+                AstKind::PositiveQuantifier(_) => Key::Other(format!("plus({})", child().write())),
+                AstKind::OptionalQuantifier(_) => Key::Other(format!("opt({})", child().write())),
+                AstKind::RepetitionQuantifier(_, min, max) => {
+                    Key::Other(format!("rep({min},{max:?},{})", child().write()))
+                }
+                AstKind::Lazy(_) | AstKind::Group(_, _) => Key::Other(child().write()),
+            }
+        });
+        key.write()
+    }
+    // the minimal DFA for the regex, built from derivatives instead of an
+    // NFA: every state is a regex, starting with this one, the state after
+    // a char is its derivative by the char, and the states that match the
+    // empty string accept. derivatives with the same canonical_key are the
+    // same state, which there are finitely many of. errors with
+    // TooManyStates like NFA::to_dfa
+    pub fn to_dfa(&self) -> Result<DFA, RegexError> {
+        let (ranges, num_classes) = char_classes(&self.char_sets());
+        // a char from each class, to take the derivatives by
+        let mut representatives = vec!['\0'; num_classes];
+        for &(low, _, class) in ranges.iter().rev() {
+            representatives[class] = low;
+        }
+
+        let mut states = vec![self.clone()];
+        let mut ids = BTreeMap::from([(self.canonical_key(), 0)]);
+        let mut table = vec![];
+        let mut accepting = vec![];
+        while accepting.len() < states.len() {
+            let state = states[accepting.len()].clone();
+            accepting.push(state.nullable());
+            for &c in &representatives {
+                let next = state.derivative(c);
+                let next_id = *ids.entry(next.canonical_key()).or_insert_with(|| {
+                    states.push(next);
+                    states.len() - 1
+                });
+                table.push(next_id);
+            }
+            if states.len() > DEFAULT_MAX_STATES {
+                return Err(RegexError::TooManyStates);
+            }
+        }
+        Ok(DFA::from_table(ranges, num_classes, table, accepting))
+    }
+}
@@ -0,0 +1,290 @@
+// =================
+// DIAGNOSTICS
+// =================
+
+use std::fmt;
+use std::ops::Range;
+
+use crate::error::RegexError;
+use crate::nfa::{DEFAULT_MAX_DEPTH, NFA};
+use crate::parse::{calc_postfix, parse_re_to_spanned_tokens, Span, Token};
+
+// things worth telling the user about a regex. errors mean the regex could
+// not be compiled, everything else is a warning about a regex that works
+// but probably doesn't do what was intended
+#[derive(Debug, Clone, PartialEq)]
+pub enum Diagnostic {
+    Error(RegexError),
+    // `.*.*` matches the same strings as a single `.*`
+    RedundantWildcardStar,
+    // the same alternative appears twice in a union, e.g. `a|b|a`, so the
+    // second one can never be the reason something matches
+    DuplicateAlternative,
+    // an unescaped {, } or ] that is matched literally, since it doesn't
+    // start or end a quantifier or class. e.g. the { in `a{b}`
+    UnescapedLiteral(char),
+    // `*+`, `++` or `?+`, which is possessive in PCRE but works exactly
+    // like `*`, `+` or `?` here
+    PossessiveQuantifier,
+    // an alternative of a union that can never match, wherever it is, e.g.
+    // the b$c in `a|b$c` which would need a char after the end of the input
+    DeadAlternative(String),
+    // {min,max} quantifiers that copy what they repeat into about this
+    // many states, e.g. (a{10}){10}{10}. RepeatStrategy::Counter would
+    // build them in far fewer, see LARGE_REPETITION_STATES
+    LargeRepetition(usize),
+}
+
+// past this many estimated states, repetitions that RepeatStrategy::Counter
+// would build a lot smaller are worth a warning. a tenth of the default
+// max_states, so the warning comes well before compiling fails
+const LARGE_REPETITION_STATES: usize = 10_000;
+
+impl Diagnostic {
+    pub fn is_error(&self) -> bool {
+        matches!(self, Diagnostic::Error(_))
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Diagnostic::Error(err) => write!(f, "error: {err}"),
+            Diagnostic::RedundantWildcardStar => {
+                write!(f, "warning: `.*.*` is redundant, use a single `.*`")
+            }
+            Diagnostic::DuplicateAlternative => {
+                write!(f, "warning: alternative appears more than once in a union")
+            }
+            Diagnostic::UnescapedLiteral(c) => {
+                write!(
+                    f,
+                    "warning: `{c}` is matched literally, escape it as `\\{c}`"
+                )
+            }
+            Diagnostic::PossessiveQuantifier => {
+                write!(f, "warning: possessive quantifier is treated as greedy")
+            }
+            Diagnostic::DeadAlternative(alternative) => {
+                write!(f, "warning: alternative `{alternative}` can never match")
+            }
+            Diagnostic::LargeRepetition(estimate) => {
+                write!(
+                    f,
+                    "warning: repetitions expand to about {estimate} states, \
+                     consider RepeatStrategy::Counter"
+                )
+            }
+        }
+    }
+}
+
+// compiles the regex like NFA::from_regex, but also reports warnings
+// that don't stop compilation
+pub fn compile(re: &str) -> (Option<NFA>, Vec<Diagnostic>) {
+    with_nfa(re, warnings(re))
+}
+
+// compile, but strict: any warning is treated like an error, so there's no
+// NFA unless the regex has no diagnostics at all. for regexes built by
+// programs, where a construct that silently means something else (like a {
+// that ends up literal) is a bug. a large repetition is the error
+// RegexError::LargeRepetition, and then the regex isn't built at all
+pub fn compile_strict(re: &str) -> (Option<NFA>, Vec<Diagnostic>) {
+    let mut diagnostics = warnings(re);
+    let large = diagnostics.iter().find_map(|diagnostic| match diagnostic {
+        Diagnostic::LargeRepetition(estimate) => Some(*estimate),
+        _ => None,
+    });
+    if let Some(estimate) = large {
+        diagnostics.retain(|diagnostic| !matches!(diagnostic, Diagnostic::LargeRepetition(_)));
+        diagnostics.insert(0, Diagnostic::Error(RegexError::LargeRepetition(estimate)));
+        return (None, diagnostics);
+    }
+    let (nfa, diagnostics) = with_nfa(re, diagnostics);
+    if diagnostics.is_empty() {
+        (nfa, diagnostics)
+    } else {
+        (None, diagnostics)
+    }
+}
+
+// builds the regex, with the error that stopped it (if any) in front of
+// the warnings
+fn with_nfa(re: &str, mut diagnostics: Vec<Diagnostic>) -> (Option<NFA>, Vec<Diagnostic>) {
+    match NFA::from_regex(re) {
+        Ok(nfa) => (Some(nfa), diagnostics),
+        Err(err) => {
+            diagnostics.insert(0, Diagnostic::Error(err));
+            (None, diagnostics)
+        }
+    }
+}
+
+// the warnings for a regex, everything compile reports but its error
+fn warnings(re: &str) -> Vec<Diagnostic> {
+    // a malformed or too deep regex is reported below by NFA::from_regex
+    let spanned_tokens = parse_re_to_spanned_tokens(re, DEFAULT_MAX_DEPTH).unwrap_or_default();
+    let tokens: Vec<Token> = spanned_tokens
+        .iter()
+        .map(|(token, _)| token.clone())
+        .collect();
+    let mut diagnostics = vec![];
+
+    // escaped chars and possessive quantifiers take up two chars
+    for (token, (start, end)) in &spanned_tokens {
+        match token {
+            Token::Letter(c @ ('{' | '}' | ']')) if end - start == 1 => {
+                diagnostics.push(Diagnostic::UnescapedLiteral(*c));
+            }
+            Token::KleeneQuantifier | Token::PositiveQuantifier | Token::OptionalQuantifier
+                if end - start == 2 =>
+            {
+                diagnostics.push(Diagnostic::PossessiveQuantifier);
+            }
+            _ => {}
+        }
+    }
+
+    let wildcard_star = [
+        Token::Wildcard,
+        Token::KleeneQuantifier,
+        Token::Concatenation,
+        Token::Wildcard,
+        Token::KleeneQuantifier,
+    ];
+    if tokens
+        .windows(wildcard_star.len())
+        .any(|w| w == wildcard_star)
+    {
+        diagnostics.push(Diagnostic::RedundantWildcardStar);
+    }
+
+    for _ in 0..count_duplicate_alternatives(&tokens) {
+        diagnostics.push(Diagnostic::DuplicateAlternative);
+    }
+    for alternative in dead_alternatives(re, &spanned_tokens) {
+        diagnostics.push(Diagnostic::DeadAlternative(alternative));
+    }
+    // estimated before building, which is where a huge expansion would
+    // freeze or fail with TooManyStates
+    if let Ok(postfix) = calc_postfix(tokens.clone()) {
+        let estimate = estimate_states(&postfix, true);
+        if estimate > LARGE_REPETITION_STATES
+            && estimate_states(&postfix, false) <= LARGE_REPETITION_STATES
+        {
+            diagnostics.push(Diagnostic::LargeRepetition(estimate));
+        }
+    }
+    diagnostics
+}
+
+// the alternatives of every union (at the top level and in each group),
+// each as the range of tokens it covers. a group without | is a union of
+// one alternative
+fn unions(tokens: &[Token]) -> Vec<Vec<Range<usize>>> {
+    // one entry per open group: where the current alternative started and
+    // the alternatives seen so far
+    let mut stack: Vec<(usize, Vec<Range<usize>>)> = vec![(0, vec![])];
+    let mut unions = vec![];
+
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            Token::OpenParenthesis | Token::NonCapturingParenthesis => stack.push((i + 1, vec![])),
+            Token::CloseParenthesis if stack.len() > 1 => {
+                let (start, mut alternatives) = stack.pop().unwrap();
+                alternatives.push(start..i);
+                unions.push(alternatives);
+            }
+            Token::Union => {
+                let (start, alternatives) = stack.last_mut().unwrap();
+                alternatives.push(*start..i);
+                *start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    // groups left open by a malformed regex are dropped
+    let (start, mut alternatives) = stack.swap_remove(0);
+    alternatives.push(start..tokens.len());
+    unions.push(alternatives);
+    unions
+}
+
+// compares the alternatives of every union token by token
+fn count_duplicate_alternatives(tokens: &[Token]) -> usize {
+    unions(tokens)
+        .iter()
+        .map(|alternatives| {
+            (0..alternatives.len())
+                .filter(|&i| {
+                    alternatives[..i]
+                        .iter()
+                        .any(|earlier| tokens[earlier.clone()] == tokens[alternatives[i].clone()])
+                })
+                .count()
+        })
+        .sum()
+}
+
+// the text of every alternative that can't match anything on its own, like
+// b$c in a|b$c. each alternative is compiled by itself, and is dead when
+// its NFA matches nothing
+fn dead_alternatives(re: &str, spanned_tokens: &[(Token, Span)]) -> Vec<String> {
+    let tokens: Vec<Token> = spanned_tokens
+        .iter()
+        .map(|(token, _)| token.clone())
+        .collect();
+    let chars: Vec<char> = re.chars().collect();
+    let mut dead = vec![];
+    for alternatives in unions(&tokens) {
+        if alternatives.len() < 2 {
+            continue;
+        }
+        for alternative in alternatives {
+            // an empty alternative is already an error
+            if alternative.is_empty() {
+                continue;
+            }
+            let start = spanned_tokens[alternative.start].1 .0;
+            let end = spanned_tokens[alternative.end - 1].1 .1;
+            let text: String = chars[start..end].iter().collect();
+            if NFA::from_regex(&text).is_ok_and(|nfa| nfa.matches_nothing()) {
+                dead.push(text);
+            }
+        }
+    }
+    dead
+}
+
+// about how many states the NFA for the (valid) postfix has, multiplying
+// the states of each {min,max} by the copies it makes when expand is set,
+// and counting them once (like RepeatStrategy::Counter) otherwise. the same
+// number of copies as when building, with two states for every operand
+// and operator but concatenation. a stack of sizes rather than recursion
+// over the Ast, which a long literal would make deep enough to overflow
+fn estimate_states(postfix: &[Token], expand: bool) -> usize {
+    let mut sizes: Vec<usize> = vec![];
+    for token in postfix {
+        let size = match token {
+            Token::Concatenation | Token::Union => {
+                let right = sizes.pop().unwrap();
+                let left = sizes.pop().unwrap();
+                let extra = if *token == Token::Union { 2 } else { 0 };
+                left.saturating_add(right).saturating_add(extra)
+            }
+            Token::RepetitionQuantifier(min, max) if expand => {
+                let num_copies = max.unwrap_or((*min).max(1)).max(1);
+                sizes.pop().unwrap().saturating_mul(num_copies)
+            }
+            Token::Group(_) | Token::Lazy => sizes.pop().unwrap(),
+            Token::KleeneQuantifier
+            | Token::PositiveQuantifier
+            | Token::OptionalQuantifier
+            | Token::RepetitionQuantifier(_, _) => sizes.pop().unwrap().saturating_add(2),
+            _ => 2,
+        };
+        sizes.push(size);
+    }
+    sizes.pop().unwrap_or(0)
+}
@@ -0,0 +1,99 @@
+// =================
+// ERRORS
+// =================
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegexError {
+    // the regex, or a group in it, has nothing to match. e.g. `` or `()`
+    Empty,
+    // an operator is missing the pattern it applies to. e.g. `*a` or `a|`
+    MissingOperand,
+    // a `)` without a `(` to close
+    UnmatchedParenthesis,
+    // a `(` that is never closed
+    UnclosedParenthesis,
+    // a quantifier applied directly to another quantifier. e.g. `a**`
+    NestedQuantifier,
+    // a {min,max} quantifier where min is larger than max
+    InvalidRepetition,
+    // a malformed [...] class. e.g. `[a`, `[z-a]` or `[a&&]`
+    InvalidClass,
+    // groups or classes nested deeper than allowed. e.g. thousands of
+    // parentheses in `((((a))))`
+    TooDeep,
+    // a postfix given to NFA::from_postfix that no regex parses to. e.g.
+    // two operands and no operator, or a parenthesis
+    InvalidPostfix,
+    // the NFA would have more states than allowed, usually from
+    // expanding a large {min,max} quantifier
+    TooManyStates,
+    // {min,max} quantifiers that would expand to about this many states,
+    // where RepeatStrategy::Counter would build far fewer. only from
+    // compile_strict, which refuses them before building anything
+    LargeRepetition(usize),
+    // the group index does not exist in the regex
+    NoSuchGroup(usize),
+    // the group is inside of a {min,max} quantifier, so it was copied once
+    // per repetition and can't be patched on its own
+    RepeatedGroup(usize),
+    // bytes given to DFA::from_bytes that DFA::to_bytes didn't write, e.g.
+    // cut short or with a state that doesn't exist
+    InvalidDfa,
+    // a serialized DFA in a version of the format this build can't read
+    UnsupportedDfaVersion(u8),
+    // a \p{...} class with a name that isn't a general category, e.g.
+    // `\p{Foo}`
+    UnknownProperty(String),
+    // a \p{...} class in a build without the unicode-classes feature,
+    // which has the tables for them
+    PropertiesDisabled,
+}
+
+impl fmt::Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegexError::Empty => write!(f, "regex or group matches nothing"),
+            RegexError::MissingOperand => write!(f, "operator is missing an operand"),
+            RegexError::UnmatchedParenthesis => write!(f, "unmatched closing parenthesis"),
+            RegexError::UnclosedParenthesis => write!(f, "unclosed parenthesis"),
+            RegexError::NestedQuantifier => {
+                write!(f, "quantifier directly follows another quantifier")
+            }
+            RegexError::InvalidRepetition => {
+                write!(f, "repetition minimum is larger than its maximum")
+            }
+            RegexError::InvalidClass => write!(f, "malformed character class"),
+            RegexError::TooDeep => write!(f, "regex is nested too deeply"),
+            RegexError::InvalidPostfix => write!(f, "postfix is not a valid regex"),
+            RegexError::TooManyStates => write!(f, "regex compiles to too many states"),
+            RegexError::LargeRepetition(estimate) => {
+                write!(
+                    f,
+                    "repetitions expand to about {estimate} states, \
+                     use RepeatStrategy::Counter"
+                )
+            }
+            RegexError::NoSuchGroup(group) => write!(f, "no group {group} in regex"),
+            RegexError::RepeatedGroup(group) => {
+                write!(f, "group {group} is repeated by a {{min,max}} quantifier")
+            }
+            RegexError::InvalidDfa => write!(f, "bytes are not a valid serialized DFA"),
+            RegexError::UnsupportedDfaVersion(version) => {
+                write!(f, "serialized DFA has unsupported format version {version}")
+            }
+            RegexError::UnknownProperty(name) => {
+                write!(f, "no unicode general category named {name}")
+            }
+            RegexError::PropertiesDisabled => {
+                write!(
+                    f,
+                    "unicode property classes need the unicode-classes feature"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for RegexError {}
@@ -1,7 +1,24 @@
+use std::cell::{Cell, RefCell};
+use std::str::FromStr;
+
 use wasm_bindgen::prelude::*;
 
-mod nfa;
+mod ast;
+mod class;
+mod derivative;
+mod diagnostic;
+mod error;
+pub mod nfa;
 mod parse;
+mod unicode;
+
+pub use ast::{parse_with_spans, Ast, AstKind};
+pub use class::CharClass;
+pub use diagnostic::{compile, compile_strict, Diagnostic};
+pub use error::RegexError;
+pub use nfa::RegexSet;
+pub use parse::Token;
+pub use unicode::PROPERTY_NAMES;
 
 // a bit unconventional, but the tests are in a separate file from code
 #[cfg(test)]
@@ -10,16 +27,361 @@ mod tests;
 #[wasm_bindgen]
 pub struct Regex {
     nfa: nfa::NFA,
+    // inputs with more chars than this are never matched, so a public page
+    // can't be frozen by a huge paste
+    max_input_len: Option<usize>,
+    // whether the last input was rejected for being longer than max_input_len
+    input_too_long: Cell<bool>,
+}
+
+impl Regex {
+    // checks the input against max_input_len and records the result
+    fn within_bound(&self, input: &str) -> bool {
+        let too_long = match self.max_input_len {
+            Some(max) => input.chars().nth(max).is_some(),
+            None => false,
+        };
+        self.input_too_long.set(too_long);
+        !too_long
+    }
+}
+
+impl FromStr for Regex {
+    type Err = RegexError;
+
+    fn from_str(re: &str) -> Result<Regex, RegexError> {
+        Ok(Regex {
+            nfa: nfa::NFA::from_regex(re)?,
+            max_input_len: None,
+            input_too_long: Cell::new(false),
+        })
+    }
+}
+
+impl TryFrom<&str> for Regex {
+    type Error = RegexError;
+
+    fn try_from(re: &str) -> Result<Regex, RegexError> {
+        re.parse()
+    }
+}
+
+impl TryFrom<String> for Regex {
+    type Error = RegexError;
+
+    fn try_from(re: String) -> Result<Regex, RegexError> {
+        re.parse()
+    }
+}
+
+// the most chars CHAR_BUFFER keeps room for between calls, so one huge
+// input doesn't hold on to its memory for good
+const MAX_KEPT_CHARS: usize = 1 << 16;
+
+thread_local! {
+    // the chars of the last input to isMatchOwned, kept so the next call
+    // can reuse the allocation instead of collecting into a new one
+    static CHAR_BUFFER: RefCell<Vec<char>> = const { RefCell::new(vec![]) };
+}
+
+// utf-16 offset of every char in the input, plus the length of the input
+// in utf-16 code units. JS strings are indexed in code units, so char
+// spans have to be turned into these before JS can slice with them
+fn utf16_offsets(input: &str) -> Vec<usize> {
+    let mut offsets = vec![0];
+    for c in input.chars() {
+        offsets.push(offsets.last().unwrap() + c.len_utf16());
+    }
+    offsets
 }
 
 #[wasm_bindgen]
 impl Regex {
     pub fn new(str: String) -> Option<Regex> {
-        let nfa = nfa::NFA::from_regex(&str)?;
-        Some(Regex { nfa })
+        str.parse().ok()
+    }
+    // a regex that never matches anything, see NFA::never. handy as the
+    // "no match" case when a pipeline picks a regex per condition
+    pub fn never() -> Regex {
+        Regex {
+            nfa: nfa::NFA::never(),
+            max_input_len: None,
+            input_too_long: Cell::new(false),
+        }
+    }
+    // a regex whose full match is every input, the empty one included,
+    // see NFA::always_any. the counterpart of never
+    pub fn always() -> Regex {
+        Regex {
+            nfa: nfa::NFA::always_any(),
+            max_input_len: None,
+            input_too_long: Cell::new(false),
+        }
+    }
+    // like new, but in verbose mode: whitespace in the regex is ignored
+    // and # starts a comment to the end of the line, so a b c matches "abc"
+    #[allow(non_snake_case)]
+    pub fn newVerbose(str: String) -> Option<Regex> {
+        let options = nfa::CompileOptions {
+            verbose: true,
+            ..Default::default()
+        };
+        Some(Regex {
+            nfa: nfa::NFA::from_regex_with_options(&str, &options).ok()?,
+            max_input_len: None,
+            input_too_long: Cell::new(false),
+        })
+    }
+    // like new, but inputs longer than max_input_len chars are not matched:
+    // isMatch returns false, split returns no pieces, and inputTooLong
+    // returns true until the next input
+    #[allow(non_snake_case)]
+    pub fn newBounded(str: String, max_input_len: usize) -> Option<Regex> {
+        let mut regex = Regex::new(str)?;
+        regex.max_input_len = Some(max_input_len);
+        Some(regex)
+    }
+    // whether the last input was rejected for being too long, see newBounded
+    #[allow(non_snake_case)]
+    pub fn inputTooLong(&self) -> bool {
+        self.input_too_long.get()
+    }
+    // alias of fullMatch, kept for existing callers
+    #[allow(non_snake_case)]
+    pub fn isMatch(&self, input: String) -> bool {
+        self.fullMatch(input)
+    }
+    // isMatch for tight loops: the chars of the input go into a buffer
+    // that's reused from call to call (see CHAR_BUFFER), instead of a new
+    // one for every input. same result as isMatch
+    #[allow(non_snake_case)]
+    pub fn isMatchOwned(&self, input: String) -> bool {
+        if !self.within_bound(&input) {
+            return false;
+        }
+        CHAR_BUFFER.with_borrow_mut(|chars| {
+            chars.clear();
+            chars.extend(input.chars());
+            let matched = self.nfa.is_match_chars(chars);
+            chars.clear();
+            chars.shrink_to(MAX_KEPT_CHARS);
+            matched
+        })
+    }
+    // whether the whole input matches
+    #[allow(non_snake_case)]
+    pub fn fullMatch(&self, input: String) -> bool {
+        self.within_bound(&input) && self.nfa.full_match(&input)
+    }
+    // whether typing more could turn the input into a full match, see
+    // NFA::is_valid_prefix. false for an over-long input
+    #[allow(non_snake_case)]
+    pub fn isValidPrefix(&self, input: String) -> bool {
+        self.within_bound(&input) && self.nfa.is_valid_prefix(&input)
+    }
+    // whether some substring of the input matches
+    #[allow(non_snake_case)]
+    pub fn containsMatch(&self, input: String) -> bool {
+        self.within_bound(&input) && self.nfa.contains_match(&input)
+    }
+    // the number of non-overlapping matches in the input, 0 for an
+    // over-long input
+    #[allow(non_snake_case)]
+    pub fn matchesCount(&self, input: String) -> usize {
+        if !self.within_bound(&input) {
+            return 0;
+        }
+        self.nfa.matches_count(&input)
+    }
+    pub fn split(&self, input: String) -> Vec<String> {
+        if !self.within_bound(&input) {
+            return vec![];
+        }
+        self.nfa
+            .split(&input)
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+    pub fn splitn(&self, input: String, n: usize) -> Vec<String> {
+        if !self.within_bound(&input) {
+            return vec![];
+        }
+        self.nfa
+            .splitn(&input, n)
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+    // see NFA::split_inclusive
+    #[allow(non_snake_case)]
+    pub fn splitInclusive(&self, input: String) -> Vec<String> {
+        if !self.within_bound(&input) {
+            return vec![];
+        }
+        self.nfa
+            .split_inclusive(&input)
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+    // the input with the first (leftmost-longest) match replaced, taken
+    // literally. over-long inputs come back unchanged
+    pub fn replace(&self, input: String, replacement: String) -> String {
+        if !self.within_bound(&input) {
+            return input;
+        }
+        self.nfa.replace(&input, &replacement)
+    }
+    // like replace, but for every non-overlapping match
+    #[allow(non_snake_case)]
+    pub fn replaceAll(&self, input: String, replacement: String) -> String {
+        if !self.within_bound(&input) {
+            return input;
+        }
+        self.nfa.replace_all(&input, &replacement)
+    }
+    // like replaceAll, but an uppercase or title case match gets the
+    // replacement in the same case, see NFA::replace_all_preserving_case
+    #[allow(non_snake_case)]
+    pub fn replaceAllPreservingCase(&self, input: String, replacement: String) -> String {
+        if !self.within_bound(&input) {
+            return input;
+        }
+        self.nfa.replace_all_preserving_case(&input, &replacement)
+    }
+    // the groups of a match as a JSON array with {"start":s,"end":e} for
+    // each group, or null for a group that took no part in the match. the
+    // whole input has to match, otherwise this is just null. offsets are in
+    // utf-16 code units like JS string indices (not bytes or chars), so
+    // input.slice(start, end) is the group's text even with emoji around
+    #[allow(non_snake_case)]
+    pub fn capturesJs(&self, input: String) -> String {
+        if !self.within_bound(&input) {
+            return "null".to_string();
+        }
+        let captures = match self.nfa.captures(&input) {
+            Some(captures) => captures,
+            None => return "null".to_string(),
+        };
+        let offsets = utf16_offsets(&input);
+        let groups: Vec<String> = captures
+            .into_iter()
+            .map(|span| match span {
+                Some((start, end)) => {
+                    format!(r#"{{"start":{},"end":{}}}"#, offsets[start], offsets[end])
+                }
+                None => "null".to_string(),
+            })
+            .collect();
+        format!("[{}]", groups.join(","))
+    }
+    // the number of capturing groups, so the length of the capturesJs
+    // array, see NFA::group_count
+    #[allow(non_snake_case)]
+    pub fn groupCount(&self) -> usize {
+        self.nfa.group_count()
+    }
+    // where the leftmost-longest match starts as JSON, {"line":l,"column":c},
+    // or null when nothing matches. both count from 0, and like capturesJs
+    // the column is in utf-16 code units
+    #[allow(non_snake_case)]
+    pub fn findLineCol(&self, input: String) -> String {
+        if !self.within_bound(&input) {
+            return "null".to_string();
+        }
+        let (start, _) = match self.nfa.find(&input) {
+            Some(span) => span,
+            None => return "null".to_string(),
+        };
+        let (line, column) = nfa::line_col(&input, start);
+        // the column in chars, turned into code units of the same line
+        let line_start = start - column;
+        let offsets = utf16_offsets(&input);
+        let column = offsets[start] - offsets[line_start];
+        format!(r#"{{"line":{line},"column":{column}}}"#)
+    }
+    // the size of the compiled automaton, for warning about a pattern that
+    // compiles to a lot more than it looks like, e.g. (a{100}){100}
+    #[allow(non_snake_case)]
+    pub fn numStates(&self) -> usize {
+        self.nfa.num_states()
+    }
+    #[allow(non_snake_case)]
+    pub fn numTransitions(&self) -> usize {
+        self.nfa.num_transitions()
+    }
+    // the automaton as nodes and links for a force-directed layout, see
+    // NFA::to_graph_json. node ids are state ids, as in numStates
+    #[allow(non_snake_case)]
+    pub fn graphJson(&self) -> String {
+        self.nfa.to_graph_json()
+    }
+    // whether the pattern starts with ^ on every branch, so it can only
+    // match at the start of the input
+    #[allow(non_snake_case)]
+    pub fn isAnchoredStart(&self) -> bool {
+        self.nfa.is_anchored_start()
+    }
+    // whether the pattern ends with $ on every branch, so it can only
+    // match at the end of the input
+    #[allow(non_snake_case)]
+    pub fn isAnchoredEnd(&self) -> bool {
+        self.nfa.is_anchored_end()
+    }
+    // the text as a pattern that matches it literally, see nfa::escape
+    pub fn escape(text: &str) -> String {
+        nfa::escape(text)
+    }
+    // messages for the errors and warnings in a regex, see compile
+    pub fn diagnostics(str: String) -> Vec<String> {
+        let (_, diagnostics) = compile(&str);
+        diagnostics.iter().map(|d| d.to_string()).collect()
+    }
+    // the parse tree of a regex as JSON for drawing it, see Ast::to_json,
+    // or null if the regex is invalid. like capturesJs the spans are in
+    // utf-16 code units, so pattern.slice(start, end) is a node's source
+    #[allow(non_snake_case)]
+    pub fn astJson(str: String) -> String {
+        match parse_with_spans(&str) {
+            Ok(ast) => {
+                let offsets = utf16_offsets(&str);
+                ast.to_json_with(&|offset| offsets[offset])
+            }
+            Err(_) => "null".to_string(),
+        }
+    }
+    // experimental, see NFA::patch_group
+    #[allow(non_snake_case)]
+    pub fn patchGroup(&mut self, group: usize, str: String) -> bool {
+        self.nfa.patch_group(group, &str).is_ok()
+    }
+    // the minimal DFA for the regex in the binary format of DFA::to_bytes,
+    // to be loaded with Dfa.fromBytes. undefined if the DFA is too big
+    #[allow(non_snake_case)]
+    pub fn toDfaBytes(&self) -> Option<Vec<u8>> {
+        Some(self.nfa.to_dfa().ok()?.to_bytes())
+    }
+}
+
+// a precompiled regex, from the bytes of Regex.toDfaBytes. only matches
+// whole inputs, but without parsing or compiling anything when it loads
+#[wasm_bindgen]
+pub struct Dfa {
+    dfa: nfa::DFA,
+}
+
+#[wasm_bindgen]
+impl Dfa {
+    // undefined if the bytes aren't a DFA, see DFA::from_bytes
+    #[allow(non_snake_case)]
+    pub fn fromBytes(bytes: &[u8]) -> Option<Dfa> {
+        let dfa = nfa::DFA::from_bytes(bytes).ok()?;
+        Some(Dfa { dfa })
     }
+    // whether the whole input matches
     #[allow(non_snake_case)]
     pub fn isMatch(&self, input: String) -> bool {
-        self.nfa.is_match(&input)
+        self.dfa.is_match(&input)
     }
 }
@@ -2,144 +2,855 @@
 // NFA
 // =================
 
-use std::collections::{HashSet, VecDeque};
+use std::collections::{BTreeSet, VecDeque};
+use std::hash::{Hash, Hasher};
 
-use crate::parse::{calc_postfix, parse_re_to_tokens, Token};
+use crate::class::CharClass;
+use crate::error::RegexError;
+use counter::CounterOp;
 
-#[derive(Debug)]
+use crate::parse::{
+    calc_postfix, insert_empty_operands, parse_re_to_tokens, strip_verbose, validate_postfix, Token,
+};
+
+mod analysis;
+mod captures;
+mod counter;
+mod decompile;
+mod dfa;
+mod graph;
+#[cfg(feature = "graphemes")]
+mod graphemes;
+mod invariants;
+mod matcher;
+mod normalize;
+mod program;
+mod set;
+mod simplify;
+mod slices;
+mod symbol;
+
+pub use analysis::WILDCARD_PLACEHOLDER;
+pub use captures::Captures;
+pub use decompile::escape;
+pub use dfa::DFA;
+pub use matcher::{Checkpoint, Matcher};
+pub use normalize::case_fold;
+pub use program::Program;
+pub use set::RegexSet;
+pub use slices::Match;
+pub use symbol::{Label, Symbol, SymbolNFA};
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 enum TransitionLabel {
     Letter(char),
     Wildcard,
+    // any char of the class. unlike the wildcard, not restricted by
+    // the wildcard set
+    Class(CharClass),
     Epsilon,
-    None,
+    // like an epsilon, but only taken where the assertion holds
+    Assertion(Assertion),
+    // like an epsilon, but checks and updates a counter of a counted loop
+    // from RepeatStrategy::Counter
+    Counter(CounterOp),
 }
-#[derive(Debug)]
-struct Transition {
-    label: TransitionLabel,
-    to: usize,
+// zero-width checks on the chars around a position in the input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Assertion {
+    // between a word char and a non-word char, or the start or end of the
+    // input next to a word char
+    WordBoundary,
+    // ^, the start of the input
+    StartText,
+    // $, the end of the input
+    EndText,
+    // ^ in multiline mode, the start of the input or right after a \n
+    StartLine,
+    // $ in multiline mode, the end of the input or right before a \n
+    EndLine,
+}
+
+// every word an alternation of plain words matches, like ["cat", "cot",
+// "cut"] for cat|cot|cut or ["ab", "ac"] for a(b|c). None unless the
+// postfix is only letters (and empty operands) put together with unions
+// and concatenations, with a union somewhere, and there are at most
+// MAX_FACTORED_WORDS words
+fn literal_words(postfix: &[Token]) -> Option<Vec<String>> {
+    if !postfix.contains(&Token::Union) {
+        return None;
+    }
+    let mut stack: Vec<Vec<String>> = vec![];
+    for token in postfix {
+        match token {
+            Token::Letter(c) => stack.push(vec![c.to_string()]),
+            Token::Empty => stack.push(vec![String::new()]),
+            Token::Union => {
+                let right = stack.pop()?;
+                let mut left = stack.pop()?;
+                left.extend(right);
+                stack.push(left);
+            }
+            Token::Concatenation => {
+                let right = stack.pop()?;
+                let left = stack.pop()?;
+                if left.len() * right.len() > MAX_FACTORED_WORDS {
+                    return None;
+                }
+                let words = left
+                    .iter()
+                    .flat_map(|l| right.iter().map(move |r| format!("{l}{r}")))
+                    .collect();
+                stack.push(words);
+            }
+            _ => return None,
+        }
+        if stack.last()?.len() > MAX_FACTORED_WORDS {
+            return None;
+        }
+    }
+    let words = stack.pop()?;
+    stack.is_empty().then_some(words)
+}
+
+// what ^ and $ take as the end of a line in multiline mode (and line_col
+// too): \n, \r, and the unicode line and paragraph separators. a \r\n is
+// a single line break, so neither holds between its \r and \n
+pub const LINE_TERMINATORS: [char; 4] = ['\n', '\r', '\u{2028}', '\u{2029}'];
+
+fn is_line_terminator(c: char) -> bool {
+    LINE_TERMINATORS.contains(&c)
 }
-impl Transition {
-    const NONE: Transition = Transition {
-        label: TransitionLabel::None,
-        to: 0,
+
+// what a . compiles to: the wildcard, or without dot_matches_newline the
+// class of what the wildcard matches apart from the line terminators
+fn wildcard_label(options: &CompileOptions) -> TransitionLabel {
+    if options.dot_matches_newline {
+        return TransitionLabel::Wildcard;
+    }
+    let terminators = CharClass::new(LINE_TERMINATORS.map(|c| (c, c)).to_vec());
+    let class = match &options.wildcard_set {
+        Some(set) => CharClass::new(set.iter().map(|&c| (c, c)).collect()).difference(&terminators),
+        None => terminators.complement(),
     };
+    TransitionLabel::Class(class)
+}
+
+// letters, digits and _
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
 }
-#[derive(Debug)]
-struct State {
-    // thompson NFAs branches at most
-    num_transitions: usize,
-    transitions: [Transition; 2],
+
+// the matching algorithm behind NFA::search and SymbolNFA::is_match. a
+// breadth first search over (idx of input, state, extra) triples from
+// start, calling visit on each as it is dequeued until visit returns
+// false. step says where a transition goes from a triple, as the next idx
+// and extra, or None if it can't be taken. the extra is whatever else
+// tells two triples apart, like the counts of counted loops.
+// triples are marked visited when they are queued, so an epsilon cycle
+// (e.g. from (a?)*) can't queue the same one more than once
+fn breadth_first<L, X: Ord + Clone>(
+    states: &[State<L>],
+    start: (usize, usize, X),
+    mut visit: impl FnMut(usize, usize) -> bool,
+    mut step: impl FnMut(&(usize, usize, X), &Transition<L>) -> Option<(usize, X)>,
+) -> MatchStats {
+    let mut visited: BTreeSet<(usize, usize, X)> = BTreeSet::new();
+    let mut queue = VecDeque::<(usize, usize, X)>::new();
+    let mut stats = MatchStats::default();
+    let mut closure_idxs: BTreeSet<usize> = BTreeSet::new();
+    queue.push_back(start.clone());
+    visited.insert(start);
+
+    while let Some(current) = queue.pop_front() {
+        let (idx, state_id, _) = current;
+        stats.states_dequeued += 1;
+        if closure_idxs.insert(idx) {
+            stats.closures_computed += 1;
+        }
+        if !visit(idx, state_id) {
+            return stats;
+        }
+
+        // enqueue all
+        for transition in states[state_id].active_transitions() {
+            if let Some((next_idx, extra)) = step(&current, transition) {
+                let next = (next_idx, transition.to, extra);
+                if visited.insert(next.clone()) {
+                    queue.push_back(next);
+                }
+            }
+        }
+        stats.max_frontier_size = stats.max_frontier_size.max(queue.len());
+    }
+    stats
+}
+
+impl Assertion {
+    // whether the assertion holds between the chars before and after a
+    // position, None being the start or end of the input
+    fn holds(&self, before: Option<char>, after: Option<char>) -> bool {
+        match self {
+            Assertion::WordBoundary => {
+                before.is_some_and(is_word_char) != after.is_some_and(is_word_char)
+            }
+            Assertion::StartText => before.is_none(),
+            Assertion::EndText => after.is_none(),
+            Assertion::StartLine | Assertion::EndLine
+                if before == Some('\r') && after == Some('\n') =>
+            {
+                false
+            }
+            Assertion::StartLine => before.is_none_or(is_line_terminator),
+            Assertion::EndLine => after.is_none_or(is_line_terminator),
+        }
+    }
+    fn holds_at(&self, chars: &[char], idx: usize) -> bool {
+        let before = idx.checked_sub(1).map(|i| chars[i]);
+        self.holds(before, chars.get(idx).copied())
+    }
+}
+// states and transitions are generic over what a transition reads, so a
+// SymbolNFA is made of the same states with a Label<S> on each transition
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct Transition<L = TransitionLabel> {
+    label: L,
+    to: usize,
+}
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct State<L = TransitionLabel> {
+    // thompson NFAs branch at most twice, but a flattened union
+    // branches once per alternative
+    transitions: Vec<Transition<L>>,
     accepting: bool,
 }
 
-impl State {
-    fn new() -> State {
-        let transitions: [Transition; 2] = [Transition::NONE, Transition::NONE];
+impl<L> State<L> {
+    fn new() -> State<L> {
         State {
-            num_transitions: 0,
-            transitions,
+            transitions: vec![],
             accepting: false,
         }
     }
-    fn with_transition(mut self, transition: Transition) -> Self {
-        self.transitions[self.num_transitions] = transition;
-        self.num_transitions += 1;
+    fn with_transition(mut self, transition: Transition<L>) -> Self {
+        self.transitions.push(transition);
         self
     }
-    fn add_transition(&mut self, transition: Transition) {
-        self.transitions[self.num_transitions] = transition;
-        self.num_transitions += 1;
+    fn add_transition(&mut self, transition: Transition<L>) {
+        self.transitions.push(transition);
     }
     fn set_accepting(&mut self, accepting: bool) {
         self.accepting = accepting;
     }
+    // the transitions out of the state. a state holds exactly as many as
+    // it has, so there's never an empty slot to skip over
+    fn active_transitions(&self) -> &[Transition<L>] {
+        &self.transitions
+    }
 }
 
 struct NFAFragement {
+    // a fragment's states are always the block from first_id to the end
+    // of the states, since every operator only adds states after its operands
+    first_id: usize,
     start_id: usize,
     out_id: usize,
+    // whether this is a union whose start and out can take more branches
+    is_union: bool,
 }
 
+// the most states a compiled regex can have by default. normal regexes
+// don't come close, but a{1000000} would expand to millions of states
+pub const DEFAULT_MAX_STATES: usize = 100_000;
+
+// how deep groups and classes can nest by default. far deeper than any
+// regex a person writes, but shallow enough for what still recurses into
+// them, like parsing nested classes. it doesn't bound how deep the Ast
+// is: a literal is a chain of concatenations as long as it is, so what
+// walks an Ast (to_json, derivatives, dropping it) does so with a stack
+pub const DEFAULT_MAX_DEPTH: usize = 250;
+
+// the most words an alternation is factored into, see literal_words. every
+// word is spelled out, so (a|b)(c|d)... could otherwise have millions
+const MAX_FACTORED_WORDS: usize = 1_000;
+
+// options that change how a regex gets compiled
+#[derive(Debug, Clone)]
+pub struct CompileOptions {
+    // restricts what the wildcard (.) matches, e.g. only DNA
+    // bases ACGT. None means the wildcard matches any char
+    pub wildcard_set: Option<BTreeSet<char>>,
+    // compiling fails with RegexError::TooManyStates past this many states
+    pub max_states: usize,
+    // compiling fails with RegexError::TooDeep if groups and classes are
+    // nested deeper than this
+    pub max_depth: usize,
+    // only match whole words, as if the regex had word boundaries on both
+    // sides: cat is found in "a cat here" but not in "category"
+    pub whole_word: bool,
+    // ^ and $ also match at the start and end of every line, right after
+    // and right before one of the LINE_TERMINATORS, instead of only at the
+    // start and end of the input
+    pub multiline: bool,
+    // verbose (extended) mode: whitespace in the regex is ignored and #
+    // starts a comment to the end of the line, see strip_verbose. escape
+    // them (\  and \#) or put them in a class to match them
+    pub verbose: bool,
+    // allows a union with an empty side, which matches the empty string:
+    // |abc matches "" and "abc", and so does abc|. off by default, where a
+    // missing operand is RegexError::MissingOperand
+    pub empty_alternatives: bool,
+    // how {min,max} quantifiers are built, see RepeatStrategy
+    pub repeat_strategy: RepeatStrategy,
+    // which match find and everything built on it picks, see MatchKind. a
+    // regex with a lazy quantifier is always LeftmostFirst
+    pub match_kind: MatchKind,
+    // folding baked into the NFA: every letter and class also matches the
+    // chars that fold to the same thing, e.g. with case_fold hello matches
+    // HELLO. unlike find_folded nothing is folded when matching, but
+    // compiling has to fold every char once to find what goes together,
+    // the first time each fold is used on a thread
+    pub fold: Option<fn(char) -> String>,
+    // an alternation of plain words, like cat|cot|cut, is built as a trie
+    // with the common suffixes merged too, see literal_words. off by
+    // default, which builds the usual union with a fragment per word and
+    // keeps the states the same shape as the pattern
+    pub factor_literals: bool,
+    // whether . matches the LINE_TERMINATORS too. on by default, as it
+    // always has been, unlike most engines where . stops at a \n. when
+    // it's off, . is the class of every other char (in the wildcard set,
+    // if there's one)
+    pub dot_matches_newline: bool,
+}
+
+// Eager copies the quantified pattern once per repetition, so a{2,5} is
+// five copies of a. Counter keeps one copy in a loop with a counter, which
+// takes far fewer states but makes matching carry the counts along
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RepeatStrategy {
+    #[default]
+    Eager,
+    Counter,
+}
+
+// which of the matches starting at the leftmost position find picks.
+// LeftmostLongest takes the longest, like POSIX, so a|ab finds "ab" in
+// "ab". LeftmostFirst takes the one a backtracking engine would find
+// first, like PCRE: unions prefer their left side, greedy quantifiers
+// prefer to repeat and lazy ones (a*?) to stop, so a|ab finds "a"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum MatchKind {
+    #[default]
+    LeftmostLongest,
+    LeftmostFirst,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        CompileOptions {
+            wildcard_set: None,
+            max_states: DEFAULT_MAX_STATES,
+            max_depth: DEFAULT_MAX_DEPTH,
+            whole_word: false,
+            multiline: false,
+            verbose: false,
+            empty_alternatives: false,
+            repeat_strategy: RepeatStrategy::Eager,
+            match_kind: MatchKind::LeftmostLongest,
+            fold: None,
+            factor_literals: false,
+            dot_matches_newline: true,
+        }
+    }
+}
+
+// where a parenthesized group starts and ends in the NFA. a thompson
+// fragment is only ever entered through its start and left through its
+// out, so these two states are enough to find (and replace) the group
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct GroupBoundary {
+    start_id: usize,
+    out_id: usize,
+    // number of groups nested inside this one. groups are ordered by their
+    // open parenthesis, so the nested ones come right after this group
+    num_nested: usize,
+    // whether the group is inside of a {min,max} quantifier. only the
+    // first copy of a repeated group is the one recorded here
+    repeated: bool,
+    // lazy quantifiers inside the group, see Compiled::num_lazy
+    num_lazy: usize,
+}
+
+// counters from one search, for finding out why a pattern is slow
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct MatchStats {
+    // (idx of input, state) pairs taken off the queue
+    pub states_dequeued: usize,
+    // the longest the queue got
+    pub max_frontier_size: usize,
+    // input positions whose epsilon closure was explored
+    pub closures_computed: usize,
+}
+
+// equality is structural: two NFAs are equal when they have the same
+// states with the same transitions in the same order. this is NOT language
+// equality, a|b and b|a accept the same strings but build different NFAs.
+// the hash is structural too, see canonical_hash for one that isn't. how
+// they were compiled doesn't count, only what came out
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone)]
 pub struct NFA {
     start_id: usize,
     states: Vec<State>,
+    groups: Vec<GroupBoundary>,
+    wildcard_set: Option<BTreeSet<char>>,
+    // the chars every match starts with, if there are few enough to list
+    // and no match is empty. is_match and contains_match check it first,
+    // so inputs that can't match are turned down without a search. has to
+    // be updated whenever the states change, see update_first_char_set
+    first_char_set: Option<BTreeSet<char>>,
+    // the literal every match starts with, e.g. abc for abc.*, or empty if
+    // there is none. contains_match only tries to match where it occurs,
+    // updated along with first_char_set
+    literal_prefix: String,
+    // which match find picks, the order of each state's transitions is
+    // its priority for LeftmostFirst
+    match_kind: MatchKind,
+    // the options it was compiled with, so patch_group builds a new group
+    // the same way
+    compiled: Compiled,
 }
 
-impl NFA {
-    pub fn from_regex(re: &String) -> Option<NFA> {
-        let tokens = parse_re_to_tokens(re);
+// how an NFA was compiled
+#[derive(Debug, Clone, Default)]
+struct Compiled {
+    options: CompileOptions,
+    // lazy quantifiers in the regex, which make it LeftmostFirst whatever
+    // the options say
+    num_lazy: usize,
+}
 
-        // if the postfix is invalid (None), we cannot construct
-        // an NFA because we we're provided with an invalid regex
-        // so we propogate the None
-        let postfix = calc_postfix(tokens)?;
+impl Compiled {
+    fn match_kind(&self) -> MatchKind {
+        match self.num_lazy {
+            0 => self.options.match_kind,
+            _ => MatchKind::LeftmostFirst,
+        }
+    }
+}
+
+impl PartialEq for NFA {
+    fn eq(&self, other: &NFA) -> bool {
+        self.start_id == other.start_id
+            && self.states == other.states
+            && self.groups == other.groups
+            && self.wildcard_set == other.wildcard_set
+            && self.first_char_set == other.first_char_set
+            && self.literal_prefix == other.literal_prefix
+            && self.match_kind == other.match_kind
+    }
+}
+
+impl Eq for NFA {}
 
+impl Hash for NFA {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.start_id.hash(state);
+        self.states.hash(state);
+        self.groups.hash(state);
+        self.wildcard_set.hash(state);
+        self.first_char_set.hash(state);
+        self.literal_prefix.hash(state);
+        self.match_kind.hash(state);
+    }
+}
+
+impl NFA {
+    pub fn from_regex(re: &str) -> Result<NFA, RegexError> {
+        NFA::from_regex_with_options(re, &CompileOptions::default())
+    }
+    pub fn from_regex_with_options(re: &str, options: &CompileOptions) -> Result<NFA, RegexError> {
+        // if the postfix is invalid, we cannot construct
+        // an NFA because we we're provided with an invalid regex
+        // so we propogate the error
+        let postfix = NFA::postfix_with_options(re, options)?;
+        NFA::from_valid_postfix(postfix, options)
+    }
+    fn postfix_with_options(re: &str, options: &CompileOptions) -> Result<Vec<Token>, RegexError> {
+        let stripped;
+        let re = if options.verbose {
+            stripped = strip_verbose(re);
+            &stripped
+        } else {
+            re
+        };
+        let mut tokens = parse_re_to_tokens(re, options.max_depth)?;
+        if options.empty_alternatives {
+            tokens = insert_empty_operands(tokens);
+        }
+        calc_postfix(tokens)
+    }
+    // builds the NFA for a postfix regex directly, skipping the parsing,
+    // e.g. [a, b, Concatenation] for ab. group markers (Token::Group) are
+    // numbered from 0 in the order of their open parentheses, and
+    // parentheses can't appear. the postfix is checked first, so a
+    // malformed one is an error rather than a panic
+    pub fn from_postfix(postfix: Vec<Token>) -> Result<NFA, RegexError> {
+        validate_postfix(&postfix)?;
+        NFA::from_valid_postfix(postfix, &CompileOptions::default())
+    }
+    fn from_valid_postfix(
+        postfix: Vec<Token>,
+        options: &CompileOptions,
+    ) -> Result<NFA, RegexError> {
         // when we have an empty regex, treat it as an empty language
         // so never matches
-        if postfix.len() == 0 {
-            return Some(NFA::empty_language());
+        let compiled = Compiled {
+            options: options.clone(),
+            num_lazy: postfix
+                .iter()
+                .filter(|&token| *token == Token::Lazy)
+                .count(),
+        };
+        if postfix.is_empty() {
+            let mut nfa = NFA::never();
+            nfa.wildcard_set = options.wildcard_set.clone();
+            nfa.match_kind = options.match_kind;
+            nfa.compiled = compiled;
+            return Ok(nfa);
+        }
+
+        // lazy quantifiers only mean something when matches are picked
+        // by priority
+        let match_kind = compiled.match_kind();
+        // the words share their prefixes in a trie, and merging the states
+        // that match the same rest of the input shares their suffixes, so
+        // cat|cot|cut ends in a single t. the trie changes which word is
+        // preferred, so only when the longest match wins anyway
+        let factor = options.factor_literals
+            && match_kind == MatchKind::LeftmostLongest
+            && !options.whole_word
+            && options.fold.is_none();
+        if let Some(words) = literal_words(&postfix).filter(|_| factor) {
+            let words: Vec<&str> = words.iter().map(|word| word.as_str()).collect();
+            let mut nfa = NFA::from_literals(&words);
+            nfa.merge_bisimilar_states();
+            nfa.wildcard_set = options.wildcard_set.clone();
+            nfa.compiled = compiled;
+            return Ok(nfa);
         }
 
+        let mut states: Vec<State> = vec![];
+        let (mut fragment, groups) = NFA::build_fragment(&mut states, postfix, options)?;
+
+        if options.whole_word {
+            let boundary = TransitionLabel::Assertion(Assertion::WordBoundary);
+            let before = NFA::add_single_transition_fragment(&mut states, boundary.clone());
+            fragment = NFA::add_concat_fragment(&mut states, before, fragment);
+            let after = NFA::add_single_transition_fragment(&mut states, boundary);
+            fragment = NFA::add_concat_fragment(&mut states, fragment, after);
+        }
+
+        if let Some(fold) = options.fold {
+            normalize::bake_fold(&mut states, fold);
+        }
+
+        // turn fragment to NFA
+        let start_id = fragment.start_id;
+        // make last node accepting
+        states[fragment.out_id].set_accepting(true);
+        // we have all the info we need to create NFA
+        let mut nfa = NFA {
+            start_id,
+            states,
+            groups,
+            wildcard_set: options.wildcard_set.clone(),
+            first_char_set: None,
+            literal_prefix: String::new(),
+            match_kind,
+            compiled,
+        };
+        nfa.update_first_char_set();
+        Ok(nfa)
+    }
+    // compiles the regex for strings over only the given alphabet: every
+    // wildcard and class becomes one letter transition per char of the
+    // alphabet it matches, and letters outside of the alphabet are dropped.
+    // with nothing open-ended left, accepted_strings and is_equivalent are
+    // exact, e.g. for grading automata over {0, 1}
+    pub fn from_regex_over(re: &str, alphabet: &[char]) -> Result<NFA, RegexError> {
+        let alphabet: BTreeSet<char> = alphabet.iter().copied().collect();
+        let options = CompileOptions {
+            wildcard_set: Some(alphabet.clone()),
+            ..CompileOptions::default()
+        };
+        let mut nfa = NFA::from_regex_with_options(re, &options)?;
+        for state in &mut nfa.states {
+            state.transitions = std::mem::take(&mut state.transitions)
+                .into_iter()
+                .flat_map(|transition| {
+                    let letters: Vec<char> = match &transition.label {
+                        TransitionLabel::Epsilon
+                        | TransitionLabel::Assertion(_)
+                        | TransitionLabel::Counter(_) => {
+                            return vec![transition];
+                        }
+                        TransitionLabel::Letter(c) => {
+                            alphabet.iter().copied().filter(|a| a == c).collect()
+                        }
+                        TransitionLabel::Wildcard => alphabet.iter().copied().collect(),
+                        TransitionLabel::Class(class) => alphabet
+                            .iter()
+                            .copied()
+                            .filter(|&c| class.contains(c))
+                            .collect(),
+                    };
+                    letters
+                        .into_iter()
+                        .map(|c| Transition {
+                            label: TransitionLabel::Letter(c),
+                            to: transition.to,
+                        })
+                        .collect()
+                })
+                .collect();
+        }
+        nfa.update_first_char_set();
+        Ok(nfa)
+    }
+    // recomputes first_char_set and literal_prefix, after building the
+    // states or changing them
+    fn update_first_char_set(&mut self) {
+        if cfg!(debug_assertions) {
+            if let Err(broken) = self.validate_invariants() {
+                panic!("invalid NFA: {broken}");
+            }
+        }
+        self.first_char_set = self.first_char_filter();
+        self.literal_prefix = self.literal_prefix_filter();
+    }
+    // builds the fragment for a postfix regex, adding its states to the
+    // end of states, so the new state ids never collide with the existing ones
+    fn build_fragment(
+        states: &mut Vec<State>,
+        postfix: Vec<Token>,
+        options: &CompileOptions,
+    ) -> Result<(NFAFragement, Vec<GroupBoundary>), RegexError> {
+        let max_states = options.max_states;
         // we will liberally use unwraps since we know an NFA can
         // be constructed since we validated the input regex when
         // constructing the NFA
 
-        let mut states: Vec<State> = vec![];
+        let num_groups = postfix
+            .iter()
+            .filter(|token| matches!(token, Token::Group(_)))
+            .count();
+        let mut groups: Vec<Option<GroupBoundary>> = (0..num_groups).map(|_| None).collect();
         let mut fragments: Vec<NFAFragement> = vec![];
 
+        // each token with whether it is a quantifier made lazy by the
+        // Token::Lazy after it
+        let mut lazy_postfix: Vec<(Token, bool)> = vec![];
         for token in postfix {
+            match (token, lazy_postfix.last_mut()) {
+                (Token::Lazy, Some((_, lazy))) => *lazy = true,
+                (token, _) => lazy_postfix.push((token, false)),
+            }
+        }
+
+        // where the fragment of each lazy quantifier starts, to tell which
+        // groups they are in
+        let mut lazy_first_ids: Vec<usize> = vec![];
+        let mut postfix = lazy_postfix.into_iter().peekable();
+        while let Some((token, lazy)) = postfix.next() {
+            if lazy {
+                lazy_first_ids.push(fragments.last().unwrap().first_id);
+            }
             match token {
                 Token::Letter(c) => {
                     fragments.push(NFA::add_single_transition_fragment(
-                        &mut states,
+                        states,
                         TransitionLabel::Letter(c),
                     ));
                 }
                 Token::Wildcard => {
+                    // .*, .+ and .? are common enough to get their own
+                    // smaller fragments. they can't be lazy, a loop on one
+                    // state has no way to prefer leaving it
+                    let quantifier = postfix.next_if(|(token, lazy)| {
+                        !lazy
+                            && matches!(
+                                token,
+                                Token::KleeneQuantifier
+                                    | Token::PositiveQuantifier
+                                    | Token::OptionalQuantifier
+                            )
+                    });
+                    let label = wildcard_label(options);
+                    fragments.push(match quantifier {
+                        Some((quantifier, _)) => {
+                            NFA::add_wildcard_quantifier_fragment(states, quantifier, label)
+                        }
+                        None => NFA::add_single_transition_fragment(states, label),
+                    });
+                }
+                Token::Class(class) => {
                     fragments.push(NFA::add_single_transition_fragment(
-                        &mut states,
-                        TransitionLabel::Wildcard,
+                        states,
+                        TransitionLabel::Class(class),
+                    ));
+                }
+                Token::StartAnchor => {
+                    let assertion = match options.multiline {
+                        true => Assertion::StartLine,
+                        false => Assertion::StartText,
+                    };
+                    fragments.push(NFA::add_single_transition_fragment(
+                        states,
+                        TransitionLabel::Assertion(assertion),
+                    ));
+                }
+                Token::EndAnchor => {
+                    let assertion = match options.multiline {
+                        true => Assertion::EndLine,
+                        false => Assertion::EndText,
+                    };
+                    fragments.push(NFA::add_single_transition_fragment(
+                        states,
+                        TransitionLabel::Assertion(assertion),
+                    ));
+                }
+                Token::Empty => {
+                    fragments.push(NFA::add_single_transition_fragment(
+                        states,
+                        TransitionLabel::Epsilon,
                     ));
                 }
                 Token::Concatenation => {
                     let end_fragment = fragments.pop().unwrap();
                     let start_fragment = fragments.pop().unwrap();
                     fragments.push(NFA::add_concat_fragment(
-                        &mut states,
+                        states,
                         start_fragment,
                         end_fragment,
                     ));
                 }
                 Token::Union => {
-                    let frag_a = fragments.pop().unwrap();
-                    let frag_b = fragments.pop().unwrap();
-                    fragments.push(NFA::add_union_fragment(&mut states, frag_a, frag_b));
+                    let right = fragments.pop().unwrap();
+                    let left = fragments.pop().unwrap();
+                    fragments.push(NFA::add_union_fragment(states, left, right));
                 }
                 Token::KleeneQuantifier => {
                     let frag = fragments.pop().unwrap();
-                    fragments.push(NFA::add_quantifier_fragment(&mut states, frag, true, true));
+                    fragments.push(NFA::add_quantifier_fragment(states, frag, true, true, lazy));
                 }
                 Token::PositiveQuantifier => {
                     let frag = fragments.pop().unwrap();
-                    fragments.push(NFA::add_quantifier_fragment(&mut states, frag, true, false));
+                    fragments.push(NFA::add_quantifier_fragment(
+                        states, frag, true, false, lazy,
+                    ));
                 }
                 Token::OptionalQuantifier => {
                     let frag = fragments.pop().unwrap();
-                    fragments.push(NFA::add_quantifier_fragment(&mut states, frag, false, true));
+                    fragments.push(NFA::add_quantifier_fragment(
+                        states, frag, false, true, lazy,
+                    ));
+                }
+                Token::Group(n) => {
+                    // the group is the fragment on top of the stack. groups
+                    // nested inside of it were closed before it, and they are
+                    // the only groups with a larger index recorded so far
+                    let mut frag = NFA::add_enclosing_fragment(states, fragments.pop().unwrap());
+                    // a group has to keep its own start and out, so a union
+                    // after it can't add branches to a union inside it
+                    frag.is_union = false;
+                    let num_nested = groups[n + 1..].iter().filter(|g| g.is_some()).count();
+                    groups[n] = Some(GroupBoundary {
+                        start_id: frag.start_id,
+                        out_id: frag.out_id,
+                        num_nested,
+                        repeated: false,
+                        num_lazy: lazy_first_ids
+                            .iter()
+                            .filter(|&&id| id >= frag.first_id)
+                            .count(),
+                    });
+                    fragments.push(frag);
+                }
+                Token::RepetitionQuantifier(min, max) => {
+                    let frag = fragments.pop().unwrap();
+                    // groups in the fragment are about to be copied
+                    for group in groups.iter_mut().flatten() {
+                        if group.start_id >= frag.first_id {
+                            group.repeated = true;
+                        }
+                    }
+                    fragments.push(NFA::add_repetition_fragment(
+                        states,
+                        frag,
+                        min,
+                        max,
+                        max_states,
+                        options.repeat_strategy,
+                        lazy,
+                    )?);
                 }
                 // parentheses should not be in the postfix
                 _ => unreachable!(),
             }
         }
 
-        // turn fragment to NFA
-        let start_id = fragments[0].start_id;
-        // make last node accepting
-        states[fragments[0].out_id].set_accepting(true);
-        // we have all the info we need to create NFA
-        Some(NFA { start_id, states })
+        if states.len() > max_states {
+            return Err(RegexError::TooManyStates);
+        }
+
+        let groups = groups.into_iter().map(|g| g.unwrap()).collect();
+        Ok((fragments.pop().unwrap(), groups))
     }
-    fn empty_language() -> NFA {
+    // an NFA accepting exactly the given words, built as a trie: words that
+    // share a prefix share the states for it, so there's one state per
+    // distinct prefix and no epsilons, instead of a union of every word.
+    // every char is literal, and no words means nothing matches
+    pub fn from_literals(words: &[&str]) -> NFA {
+        let mut states = vec![State::new()];
+        for word in words {
+            let mut state_id = 0;
+            for c in word.chars() {
+                let existing = states[state_id]
+                    .active_transitions()
+                    .iter()
+                    .find(|transition| transition.label == TransitionLabel::Letter(c));
+                state_id = match existing {
+                    Some(transition) => transition.to,
+                    None => {
+                        let next_id = states.len();
+                        states.push(State::new());
+                        states[state_id].add_transition(Transition {
+                            label: TransitionLabel::Letter(c),
+                            to: next_id,
+                        });
+                        next_id
+                    }
+                };
+            }
+            states[state_id].set_accepting(true);
+        }
+        let mut nfa = NFA {
+            start_id: 0,
+            states,
+            groups: vec![],
+            wildcard_set: None,
+            first_char_set: None,
+            literal_prefix: String::new(),
+            match_kind: MatchKind::LeftmostLongest,
+            compiled: Compiled::default(),
+        };
+        nfa.update_first_char_set();
+        nfa
+    }
+    // the empty language, for a regex that never matches anything, not
+    // even the empty string. the out state accepts but can't be reached
+    pub fn never() -> NFA {
         let mut states = Vec::<State>::with_capacity(2);
         let start_id = states.len();
         let start = State::new();
@@ -147,7 +858,48 @@ impl NFA {
         out.set_accepting(true);
         states.push(start);
         states.push(out);
-        NFA { start_id, states }
+        let mut nfa = NFA {
+            start_id,
+            states,
+            groups: vec![],
+            wildcard_set: None,
+            first_char_set: None,
+            literal_prefix: String::new(),
+            match_kind: MatchKind::LeftmostLongest,
+            compiled: Compiled::default(),
+        };
+        nfa.update_first_char_set();
+        nfa
+    }
+    // every string over the alphabet, including the empty one: a single
+    // accepting state looping on a wildcard limited to the alphabet, so
+    // any string with a char outside of it doesn't match
+    pub fn always(alphabet: &[char]) -> NFA {
+        NFA::accepting_loop(Some(alphabet.iter().copied().collect()))
+    }
+    // every string at all, like always without an alphabet to stay in
+    pub fn always_any() -> NFA {
+        NFA::accepting_loop(None)
+    }
+    fn accepting_loop(wildcard_set: Option<BTreeSet<char>>) -> NFA {
+        let mut state = State::new();
+        state.set_accepting(true);
+        state.add_transition(Transition {
+            label: TransitionLabel::Wildcard,
+            to: 0,
+        });
+        let mut nfa = NFA {
+            start_id: 0,
+            states: vec![state],
+            groups: vec![],
+            wildcard_set,
+            first_char_set: None,
+            literal_prefix: String::new(),
+            match_kind: MatchKind::LeftmostLongest,
+            compiled: Compiled::default(),
+        };
+        nfa.update_first_char_set();
+        nfa
     }
     fn add_single_transition_fragment(
         states: &mut Vec<State>,
@@ -162,10 +914,15 @@ impl NFA {
         states.push(start);
         states.push(out);
 
-        NFAFragement { start_id, out_id }
+        NFAFragement {
+            first_id: start_id,
+            start_id,
+            out_id,
+            is_union: false,
+        }
     }
     fn add_concat_fragment(
-        states: &mut Vec<State>,
+        states: &mut [State],
         start_fragment: NFAFragement,
         end_fragment: NFAFragement,
     ) -> NFAFragement {
@@ -180,33 +937,54 @@ impl NFA {
         let start_id = start_fragment.start_id;
         let out_id = end_fragment.out_id;
 
-        NFAFragement { start_id, out_id }
+        NFAFragement {
+            first_id: start_fragment.first_id,
+            start_id,
+            out_id,
+            is_union: false,
+        }
     }
 
     fn add_union_fragment(
         states: &mut Vec<State>,
-        frag_a: NFAFragement,
-        frag_b: NFAFragement,
+        left: NFAFragement,
+        right: NFAFragement,
     ) -> NFAFragement {
+        // a|b|c is parsed as (a|b)|c, so rather than nesting another union
+        // around the left one we add c as one more branch of it. this keeps
+        // wide alternations to one start and one out state, and one
+        // epsilon step to reach every alternative
+        if left.is_union {
+            states[left.start_id].add_transition(Transition {
+                label: TransitionLabel::Epsilon,
+                to: right.start_id,
+            });
+            states[right.out_id].add_transition(Transition {
+                label: TransitionLabel::Epsilon,
+                to: left.out_id,
+            });
+            return left;
+        }
+
         let start_id = states.len();
         let out_id = states.len() + 1;
 
         let start = State::new()
             .with_transition(Transition {
                 label: TransitionLabel::Epsilon,
-                to: frag_a.start_id,
+                to: left.start_id,
             })
             .with_transition(Transition {
                 label: TransitionLabel::Epsilon,
-                to: frag_b.start_id,
+                to: right.start_id,
             });
         let out = State::new();
 
-        states[frag_a.out_id].add_transition(Transition {
+        states[left.out_id].add_transition(Transition {
             label: TransitionLabel::Epsilon,
             to: out_id,
         });
-        states[frag_b.out_id].add_transition(Transition {
+        states[right.out_id].add_transition(Transition {
             label: TransitionLabel::Epsilon,
             to: out_id,
         });
@@ -214,101 +992,769 @@ impl NFA {
         states.push(start);
         states.push(out);
 
-        NFAFragement { start_id, out_id }
+        NFAFragement {
+            first_id: left.first_id,
+            start_id,
+            out_id,
+            is_union: true,
+        }
     }
 
+    // frag*, frag+ or frag? depending on whether it can repeat and can be
+    // skipped. the order of the transitions is which way is preferred
+    // (see MatchKind::LeftmostFirst): repeating and entering frag first,
+    // or leaving and skipping it first when lazy
     fn add_quantifier_fragment(
         states: &mut Vec<State>,
         frag: NFAFragement,
         repeat: bool,
         optional: bool,
+        lazy: bool,
     ) -> NFAFragement {
         let start_id = states.len();
         let out_id = states.len() + 1;
-
-        // connect to frag
-        let mut start = State::new().with_transition(Transition {
+        let epsilon = |to| Transition {
             label: TransitionLabel::Epsilon,
-            to: frag.start_id,
-        });
+            to,
+        };
+
+        // connect to frag, and to out if it can be skipped
+        let mut start = State::new();
+        let mut ways = vec![epsilon(frag.start_id)];
+        if optional {
+            ways.push(epsilon(out_id));
+        }
+        // have the result of frag loop back and go to out
+        let mut ways_out = vec![epsilon(out_id)];
+        if repeat {
+            ways_out.insert(0, epsilon(frag.start_id));
+        }
+        if lazy {
+            ways.reverse();
+            ways_out.reverse();
+        }
+        for transition in ways {
+            start.add_transition(transition);
+        }
+        for transition in ways_out {
+            states[frag.out_id].add_transition(transition);
+        }
         let out = State::new();
 
-        // have the result of frag go to out
+        states.push(start);
+        states.push(out);
+
+        NFAFragement {
+            first_id: frag.first_id,
+            start_id,
+            out_id,
+            is_union: false,
+        }
+    }
+
+    // .*, .+ or .? without the two states of the wildcard fragment and
+    // the two of the quantifier around it: .* is one state that loops on
+    // the wildcard, .+ and .? are a wildcard transition between two states
+    // with a loop on the second or a skip over it. the label is what the
+    // wildcard compiled to, see wildcard_label
+    fn add_wildcard_quantifier_fragment(
+        states: &mut Vec<State>,
+        quantifier: Token,
+        label: TransitionLabel,
+    ) -> NFAFragement {
+        let start_id = states.len();
+        let wildcard = |to| Transition {
+            label: label.clone(),
+            to,
+        };
+        let out_id = match quantifier {
+            Token::KleeneQuantifier => {
+                states.push(State::new().with_transition(wildcard(start_id)));
+                start_id
+            }
+            Token::PositiveQuantifier => {
+                states.push(State::new().with_transition(wildcard(start_id + 1)));
+                states.push(State::new().with_transition(wildcard(start_id + 1)));
+                start_id + 1
+            }
+            _ => {
+                let skip = Transition {
+                    label: TransitionLabel::Epsilon,
+                    to: start_id + 1,
+                };
+                states.push(
+                    State::new()
+                        .with_transition(wildcard(start_id + 1))
+                        .with_transition(skip),
+                );
+                states.push(State::new());
+                start_id + 1
+            }
+        };
+        NFAFragement {
+            first_id: start_id,
+            start_id,
+            out_id,
+            is_union: false,
+        }
+    }
+    // a group records where it starts and ends by its start and out
+    // states, which breaks if a loop inside of it comes back to them (like
+    // the one state of .*). such a fragment gets a new start and out around
+    // it, any other is returned as is
+    fn add_enclosing_fragment(states: &mut Vec<State>, frag: NFAFragement) -> NFAFragement {
+        let loops_back = |id: usize| {
+            states[id]
+                .active_transitions()
+                .iter()
+                .any(|transition| transition.to == id)
+        };
+        if !loops_back(frag.start_id) && !loops_back(frag.out_id) {
+            return frag;
+        }
+
+        let start_id = states.len();
+        let out_id = states.len() + 1;
         states[frag.out_id].add_transition(Transition {
             label: TransitionLabel::Epsilon,
             to: out_id,
         });
+        states.push(State::new().with_transition(Transition {
+            label: TransitionLabel::Epsilon,
+            to: frag.start_id,
+        }));
+        states.push(State::new());
+        NFAFragement {
+            first_id: frag.first_id,
+            start_id,
+            out_id,
+            is_union: false,
+        }
+    }
 
-        if optional {
-            start.add_transition(Transition {
-                label: TransitionLabel::Epsilon,
-                to: out_id,
+    // copies a finished fragment, whose states end right before end, onto
+    // the end of the states. the fragment's transitions only point at its
+    // own states, so they just get shifted
+    fn copy_fragment(states: &mut Vec<State>, frag: &NFAFragement, end: usize) -> NFAFragement {
+        let offset = states.len() - frag.first_id;
+        for id in frag.first_id..end {
+            let mut state = states[id].clone();
+            for transition in &mut state.transitions {
+                transition.to += offset;
+            }
+            states.push(state);
+        }
+        NFAFragement {
+            first_id: frag.first_id + offset,
+            start_id: frag.start_id + offset,
+            out_id: frag.out_id + offset,
+            is_union: false,
+        }
+    }
+
+    // frag{min,max} by expanding it into copies: min required copies, then
+    // either a repeating last copy if there is no max, or max - min nested
+    // optional copies, e.g. a{1,3} is built like a(a(a)?)?. with
+    // RepeatStrategy::Counter it is a counted loop around frag instead
+    fn add_repetition_fragment(
+        states: &mut Vec<State>,
+        frag: NFAFragement,
+        min: usize,
+        max: Option<usize>,
+        max_states: usize,
+        strategy: RepeatStrategy,
+        lazy: bool,
+    ) -> Result<NFAFragement, RegexError> {
+        let num_copies = match max {
+            Some(max) => max,
+            None => min.max(1),
+        };
+        // a{0} and a{0,0} only match the empty string
+        if num_copies == 0 {
+            return Ok(NFA::add_single_transition_fragment(
+                states,
+                TransitionLabel::Epsilon,
+            ));
+        }
+
+        // check before copying so a{1000000000} fails fast
+        let frag_size = states.len() - frag.first_id;
+        let estimate = frag_size
+            .saturating_mul(num_copies - 1)
+            .saturating_add(states.len());
+        if estimate > max_states {
+            return Err(RegexError::TooManyStates);
+        }
+        // the counter still counts the expanded size against max_states,
+        // since expand_counters can build all of it. with a single copy
+        // (like a{0,} which is just a*) there is nothing to save
+        if strategy == RepeatStrategy::Counter && num_copies > 1 {
+            return Ok(NFA::add_counter_fragment(states, frag, min, max, lazy));
+        }
+
+        // copy before linking anything, linking adds transitions to the
+        // out states that point outside of the fragment
+        let frag_end = states.len();
+        let mut copies = vec![];
+        for _ in 1..num_copies {
+            copies.push(NFA::copy_fragment(states, &frag, frag_end));
+        }
+        let mut pieces = std::iter::once(frag).chain(copies);
+
+        let mut required: Option<NFAFragement> = None;
+        for i in 0..min {
+            let mut piece = pieces.next().unwrap();
+            if max.is_none() && i == min - 1 {
+                piece = NFA::add_quantifier_fragment(states, piece, true, false, lazy);
+            }
+            required = Some(match required {
+                Some(required) => NFA::add_concat_fragment(states, required, piece),
+                None => piece,
             });
         }
+        if max.is_none() && min == 0 {
+            let piece = pieces.next().unwrap();
+            return Ok(NFA::add_quantifier_fragment(
+                states, piece, true, true, lazy,
+            ));
+        }
 
-        if repeat {
-            states[frag.out_id].add_transition(Transition {
-                label: TransitionLabel::Epsilon,
-                to: frag.start_id,
-            })
+        // nest the optional copies from the innermost one out
+        let mut optional: Option<NFAFragement> = None;
+        for piece in pieces.rev() {
+            let piece = match optional {
+                Some(optional) => NFA::add_concat_fragment(states, piece, optional),
+                None => piece,
+            };
+            optional = Some(NFA::add_quantifier_fragment(
+                states, piece, false, true, lazy,
+            ));
         }
 
-        states.push(start);
-        states.push(out);
+        Ok(match (required, optional) {
+            (Some(required), Some(optional)) => {
+                NFA::add_concat_fragment(states, required, optional)
+            }
+            (Some(fragment), None) | (None, Some(fragment)) => fragment,
+            (None, None) => unreachable!(),
+        })
+    }
+}
 
-        NFAFragement { start_id, out_id }
+impl NFA {
+    pub fn num_states(&self) -> usize {
+        self.states.len()
+    }
+    // which match find picks, LeftmostFirst if the regex has a lazy
+    // quantifier
+    pub fn match_kind(&self) -> MatchKind {
+        self.match_kind
+    }
+    // transitions of every kind, epsilons included
+    pub fn num_transitions(&self) -> usize {
+        self.states
+            .iter()
+            .map(|state| state.active_transitions().len())
+            .sum()
     }
 }
 
 impl NFA {
-    pub fn is_match(&self, input: &String) -> bool {
-        let chars: Vec<char> = input.chars().collect();
+    // EXPERIMENTAL: replaces the contents of a group (numbered by the order
+    // of the open parentheses, starting at 0) with the regex re, without
+    // recompiling the rest of the NFA. the new fragment gets built onto the
+    // end of the states and everything that lead into the old group is
+    // relinked to it. the old group's states become unreachable, they are
+    // left in place so no other state ids change. re is compiled with the
+    // same options as the rest of the NFA.
+    // errors if there is no such group or re is invalid
+    pub fn patch_group(&mut self, group: usize, re: &str) -> Result<(), RegexError> {
+        let old = self
+            .groups
+            .get(group)
+            .ok_or(RegexError::NoSuchGroup(group))?;
+        if old.repeated {
+            return Err(RegexError::RepeatedGroup(group));
+        }
+        let (old_start, old_out, old_nested, old_lazy) =
+            (old.start_id, old.out_id, old.num_nested, old.num_lazy);
 
-        // hashset entry: (idx of input, state visited)
-        let mut visited: HashSet<(usize, usize)> = HashSet::new();
-        let mut queue = VecDeque::<(usize, usize)>::new();
+        // the new group is compiled the way the rest of the NFA was
+        let options = self.compiled.options.clone();
+        let postfix = NFA::postfix_with_options(re, &options)?;
+        let num_lazy = postfix
+            .iter()
+            .filter(|&token| *token == Token::Lazy)
+            .count();
+        let first_new = self.states.len();
+        let (fragment, nested) = NFA::build_fragment(&mut self.states, postfix, &options)?;
+        let fragment = NFA::add_enclosing_fragment(&mut self.states, fragment);
+        if let Some(fold) = options.fold {
+            normalize::bake_fold(&mut self.states[first_new..], fold);
+        }
+
+        // anything that entered the old group (the previous fragment, a union
+        // branch, a quantifier loop) should now enter the new one
+        for state in &mut self.states {
+            for transition in &mut state.transitions {
+                if transition.to == old_start {
+                    transition.to = fragment.start_id;
+                }
+            }
+        }
+        if self.start_id == old_start {
+            self.start_id = fragment.start_id;
+        }
+
+        // the old out only has the transitions that were added after the
+        // group was built, so moving them over leaves the group's
+        // surroundings intact
+        let transitions = std::mem::take(&mut self.states[old_out].transitions);
+        self.states[fragment.out_id].transitions.extend(transitions);
+        let accepting = self.states[old_out].accepting;
+        self.states[old_out].set_accepting(false);
+        self.states[fragment.out_id].set_accepting(accepting);
+
+        // groups containing the patched one now contain a different number
+        // of nested groups, and can share their start or out with it
+        let nested_diff = nested.len() as isize - old_nested as isize;
+        for (i, outer) in self.groups[..group].iter_mut().enumerate() {
+            if i + outer.num_nested >= group {
+                outer.num_nested = (outer.num_nested as isize + nested_diff) as usize;
+                outer.num_lazy = outer.num_lazy - old_lazy + num_lazy;
+                if outer.start_id == old_start {
+                    outer.start_id = fragment.start_id;
+                }
+                if outer.out_id == old_out {
+                    outer.out_id = fragment.out_id;
+                }
+            }
+        }
+        let replacement = std::iter::once(GroupBoundary {
+            start_id: fragment.start_id,
+            out_id: fragment.out_id,
+            num_nested: nested.len(),
+            repeated: false,
+            num_lazy,
+        })
+        .chain(nested);
+        self.groups.splice(group..=group + old_nested, replacement);
 
-        // push start on to queue
-        queue.push_back((0, self.start_id));
+        // a lazy quantifier patched in (or the last one patched out) changes
+        // which match find picks
+        self.compiled.num_lazy = self.compiled.num_lazy - old_lazy + num_lazy;
+        self.match_kind = self.compiled.match_kind();
 
-        while let Some((idx, state_id)) = queue.pop_front() {
-            // mark visited
-            visited.insert((idx, state_id));
+        self.update_first_char_set();
+        Ok(())
+    }
+}
 
+impl NFA {
+    fn wildcard_matches(&self, c: char) -> bool {
+        match &self.wildcard_set {
+            Some(set) => set.contains(&c),
+            None => true,
+        }
+    }
+    // whether a transition with the label consumes c
+    fn consumes(&self, label: &TransitionLabel, c: char) -> bool {
+        match label {
+            TransitionLabel::Letter(letter) => *letter == c,
+            TransitionLabel::Wildcard => self.wildcard_matches(c),
+            TransitionLabel::Class(class) => class.contains(c),
+            TransitionLabel::Epsilon
+            | TransitionLabel::Assertion(_)
+            | TransitionLabel::Counter(_) => false,
+        }
+    }
+    // where in the input we end up after taking the transition from idx,
+    // or None if the transition can't be taken there
+    fn next_idx(&self, transition: &Transition, chars: &[char], idx: usize) -> Option<usize> {
+        match transition.label {
+            TransitionLabel::Epsilon => Some(idx),
+            TransitionLabel::Assertion(assertion) => assertion.holds_at(chars, idx).then_some(idx),
+            _ => (idx < chars.len() && self.consumes(&transition.label, chars[idx]))
+                .then_some(idx + 1),
+        }
+    }
+    // breadth first search over (idx of input, state) pairs starting at
+    // (start, start state), calling visit on each pair as it is dequeued
+    // until visit returns false, see breadth_first.
+    // the order pairs are visited in is always the same for the same NFA
+    // and input: the queue is first in first out, each state's transitions
+    // are followed in the order they were added, and the visited set is
+    // only ever asked for membership
+    fn search(
+        &self,
+        chars: &[char],
+        start: usize,
+        visit: impl FnMut(usize, usize) -> bool,
+    ) -> MatchStats {
+        // counted loops also need the counts in each pair, which are
+        // empty without counters
+        let counters = vec![0; counter::num_counters(&self.states)];
+        breadth_first(
+            &self.states,
+            (start, self.start_id, counters),
+            visit,
+            |(idx, _, counters), transition| match &transition.label {
+                TransitionLabel::Counter(op) => op.apply(counters).map(|counters| (*idx, counters)),
+                _ => self
+                    .next_idx(transition, chars, *idx)
+                    .map(|next_idx| (next_idx, counters.clone())),
+            },
+        )
+    }
+    // whether the whole input matches, same as full_match
+    pub fn is_match(&self, input: &str) -> bool {
+        if !self.can_start_with(input.chars().next()) {
+            return false;
+        }
+        self.match_with_stats(input).0
+    }
+    // is_match on the chars of an input, for callers that keep a buffer of
+    // them around instead of collecting them again for every input
+    pub(crate) fn is_match_chars(&self, chars: &[char]) -> bool {
+        self.match_chars_with_stats(chars).0
+    }
+    // whether a match of the whole input can start with its first char
+    // (None for an empty input), see first_char_set
+    fn can_start_with(&self, first: Option<char>) -> bool {
+        match (&self.first_char_set, first) {
+            (None, _) => true,
+            (Some(first_char_set), Some(first)) => first_char_set.contains(&first),
+            (Some(_), None) => false,
+        }
+    }
+    // is_match, along with counters from the search it did. an input that
+    // no match can start with is turned down without one, so all of its
+    // counters are 0
+    pub fn match_with_stats(&self, input: &str) -> (bool, MatchStats) {
+        let chars: Vec<char> = input.chars().collect();
+        self.match_chars_with_stats(&chars)
+    }
+    fn match_chars_with_stats(&self, chars: &[char]) -> (bool, MatchStats) {
+        if !self.can_start_with(chars.first().copied()) {
+            return (false, MatchStats::default());
+        }
+        let mut matched = false;
+        let stats = self.search(chars, 0, |idx, state_id| {
             // if we consumed all chars and ended up on a accepting state
             // we can end, return true
-            if idx >= chars.len() {
-                if self.states[state_id].accepting {
-                    return true;
-                }
+            matched = idx >= chars.len() && self.states[state_id].accepting;
+            !matched
+        });
+        (matched, stats)
+    }
+    // whether the whole input matches, a|b matches "a" but not "ab"
+    pub fn full_match(&self, input: &str) -> bool {
+        self.is_match(input)
+    }
+    // how many chars a full match consumed, which is all of them, or None
+    // when the whole input doesn't match. handy when composing with other
+    // parsers that want to know how far to advance
+    pub fn full_match_len(&self, input: &str) -> Option<usize> {
+        self.is_match(input).then(|| input.chars().count())
+    }
+    // the (idx of input, state) pairs is_match visits, in order, up to
+    // and including the one that matched. useful for stepping through how
+    // a match happens, and always the same for the same regex and input
+    pub fn trace(&self, input: &str) -> Vec<(usize, usize)> {
+        let chars: Vec<char> = input.chars().collect();
+        let mut trace = vec![];
+        self.search(&chars, 0, |idx, state_id| {
+            trace.push((idx, state_id));
+            !(idx >= chars.len() && self.states[state_id].accepting)
+        });
+        trace
+    }
+}
+
+// =================
+// SEARCHING
+// =================
+
+// all spans are in chars, not bytes, since that is what the NFA consumes
+
+// byte offset of every char in the input, plus the length of the input,
+// so char spans can be turned into slices of the input
+fn byte_offsets(input: &str) -> Vec<usize> {
+    input
+        .char_indices()
+        .map(|(offset, _)| offset)
+        .chain(std::iter::once(input.len()))
+        .collect()
+}
+
+// the line and column of the char at index offset, both counted from 0.
+// lines are separated by LINE_TERMINATORS, a \r\n being one line break,
+// and columns are in chars
+pub fn line_col(input: &str, offset: usize) -> (usize, usize) {
+    let mut line_col = (0, 0);
+    let mut prev = None;
+    for c in input.chars().take(offset) {
+        line_col = match c {
+            '\n' if prev == Some('\r') => line_col,
+            c if is_line_terminator(c) => (line_col.0 + 1, 0),
+            _ => (line_col.0, line_col.1 + 1),
+        };
+        prev = Some(c);
+    }
+    line_col
+}
+
+// the most overlapping matches find_overlapping returns, every pair of
+// positions can be a match so the output grows quadratically with the input
+pub const MAX_OVERLAPPING_MATCHES: usize = 10_000;
+
+impl NFA {
+    // every position a match starting at start can end at, sorted.
+    // this is the same BFS as is_match, but instead of requiring
+    // all the input to be consumed we record each accepting state we reach
+    fn match_ends(&self, chars: &[char], start: usize) -> Vec<usize> {
+        let mut ends = vec![];
+        self.search(chars, start, |idx, state_id| {
+            if self.states[state_id].accepting {
+                ends.push(idx);
             }
+            true
+        });
 
-            // enqueue all
-            for transition in &self.states[state_id].transitions {
-                match transition.label {
-                    TransitionLabel::Epsilon => {
-                        let next = (idx, transition.to);
-                        if !visited.contains(&next) {
-                            queue.push_back(next);
-                        }
-                    }
-                    TransitionLabel::Wildcard => {
-                        let next = (idx + 1, transition.to);
-                        if !visited.contains(&next) && idx < chars.len() {
-                            queue.push_back(next);
-                        }
-                    }
-                    TransitionLabel::Letter(c) => {
-                        let next = (idx + 1, transition.to);
-                        if idx < chars.len() && chars[idx] == c {
-                            queue.push_back(next);
-                        }
-                    }
-                    _ => {}
-                }
+        ends.sort_unstable();
+        ends.dedup();
+        ends
+    }
+    // where the match from start picked by the match kind ends
+    fn match_end(&self, chars: &[char], start: usize) -> Option<usize> {
+        match self.match_kind {
+            MatchKind::LeftmostLongest => self.match_ends(chars, start).pop(),
+            MatchKind::LeftmostFirst => self.first_match_end(chars, start),
+        }
+    }
+    // leftmost match at or after char index from, the longest or first
+    // one there depending on the match kind
+    fn find_from_chars(&self, chars: &[char], from: usize) -> Option<(usize, usize)> {
+        (from..=chars.len()).find_map(|start| Some((start, self.match_end(chars, start)?)))
+    }
+    // calls f with the span of every non-overlapping match from left to
+    // right. after an empty match we skip a char so we don't find it again
+    // forever
+    fn for_each_match(&self, chars: &[char], mut f: impl FnMut(usize, usize)) {
+        let mut from = 0;
+        while let Some((start, end)) = self.find_from_chars(chars, from) {
+            f(start, end);
+            from = if end == start { end + 1 } else { end };
+        }
+    }
+    fn find_iter_chars(&self, chars: &[char]) -> Vec<(usize, usize)> {
+        let mut spans = vec![];
+        self.for_each_match(chars, |start, end| spans.push((start, end)));
+        spans
+    }
+    // the span of the leftmost match in the input, the longest one unless
+    // the match kind is LeftmostFirst (see MatchKind)
+    pub fn find(&self, input: &str) -> Option<(usize, usize)> {
+        let chars: Vec<char> = input.chars().collect();
+        self.find_from_chars(&chars, 0)
+    }
+    // find, but for the leftmost match that starts at or after char index
+    // from. the rest of the input is still there for assertions, so ^ only
+    // holds at 0 and whole_word sees the char before from. a scanner can
+    // loop with the end of the last match as the next from (one more after
+    // an empty match, like find_iter), and None if from is past the end
+    pub fn find_from(&self, input: &str, from: usize) -> Option<(usize, usize)> {
+        let chars: Vec<char> = input.chars().collect();
+        if from > chars.len() {
+            return None;
+        }
+        self.find_from_chars(&chars, from)
+    }
+    // the line and column where the leftmost-longest match starts, see
+    // line_col
+    pub fn find_line_col(&self, input: &str) -> Option<(usize, usize)> {
+        let (start, _) = self.find(input)?;
+        Some(line_col(input, start))
+    }
+    // whether some substring of the input matches, a|b matches "ab"
+    pub fn contains_match(&self, input: &str) -> bool {
+        if let Some(first_char_set) = &self.first_char_set {
+            if !input.chars().any(|c| first_char_set.contains(&c)) {
+                return false;
             }
         }
-
+        if !self.literal_prefix.is_empty() {
+            return self.contains_match_after_prefix(input);
+        }
+        self.find(input).is_some()
+    }
+    // contains_match, only trying to match where the literal prefix
+    // occurs. a substring search skips ahead to each of them, instead of
+    // searching from every position in between
+    fn contains_match_after_prefix(&self, input: &str) -> bool {
+        let chars: Vec<char> = input.chars().collect();
+        let offsets = byte_offsets(input);
+        let mut from = 0;
+        while let Some(found) = input[from..].find(self.literal_prefix.as_str()) {
+            let offset = from + found;
+            let start = offsets.partition_point(|&byte| byte < offset);
+            if self.match_end(&chars, start).is_some() {
+                return true;
+            }
+            // occurrences can overlap, so the next one may start one char on
+            from = offsets[start + 1];
+        }
         false
     }
+    // the number of capturing groups, one for every ( that isn't (?: or
+    // (?>, so the length of what captures returns
+    pub fn group_count(&self) -> usize {
+        self.groups.len()
+    }
+    // the literal every match starts with, or "" if there isn't one, e.g.
+    // abc for abc.* and a for a+b. contains_match only tries to match
+    // where it occurs in the input
+    pub fn literal_prefix(&self) -> &str {
+        &self.literal_prefix
+    }
+    // the chars every match starts with, or None if a match could start
+    // with any char or be empty. is_match and contains_match turn down
+    // inputs without them before searching
+    pub fn first_char_set(&self) -> Option<&BTreeSet<char>> {
+        self.first_char_set.as_ref()
+    }
+    // spans of all non-overlapping matches in the input
+    pub fn find_iter(&self, input: &str) -> Vec<(usize, usize)> {
+        let chars: Vec<char> = input.chars().collect();
+        self.find_iter_chars(&chars)
+    }
+    // how many spans find_iter would return, without collecting them
+    pub fn matches_count(&self, input: &str) -> usize {
+        let chars: Vec<char> = input.chars().collect();
+        let mut count = 0;
+        self.for_each_match(&chars, |_, _| count += 1);
+        count
+    }
+    // the pieces of the input between matches
+    pub fn split<'a>(&self, input: &'a str) -> Vec<&'a str> {
+        self.splitn(input, usize::MAX)
+    }
+    // like split, but returns at most n pieces, the last piece being the
+    // rest of the input after n - 1 matches (mirrors str::splitn)
+    pub fn splitn<'a>(&self, input: &'a str, n: usize) -> Vec<&'a str> {
+        if n == 0 {
+            return vec![];
+        }
+        let chars: Vec<char> = input.chars().collect();
+        let offsets = byte_offsets(input);
+
+        let mut pieces = vec![];
+        let mut last = 0;
+        for (start, end) in self.find_iter_chars(&chars) {
+            if pieces.len() == n - 1 {
+                break;
+            }
+            pieces.push(&input[offsets[last]..offsets[start]]);
+            last = end;
+        }
+        pieces.push(&input[offsets[last]..]);
+        pieces
+    }
+    // like split, but each match stays at the end of the piece before it
+    // (mirrors str::split_inclusive), so the pieces join back into the
+    // input. there is no empty piece after a match at the end, and an empty
+    // match right after another match doesn't make an empty piece either
+    pub fn split_inclusive<'a>(&self, input: &'a str) -> Vec<&'a str> {
+        let chars: Vec<char> = input.chars().collect();
+        let offsets = byte_offsets(input);
+
+        let mut pieces = vec![];
+        let mut last = 0;
+        self.for_each_match(&chars, |_, end| {
+            if end > last {
+                pieces.push(&input[offsets[last]..offsets[end]]);
+                last = end;
+            }
+        });
+        if last < chars.len() {
+            pieces.push(&input[offsets[last]..]);
+        }
+        pieces
+    }
+    // the input with each of the (non-overlapping, ordered) char spans
+    // swapped for what replacement returns for the text in it
+    fn replace_spans(
+        &self,
+        input: &str,
+        spans: Vec<(usize, usize)>,
+        mut replacement: impl FnMut(&str) -> String,
+    ) -> String {
+        let offsets = byte_offsets(input);
+        let mut replaced = String::new();
+        let mut last = 0;
+        for (start, end) in spans {
+            replaced.push_str(&input[offsets[last]..offsets[start]]);
+            replaced.push_str(&replacement(&input[offsets[start]..offsets[end]]));
+            last = end;
+        }
+        replaced.push_str(&input[offsets[last]..]);
+        replaced
+    }
+    // the input with the leftmost-longest match replaced. the replacement
+    // is taken literally
+    pub fn replace(&self, input: &str, replacement: &str) -> String {
+        let spans = self.find(input).into_iter().collect();
+        self.replace_spans(input, spans, |_| replacement.to_string())
+    }
+    // the input with every match find_iter finds replaced
+    pub fn replace_all(&self, input: &str, replacement: &str) -> String {
+        let spans = self.find_iter(input);
+        self.replace_spans(input, spans, |_| replacement.to_string())
+    }
+    // anchored match: the end of the match starting at char index start
+    // that find would pick (the longest, unless the match kind is
+    // LeftmostFirst), or None if no match starts there
+    pub fn match_at(&self, input: &str, start: usize) -> Option<usize> {
+        let chars: Vec<char> = input.chars().collect();
+        if start > chars.len() {
+            return None;
+        }
+        self.match_end(&chars, start)
+    }
+    // every char index a match starting at char index start can end at,
+    // sorted, so the first is the shortest (lazy) match and the last the
+    // longest (greedy) one. a|ab over "ab" from 0 is [1, 2]. empty if no
+    // match starts there, or start is past the end of the input
+    pub fn match_ends_at(&self, input: &str, start: usize) -> Vec<usize> {
+        let chars: Vec<char> = input.chars().collect();
+        if start > chars.len() {
+            return vec![];
+        }
+        self.match_ends(&chars, start)
+    }
+    // for each char of the input, whether a match starts at it, i.e.
+    // whether match_at finds one there. shows where the pattern fires
+    // across the input, ab over "xabab" is [false, true, false, true, false]
+    pub fn acceptance_map(&self, input: &str) -> Vec<bool> {
+        let chars: Vec<char> = input.chars().collect();
+        (0..chars.len())
+            .map(|start| !self.match_ends(&chars, start).is_empty())
+            .collect()
+    }
+    // every (start, end) where the input from start to end is accepted,
+    // including matches that overlap each other, ordered by start then end.
+    // stops after MAX_OVERLAPPING_MATCHES
+    pub fn find_overlapping(&self, input: &str) -> Vec<(usize, usize)> {
+        let chars: Vec<char> = input.chars().collect();
+        let mut spans = vec![];
+        for start in 0..=chars.len() {
+            for end in self.match_ends(&chars, start) {
+                if spans.len() == MAX_OVERLAPPING_MATCHES {
+                    return spans;
+                }
+                spans.push((start, end));
+            }
+        }
+        spans
+    }
 }
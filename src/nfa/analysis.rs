@@ -0,0 +1,625 @@
+// =================
+// ANALYSIS
+// =================
+
+use std::collections::{BTreeSet, VecDeque};
+use std::ops::ControlFlow;
+
+use super::{Assertion, Matcher, Transition, TransitionLabel, NFA};
+
+// what we write in generated strings for a wildcard that matches any char
+pub const WILDCARD_PLACEHOLDER: char = '_';
+
+impl NFA {
+    // a char the wildcard matches, or None if it can't match anything
+    fn wildcard_example(&self) -> Option<char> {
+        match &self.wildcard_set {
+            Some(set) => set.first().copied(),
+            None => Some(WILDCARD_PLACEHOLDER),
+        }
+    }
+    // for every state, whether an accepting state can be reached from it.
+    // one walk backwards from the accepting states, over every transition
+    // that can be taken (not a wildcard that matches nothing)
+    pub(super) fn live_states(&self) -> Vec<bool> {
+        let wildcard_matches_any = self.wildcard_example().is_some();
+        let mut incoming = vec![vec![]; self.states.len()];
+        for (id, state) in self.states.iter().enumerate() {
+            for transition in state.active_transitions() {
+                if transition.label != TransitionLabel::Wildcard || wildcard_matches_any {
+                    incoming[transition.to].push(id);
+                }
+            }
+        }
+        let mut live = vec![false; self.states.len()];
+        let mut stack: Vec<usize> = (0..self.states.len())
+            .filter(|&id| self.states[id].accepting)
+            .collect();
+        while let Some(id) = stack.pop() {
+            if !std::mem::replace(&mut live[id], true) {
+                stack.extend(&incoming[id]);
+            }
+        }
+        live
+    }
+    // every state that can be reached from the state (itself included),
+    // over transitions of any label. a plain walk, so whether a wildcard
+    // or an assertion could actually be taken isn't looked at
+    pub(crate) fn reachable_from(&self, state_id: usize) -> BTreeSet<usize> {
+        let mut reachable = BTreeSet::new();
+        let mut stack = vec![state_id];
+        while let Some(id) = stack.pop() {
+            if reachable.insert(id) {
+                stack.extend(self.states[id].active_transitions().iter().map(|t| t.to));
+            }
+        }
+        reachable
+    }
+    // whether no accepting state can be reached from the state, so any
+    // match that gets there fails, e.g. to gray it out in a drawing. see
+    // dead_states to check every state at once
+    pub fn is_dead_state(&self, id: usize) -> bool {
+        !self.live_states()[id]
+    }
+    // the ids of every state is_dead_state is true for
+    pub fn dead_states(&self) -> Vec<usize> {
+        let live = self.live_states();
+        (0..self.states.len()).filter(|&id| !live[id]).collect()
+    }
+    // a shortest string the NFA accepts, None for the empty language.
+    // BFS over states where epsilon transitions are free and consuming
+    // transitions cost one char (0-1 BFS), keeping how we got to each state
+    // so the string can be rebuilt
+    pub fn shortest_match(&self) -> Option<String> {
+        if self.has_counters() {
+            return self.expand_counters().shortest_match();
+        }
+        let mut dist: Vec<Option<usize>> = vec![None; self.states.len()];
+        // (previous state, char consumed to get here)
+        let mut prev: Vec<Option<(usize, Option<char>)>> = vec![None; self.states.len()];
+        let mut deque = VecDeque::new();
+
+        dist[self.start_id] = Some(0);
+        deque.push_back(self.start_id);
+
+        let mut accepting = None;
+        while let Some(state_id) = deque.pop_front() {
+            let d = dist[state_id].unwrap();
+            if self.states[state_id].accepting {
+                accepting = Some(state_id);
+                break;
+            }
+            for transition in self.states[state_id].active_transitions() {
+                let c = match &transition.label {
+                    // assertions are taken as if they hold, so with
+                    // whole_word the string may need words around it
+                    TransitionLabel::Epsilon
+                    | TransitionLabel::Assertion(_)
+                    | TransitionLabel::Counter(_) => None,
+                    TransitionLabel::Letter(c) => Some(*c),
+                    TransitionLabel::Wildcard => match self.wildcard_example() {
+                        Some(c) => Some(c),
+                        None => continue,
+                    },
+                    TransitionLabel::Class(class) => match class.first() {
+                        Some(c) => Some(c),
+                        None => continue,
+                    },
+                };
+                let next_dist = d + c.is_some() as usize;
+                if dist[transition.to].is_some_and(|old| old <= next_dist) {
+                    continue;
+                }
+                dist[transition.to] = Some(next_dist);
+                prev[transition.to] = Some((state_id, c));
+                // free transitions go first so states come off the deque in
+                // order of distance
+                if c.is_some() {
+                    deque.push_back(transition.to);
+                } else {
+                    deque.push_front(transition.to);
+                }
+            }
+        }
+
+        let mut chars = vec![];
+        let mut state_id = accepting?;
+        while let Some((prev_id, c)) = prev[state_id] {
+            chars.extend(c);
+            state_id = prev_id;
+        }
+        Some(chars.into_iter().rev().collect())
+    }
+    // whether the NFA accepts "", i.e. an accepting state is in the epsilon
+    // closure of the start state. on an empty input ^ and $ both hold but a
+    // word boundary doesn't, so ^$ accepts "" and whole_word never does
+    pub fn accepts_empty(&self) -> bool {
+        if self.has_counters() {
+            return self.expand_counters().accepts_empty();
+        }
+        let mut states = BTreeSet::from([self.start_id]);
+        self.closure_where(&mut states, |assertion| assertion.holds(None, None));
+        states.iter().any(|&id| self.states[id].accepting)
+    }
+    // the fewest chars any accepted string has, so inputs shorter than this
+    // can be rejected without matching. loops can only add chars, so they
+    // never lower it. nothing is short enough for the empty language, which
+    // gets usize::MAX
+    pub fn min_length(&self) -> usize {
+        match self.shortest_match() {
+            Some(shortest) => shortest.chars().count(),
+            None => usize::MAX,
+        }
+    }
+    // the most chars any accepted string has, or None if there is no limit.
+    // there's no limit when a loop that consumes chars is on a path from the
+    // start to an accepting state, loops of only epsilons (e.g. from
+    // (a{0})*) don't count
+    pub fn max_length(&self) -> Option<usize> {
+        if self.has_counters() {
+            return self.expand_counters().max_length();
+        }
+        // without epsilons every transition is exactly one char, except
+        // for assertions which are none
+        let mut nfa = self.clone();
+        nfa.remove_epsilon_states();
+        let num_states = nfa.states.len();
+        let wildcard_matches_any = nfa.wildcard_example().is_some();
+        let can_take = |transition: &Transition| {
+            transition.label != TransitionLabel::Wildcard || wildcard_matches_any
+        };
+
+        // only states on some path from the start to an accepting state can
+        // make an accepted string longer
+        let mut incoming = vec![vec![]; num_states];
+        for (id, state) in nfa.states.iter().enumerate() {
+            for transition in state.active_transitions().iter().filter(|t| can_take(t)) {
+                incoming[transition.to].push(id);
+            }
+        }
+        let mut from_start = vec![false; num_states];
+        let mut stack = vec![nfa.start_id];
+        while let Some(id) = stack.pop() {
+            if !std::mem::replace(&mut from_start[id], true) {
+                let transitions = nfa.states[id].active_transitions().iter();
+                stack.extend(transitions.filter(|t| can_take(t)).map(|t| t.to));
+            }
+        }
+        let mut to_accepting = vec![false; num_states];
+        let mut stack: Vec<usize> = (0..num_states)
+            .filter(|&id| nfa.states[id].accepting)
+            .collect();
+        while let Some(id) = stack.pop() {
+            if !std::mem::replace(&mut to_accepting[id], true) {
+                stack.extend(&incoming[id]);
+            }
+        }
+        let useful: Vec<bool> = (0..num_states)
+            .map(|id| from_start[id] && to_accepting[id])
+            .collect();
+
+        // longest path by taking the useful states in topological order
+        // (kahn's algorithm), a cycle means some states never get taken
+        let useful_transitions = |id: usize| {
+            nfa.states[id]
+                .active_transitions()
+                .iter()
+                .filter(|t| can_take(t) && useful[t.to])
+                .map(|t| {
+                    (
+                        t.to,
+                        !matches!(t.label, TransitionLabel::Assertion(_)) as usize,
+                    )
+                })
+        };
+        let mut num_incoming = vec![0; num_states];
+        for id in (0..num_states).filter(|&id| useful[id]) {
+            for (to, _) in useful_transitions(id) {
+                num_incoming[to] += 1;
+            }
+        }
+        let mut queue: VecDeque<usize> = (0..num_states)
+            .filter(|&id| useful[id] && num_incoming[id] == 0)
+            .collect();
+        let mut longest = vec![0; num_states];
+        let mut num_taken = 0;
+        let mut max_length = 0;
+        while let Some(id) = queue.pop_front() {
+            num_taken += 1;
+            if nfa.states[id].accepting {
+                max_length = max_length.max(longest[id]);
+            }
+            for (to, length) in useful_transitions(id) {
+                longest[to] = longest[to].max(longest[id] + length);
+                num_incoming[to] -= 1;
+                if num_incoming[to] == 0 {
+                    queue.push_back(to);
+                }
+            }
+        }
+
+        (num_taken == useful.iter().filter(|&&u| u).count()).then_some(max_length)
+    }
+    // whether every match has to start at the start of the input, because
+    // every way out of the start state passes a ^ before consuming a char
+    // or accepting
+    pub fn is_anchored_start(&self) -> bool {
+        if self.has_counters() {
+            return self.expand_counters().is_anchored_start();
+        }
+        let mut seen = BTreeSet::new();
+        let mut stack = vec![self.start_id];
+        while let Some(state_id) = stack.pop() {
+            if !seen.insert(state_id) {
+                continue;
+            }
+            if self.states[state_id].accepting {
+                return false;
+            }
+            for transition in self.states[state_id].active_transitions() {
+                match &transition.label {
+                    TransitionLabel::Assertion(Assertion::StartText) => {}
+                    TransitionLabel::Epsilon
+                    | TransitionLabel::Assertion(_)
+                    | TransitionLabel::Counter(_) => stack.push(transition.to),
+                    TransitionLabel::Letter(_)
+                    | TransitionLabel::Wildcard
+                    | TransitionLabel::Class(_) => return false,
+                }
+            }
+        }
+        true
+    }
+    // whether every match has to end at the end of the input, because a $
+    // comes after the last char on every way to an accepting state. same
+    // as is_anchored_start, but walking backwards from the accepting states
+    pub fn is_anchored_end(&self) -> bool {
+        if self.has_counters() {
+            return self.expand_counters().is_anchored_end();
+        }
+        let mut incoming = vec![vec![]; self.states.len()];
+        for (state_id, state) in self.states.iter().enumerate() {
+            for transition in state.active_transitions() {
+                incoming[transition.to].push((state_id, &transition.label));
+            }
+        }
+
+        let mut seen = BTreeSet::new();
+        let mut stack: Vec<usize> = (0..self.states.len())
+            .filter(|&state_id| self.states[state_id].accepting)
+            .collect();
+        while let Some(state_id) = stack.pop() {
+            if !seen.insert(state_id) {
+                continue;
+            }
+            if state_id == self.start_id {
+                return false;
+            }
+            for &(from, label) in &incoming[state_id] {
+                match label {
+                    TransitionLabel::Assertion(Assertion::EndText) => {}
+                    TransitionLabel::Epsilon
+                    | TransitionLabel::Assertion(_)
+                    | TransitionLabel::Counter(_) => stack.push(from),
+                    TransitionLabel::Letter(_)
+                    | TransitionLabel::Wildcard
+                    | TransitionLabel::Class(_) => return false,
+                }
+            }
+        }
+        true
+    }
+    // whether nothing can ever match, not even part of some input. unlike
+    // shortest_match this looks at where the anchors are, so a$b matches
+    // nothing: a ^ can only be passed before the first char and a $ only
+    // after the last. word boundaries and multiline anchors are taken as if
+    // they hold
+    pub fn matches_nothing(&self) -> bool {
+        if self.has_counters() {
+            return self.expand_counters().matches_nothing();
+        }
+        let can_consume = |label: &TransitionLabel| match label {
+            TransitionLabel::Letter(_) => true,
+            TransitionLabel::Wildcard => self.wildcard_example().is_some(),
+            TransitionLabel::Class(class) => !class.is_empty(),
+            TransitionLabel::Epsilon
+            | TransitionLabel::Assertion(_)
+            | TransitionLabel::Counter(_) => false,
+        };
+        // (state, whether a char was consumed, whether a $ was passed)
+        let mut seen = BTreeSet::new();
+        let mut stack = vec![(self.start_id, false, false)];
+        while let Some(node) = stack.pop() {
+            if !seen.insert(node) {
+                continue;
+            }
+            let (state_id, consumed, ended) = node;
+            if self.states[state_id].accepting {
+                return false;
+            }
+            for transition in self.states[state_id].active_transitions() {
+                let to = transition.to;
+                match &transition.label {
+                    TransitionLabel::Assertion(Assertion::StartText) if consumed => {}
+                    TransitionLabel::Assertion(Assertion::EndText) => {
+                        stack.push((to, consumed, true))
+                    }
+                    TransitionLabel::Epsilon | TransitionLabel::Assertion(_) => {
+                        stack.push((to, consumed, ended))
+                    }
+                    label if can_consume(label) && !ended => stack.push((to, true, ended)),
+                    _ => {}
+                }
+            }
+        }
+        true
+    }
+    // every string the NFA accepts with at most max_len chars, shorter
+    // strings first and in order within a length. the chars tried after
+    // each prefix are the ones next_chars lists, so an unrestricted wildcard
+    // adds nothing and this is only every accepted string for concrete NFAs
+    // like the ones from from_regex_over. there can be alphabet^max_len
+    // strings, so keep max_len small
+    pub fn accepted_strings(&self, max_len: usize) -> Vec<String> {
+        let mut accepted = vec![];
+        let mut prefixes = vec![(String::new(), self.matcher())];
+        for len in 0..=max_len {
+            let mut longer = vec![];
+            for (prefix, matcher) in prefixes {
+                if matcher.is_match() {
+                    accepted.push(prefix.clone());
+                }
+                if len == max_len {
+                    continue;
+                }
+                for c in matcher.next_letters().0 {
+                    let mut next = matcher.clone();
+                    next.feed(c);
+                    let mut prefix = prefix.clone();
+                    prefix.push(c);
+                    longer.push((prefix, next));
+                }
+            }
+            prefixes = longer;
+        }
+        accepted
+    }
+    // every accepted string of at most max_len chars from the alphabet,
+    // shorter strings first and in alphabet order within a length, passed
+    // to f one at a time until f returns false. unlike accepted_strings,
+    // nothing but the current string is kept: each length is a new depth
+    // first search from the start (iterative deepening), which stops early
+    // once no string of that length can lead to a match
+    pub fn for_each_accepted(
+        &self,
+        max_len: usize,
+        alphabet: &[char],
+        mut f: impl FnMut(&str) -> bool,
+    ) {
+        let matcher = self.matcher();
+        for len in 0..=max_len {
+            let mut prefix = String::new();
+            match accepted_of_len(&matcher, &mut prefix, len, alphabet, &mut f) {
+                ControlFlow::Continue(true) => {}
+                ControlFlow::Continue(false) | ControlFlow::Break(()) => return,
+            }
+        }
+    }
+    // whether both NFAs accept exactly the same strings. both are run in
+    // lockstep on every char either of them can take next, like a subset
+    // construction of their product, until a string one accepts and the
+    // other doesn't turns up or there are no new pairs of states left.
+    // chars that every transition of both NFAs takes all or none of (and
+    // the assertions see the same) lead to the same pairs, so one char of
+    // each is fed, see joint_representatives. that keeps it exact even
+    // with wildcards and large classes
+    pub fn is_equivalent(&self, other: &NFA) -> bool {
+        let representatives = NFA::joint_representatives(&[self, other]);
+        let mut seen = BTreeSet::new();
+        let mut queue = VecDeque::from([(self.matcher(), other.matcher())]);
+        while let Some((left, right)) = queue.pop_front() {
+            // the last char only matters to assertions, but without it two
+            // pairs with the same states could still disagree on them
+            let key = (left.active().clone(), right.active().clone(), left.prev());
+            if !seen.insert(key) {
+                continue;
+            }
+            if left.is_match() != right.is_match() {
+                return false;
+            }
+
+            // a char neither side takes leaves both with no states
+            let (left_next, right_next) = (left.next_letters(), right.next_letters());
+            for &c in representatives
+                .iter()
+                .filter(|&&c| takes(&left_next, c) || takes(&right_next, c))
+            {
+                let (mut left, mut right) = (left.clone(), right.clone());
+                left.feed(c);
+                right.feed(c);
+                queue.push_back((left, right));
+            }
+        }
+        true
+    }
+    // whether some string is accepted by both NFAs, without building their
+    // product automaton first. pairs of matchers are explored breadth first
+    // from the start, fed only the chars both sides can take next, and a
+    // pair is dropped as soon as either side can't match anymore. so it
+    // stops at the first string both accept, and languages that part ways
+    // early are told apart early, e.g. a.* and b.* right away. like
+    // is_equivalent, it's exact with wildcards and large classes too
+    pub fn intersects(&self, other: &NFA) -> bool {
+        self.search_product(
+            other,
+            |left, right| left.is_match() && right.is_match(),
+            |left, right| left.can_still_match() && right.can_still_match(),
+            |left_takes, right_takes| left_takes && right_takes,
+        )
+    }
+    // whether every string this NFA accepts is accepted by the other one
+    // too. the same search as intersects, for a string only this side
+    // accepts, so pairs are only dropped once this side can't match
+    pub fn is_subset_of(&self, other: &NFA) -> bool {
+        !self.search_product(
+            other,
+            |left, right| left.is_match() && !right.is_match(),
+            |left, _| left.can_still_match(),
+            |left_takes, _| left_takes,
+        )
+    }
+    // breadth first search over pairs of matchers for self and other, fed
+    // the same chars, for a pair that's found. the chars are one of each
+    // class of the joint alphabet (see is_equivalent), and feed says which
+    // of them to feed a pair from whether each side could take it next.
+    // pairs that aren't alive any more aren't fed at all
+    fn search_product(
+        &self,
+        other: &NFA,
+        found: impl Fn(&Matcher, &Matcher) -> bool,
+        alive: impl Fn(&Matcher, &Matcher) -> bool,
+        feed: impl Fn(bool, bool) -> bool,
+    ) -> bool {
+        let representatives = NFA::joint_representatives(&[self, other]);
+        let mut seen = BTreeSet::new();
+        let mut queue = VecDeque::from([(self.matcher(), other.matcher())]);
+        while let Some((left, right)) = queue.pop_front() {
+            // see is_equivalent for why the last char is part of the key
+            let key = (left.active().clone(), right.active().clone(), left.prev());
+            if !seen.insert(key) {
+                continue;
+            }
+            if found(&left, &right) {
+                return true;
+            }
+            if !alive(&left, &right) {
+                continue;
+            }
+            let (left_next, right_next) = (left.next_letters(), right.next_letters());
+            for &c in representatives
+                .iter()
+                .filter(|&&c| feed(takes(&left_next, c), takes(&right_next, c)))
+            {
+                let (mut left, mut right) = (left.clone(), right.clone());
+                left.feed(c);
+                right.feed(c);
+                queue.push_back((left, right));
+            }
+        }
+        false
+    }
+    // a random string the NFA accepts, e.g. for property tests of code
+    // that consumes matches. a random walk from the start state: each step
+    // takes a transition that can still lead to a match, or stops if the
+    // state accepts, all equally likely. rng gives the random numbers
+    // (e.g. || rng.gen() with the rand crate). wildcards and classes take a
+    // random char from the alphabet that they match, a class with none of
+    // them its first char. None if the walk isn't on an accepting state
+    // after max_steps steps, or the string breaks an assertion on the way
+    pub fn sample(
+        &self,
+        rng: &mut impl FnMut() -> u64,
+        max_steps: usize,
+        alphabet: &[char],
+    ) -> Option<String> {
+        if self.has_counters() {
+            return self.expand_counters().sample(rng, max_steps, alphabet);
+        }
+        let live = self.live_states();
+        let mut pick = |len: usize| (rng() % len as u64) as usize;
+        let mut sample = String::new();
+        let mut state_id = self.start_id;
+        for _ in 0..max_steps {
+            if !live[state_id] {
+                return None;
+            }
+            let mut moves: Vec<(Option<char>, usize)> = vec![];
+            for transition in self.states[state_id].active_transitions() {
+                if !live[transition.to] {
+                    continue;
+                }
+                let c = match &transition.label {
+                    TransitionLabel::Letter(c) => Some(*c),
+                    TransitionLabel::Wildcard => {
+                        let chars: Vec<char> = alphabet
+                            .iter()
+                            .copied()
+                            .filter(|&c| self.wildcard_matches(c))
+                            .collect();
+                        if chars.is_empty() {
+                            continue;
+                        }
+                        Some(chars[pick(chars.len())])
+                    }
+                    TransitionLabel::Class(class) => {
+                        let chars: Vec<char> = alphabet
+                            .iter()
+                            .copied()
+                            .filter(|&c| class.contains(c))
+                            .collect();
+                        match chars.is_empty() {
+                            true => class.first(),
+                            false => Some(chars[pick(chars.len())]),
+                        }
+                    }
+                    TransitionLabel::Epsilon | TransitionLabel::Assertion(_) => None,
+                    // expanded above
+                    TransitionLabel::Counter(_) => unreachable!(),
+                };
+                moves.push((c, transition.to));
+            }
+            let accepting = self.states[state_id].accepting;
+            if moves.is_empty() && !accepting {
+                return None;
+            }
+            let choice = pick(moves.len() + accepting as usize);
+            if choice == moves.len() {
+                break;
+            }
+            let (c, to) = moves[choice];
+            sample.extend(c);
+            state_id = to;
+        }
+        if !self.states[state_id].accepting {
+            return None;
+        }
+        self.is_match(&sample).then_some(sample)
+    }
+}
+
+// whether a char can be next, from what Matcher::next_letters says. any
+// might only be a large class, so this can be true for chars that aren't
+// taken, but never false for one that is
+fn takes((letters, any): &(BTreeSet<char>, bool), c: char) -> bool {
+    *any || letters.contains(&c)
+}
+
+// calls f on every accepted string that is the prefix and len more chars
+// from the alphabet. breaks when f asks to stop, and otherwise says whether
+// any such string kept the matcher alive
+fn accepted_of_len(
+    matcher: &Matcher,
+    prefix: &mut String,
+    len: usize,
+    alphabet: &[char],
+    f: &mut impl FnMut(&str) -> bool,
+) -> ControlFlow<(), bool> {
+    if len == 0 {
+        if matcher.is_match() && !f(prefix) {
+            return ControlFlow::Break(());
+        }
+        return ControlFlow::Continue(true);
+    }
+    let mut alive = false;
+    for &c in alphabet {
+        let mut next = matcher.clone();
+        next.feed(c);
+        if next.is_dead() {
+            continue;
+        }
+        prefix.push(c);
+        alive |= accepted_of_len(&next, prefix, len - 1, alphabet, f)?;
+        prefix.pop();
+    }
+    ControlFlow::Continue(alive)
+}
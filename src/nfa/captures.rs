@@ -0,0 +1,194 @@
+// =================
+// CAPTURES
+// =================
+
+use std::collections::BTreeSet;
+
+use super::counter::num_counters;
+use super::{TransitionLabel, NFA};
+
+// the span of each group as char indices, or None for a group that took
+// no part in the match
+pub type Captures = Vec<Option<(usize, usize)>>;
+
+// one way of matching so far: the state it is in with its counters, and
+// where each group started and ended on the way there (two slots per group)
+struct Thread {
+    state_id: usize,
+    counters: Vec<usize>,
+    slots: Vec<Option<usize>>,
+}
+
+impl NFA {
+    // adds the thread, and every thread it reaches over epsilons (and
+    // assertions that hold at idx), to threads in priority order.
+    // transitions are followed in the order they were added, so the left
+    // side of a union and another loop of a quantifier win over the
+    // alternatives. a state (with the same counters) already in threads
+    // keeps the thread that got there first
+    fn add_thread(
+        &self,
+        threads: &mut Vec<Thread>,
+        seen: &mut BTreeSet<(usize, Vec<usize>)>,
+        thread: Thread,
+        chars: &[char],
+        idx: usize,
+    ) {
+        // a stack instead of recursion so long chains of epsilons (e.g. from
+        // a{1000}) can't overflow
+        let mut stack = vec![thread];
+        while let Some(mut thread) = stack.pop() {
+            if !seen.insert((thread.state_id, thread.counters.clone())) {
+                continue;
+            }
+
+            // a group is only entered through its start and left through
+            // its out, so arriving on them is where the group starts and ends
+            for (group, boundary) in self.groups.iter().enumerate() {
+                if boundary.start_id == thread.state_id {
+                    thread.slots[2 * group] = Some(idx);
+                }
+                if boundary.out_id == thread.state_id {
+                    thread.slots[2 * group + 1] = Some(idx);
+                }
+            }
+
+            // pushed in reverse so the first transition is popped first
+            for transition in self.states[thread.state_id]
+                .active_transitions()
+                .iter()
+                .rev()
+            {
+                let counters = match &transition.label {
+                    TransitionLabel::Epsilon => Some(thread.counters.clone()),
+                    TransitionLabel::Assertion(assertion) => assertion
+                        .holds_at(chars, idx)
+                        .then(|| thread.counters.clone()),
+                    TransitionLabel::Counter(op) => op.apply(&thread.counters),
+                    _ => None,
+                };
+                if let Some(counters) = counters {
+                    stack.push(Thread {
+                        state_id: transition.to,
+                        counters,
+                        slots: thread.slots.clone(),
+                    });
+                }
+            }
+            threads.push(thread);
+        }
+    }
+    // the threads at the start of a match from start, in priority order
+    fn first_threads(&self, chars: &[char], start: usize) -> Vec<Thread> {
+        let mut threads = vec![];
+        let first = Thread {
+            state_id: self.start_id,
+            counters: vec![0; num_counters(&self.states)],
+            slots: vec![None; 2 * self.groups.len()],
+        };
+        self.add_thread(&mut threads, &mut BTreeSet::new(), first, chars, start);
+        threads
+    }
+    // the threads after consuming chars[idx], still in priority order: the
+    // threads stepped from a thread come before those of the threads after it
+    fn step_threads(&self, threads: &[Thread], chars: &[char], idx: usize) -> Vec<Thread> {
+        let mut next = vec![];
+        let mut seen = BTreeSet::new();
+        for thread in threads {
+            for transition in self.states[thread.state_id].active_transitions() {
+                if self.consumes(&transition.label, chars[idx]) {
+                    let stepped = Thread {
+                        state_id: transition.to,
+                        counters: thread.counters.clone(),
+                        slots: thread.slots.clone(),
+                    };
+                    self.add_thread(&mut next, &mut seen, stepped, chars, idx + 1);
+                }
+            }
+        }
+        next
+    }
+    // the end of the match from start that a backtracking engine would
+    // find, for MatchKind::LeftmostFirst. every thread is stepped at once
+    // in priority order (a pike VM), and when one accepts, the threads
+    // after it are dropped since they could only be worse. the threads
+    // before it go on, and a later accept from them wins. so lazy
+    // quantifiers, whose leaving threads come first, stop at the first
+    // end they can, and greedy ones keep going to the last. accepting
+    // comes after the state's own transitions, which the single state
+    // of .* needs to be greedy
+    pub(super) fn first_match_end(&self, chars: &[char], start: usize) -> Option<usize> {
+        let mut threads = self.first_threads(chars, start);
+        let mut end = None;
+        for idx in start..=chars.len() {
+            let accepting = threads
+                .iter()
+                .position(|thread| self.states[thread.state_id].accepting);
+            if let Some(i) = accepting {
+                end = Some(idx);
+                threads.truncate(i + 1);
+            }
+            if threads.is_empty() || idx == chars.len() {
+                break;
+            }
+            threads = self.step_threads(&threads, chars, idx);
+        }
+        end
+    }
+    // the captures of matching exactly chars[start..end], stepping every
+    // thread over one char at a time
+    pub(crate) fn captures_between(
+        &self,
+        chars: &[char],
+        start: usize,
+        end: usize,
+    ) -> Option<Captures> {
+        let mut threads = self.first_threads(chars, start);
+        for idx in start..end {
+            threads = self.step_threads(&threads, chars, idx);
+            if threads.is_empty() {
+                return None;
+            }
+        }
+
+        let thread = threads
+            .into_iter()
+            .find(|thread| self.states[thread.state_id].accepting)?;
+        Some(
+            thread
+                .slots
+                .chunks(2)
+                .map(|slots| match (slots[0], slots[1]) {
+                    (Some(start), Some(end)) => Some((start, end)),
+                    _ => None,
+                })
+                .collect(),
+        )
+    }
+    // the span of each group when the whole input matches, or None if it
+    // doesn't. groups are numbered by their open parenthesis from 0, like in
+    // patch_group. a group that matches more than once (e.g. in (a)*) keeps
+    // its last span, and when there's more than one way to match, quantifiers
+    // take as much as they can and unions prefer their left side.
+    // a group inside of a {min,max} quantifier only reports its first copy
+    pub fn captures(&self, input: &str) -> Option<Captures> {
+        if self.has_counters() {
+            return self.expand_counters().captures(input);
+        }
+        let chars: Vec<char> = input.chars().collect();
+        self.captures_between(&chars, 0, chars.len())
+    }
+    // the captures of every match find_iter finds, in order, e.g. each
+    // (key)=(value) pair in a string. the spans are of the whole input, not
+    // of the match they are in
+    pub fn captures_iter(&self, input: &str) -> Vec<Captures> {
+        if self.has_counters() {
+            return self.expand_counters().captures_iter(input);
+        }
+        let chars: Vec<char> = input.chars().collect();
+        self.find_iter_chars(&chars)
+            .into_iter()
+            .filter_map(|(start, end)| self.captures_between(&chars, start, end))
+            .collect()
+    }
+}
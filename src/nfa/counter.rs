@@ -0,0 +1,228 @@
+// =================
+// COUNTERS
+// =================
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use super::{GroupBoundary, NFAFragement, RepeatStrategy, State, Transition, TransitionLabel, NFA};
+
+// what a counter transition does. like an epsilon it consumes nothing, but
+// it is only taken when its check on the counter passes. counters are 0
+// outside of their loop, since leaving the loop resets them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(super) enum CounterOp {
+    // into the body for another repetition, unless there have been max
+    Enter { counter: usize, max: Option<usize> },
+    // after a repetition. the count stops at limit, past which it doesn't
+    // matter anymore (max, or min without a max)
+    Increment { counter: usize, limit: usize },
+    // out of the loop after at least min repetitions
+    Exit { counter: usize, min: usize },
+}
+
+impl CounterOp {
+    // the counters after taking the transition, None if it can't be taken
+    pub(super) fn apply(&self, counters: &[usize]) -> Option<Vec<usize>> {
+        let mut counters = counters.to_vec();
+        match *self {
+            CounterOp::Enter { counter, max } => {
+                if max.is_some_and(|max| counters[counter] >= max) {
+                    return None;
+                }
+            }
+            CounterOp::Increment { counter, limit } => {
+                counters[counter] = (counters[counter] + 1).min(limit);
+            }
+            CounterOp::Exit { counter, min } => {
+                if counters[counter] < min {
+                    return None;
+                }
+                counters[counter] = 0;
+            }
+        }
+        Some(counters)
+    }
+    fn counter(&self) -> usize {
+        match *self {
+            CounterOp::Enter { counter, .. }
+            | CounterOp::Increment { counter, .. }
+            | CounterOp::Exit { counter, .. } => counter,
+        }
+    }
+}
+
+// how many counters the states use
+pub(super) fn num_counters(states: &[State]) -> usize {
+    states
+        .iter()
+        .flat_map(|state| state.active_transitions())
+        .filter_map(|transition| match &transition.label {
+            TransitionLabel::Counter(op) => Some(op.counter() + 1),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+// numbers (state, counters) nodes in the order they are first seen
+#[derive(Default)]
+struct Nodes {
+    ids: BTreeMap<(usize, Vec<usize>), usize>,
+    nodes: Vec<(usize, Vec<usize>)>,
+}
+
+impl Nodes {
+    fn id(&mut self, node: (usize, Vec<usize>)) -> usize {
+        if let Some(&id) = self.ids.get(&node) {
+            return id;
+        }
+        self.nodes.push(node.clone());
+        self.ids.insert(node, self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+}
+
+impl NFA {
+    // frag{min,max} as one copy of frag in a loop with a counter, instead
+    // of a copy per repetition. the loop state enters frag while there
+    // have been fewer than max repetitions, and leaves once there have
+    // been at least min
+    pub(super) fn add_counter_fragment(
+        states: &mut Vec<State>,
+        frag: NFAFragement,
+        min: usize,
+        max: Option<usize>,
+        lazy: bool,
+    ) -> NFAFragement {
+        let counter = num_counters(states);
+        let loop_id = states.len();
+        let out_id = states.len() + 1;
+        let label = |op| TransitionLabel::Counter(op);
+
+        states[frag.out_id].add_transition(Transition {
+            label: label(CounterOp::Increment {
+                counter,
+                limit: max.unwrap_or(min),
+            }),
+            to: loop_id,
+        });
+        // like the other loops, repeating comes before leaving unless lazy
+        let mut ways = [
+            Transition {
+                label: label(CounterOp::Enter { counter, max }),
+                to: frag.start_id,
+            },
+            Transition {
+                label: label(CounterOp::Exit { counter, min }),
+                to: out_id,
+            },
+        ];
+        if lazy {
+            ways.reverse();
+        }
+        let [first, second] = ways;
+        states.push(State::new().with_transition(first).with_transition(second));
+        states.push(State::new());
+
+        NFAFragement {
+            first_id: frag.first_id,
+            start_id: loop_id,
+            out_id,
+            is_union: false,
+        }
+    }
+    // whether the NFA has counted loops, from RepeatStrategy::Counter
+    pub fn has_counters(&self) -> bool {
+        num_counters(&self.states) > 0
+    }
+    // the same NFA without counters: one state for every state and counter
+    // values it can be in, so a counted loop becomes one copy of its body
+    // per repetition, like RepeatStrategy::Eager would build it. matching
+    // follows the counters as it goes, but everything else (captures,
+    // analysis, simplify, ...) works on this. an NFA without counters is
+    // returned as is
+    pub fn expand_counters(&self) -> NFA {
+        let num_counters = num_counters(&self.states);
+        if num_counters == 0 {
+            return self.clone();
+        }
+
+        // the (state, counters) node behind each expanded state
+        let mut nodes = Nodes::default();
+        let start_id = nodes.id((self.start_id, vec![0; num_counters]));
+        // a group keeps the copy of its start and out from its first
+        // repetition, like the first copy Eager records
+        let groups: Vec<GroupBoundary> = self
+            .groups
+            .iter()
+            .map(|group| {
+                let first = self.first_counters_at(group.start_id, num_counters);
+                GroupBoundary {
+                    start_id: nodes.id((group.start_id, first.clone())),
+                    out_id: nodes.id((group.out_id, first)),
+                    ..group.clone()
+                }
+            })
+            .collect();
+
+        let mut states: Vec<State> = vec![];
+        while states.len() < nodes.nodes.len() {
+            let (state_id, counters) = nodes.nodes[states.len()].clone();
+            let mut state = State::new();
+            state.set_accepting(self.states[state_id].accepting);
+            for transition in self.states[state_id].active_transitions() {
+                let (label, next_counters) = match &transition.label {
+                    TransitionLabel::Counter(op) => match op.apply(&counters) {
+                        Some(next_counters) => (TransitionLabel::Epsilon, next_counters),
+                        None => continue,
+                    },
+                    label => (label.clone(), counters.clone()),
+                };
+                let to = nodes.id((transition.to, next_counters));
+                state.add_transition(Transition { label, to });
+            }
+            states.push(state);
+        }
+
+        let mut nfa = NFA {
+            start_id,
+            states,
+            groups,
+            wildcard_set: self.wildcard_set.clone(),
+            first_char_set: None,
+            literal_prefix: String::new(),
+            match_kind: self.match_kind,
+            compiled: self.compiled.clone(),
+        };
+        // so a group patched in later doesn't bring the counters back
+        nfa.compiled.options.repeat_strategy = RepeatStrategy::Eager;
+        nfa.update_first_char_set();
+        nfa
+    }
+    // the counter values the state is first reached with, searching
+    // breadth first from the start. all 0 if it can't be reached
+    fn first_counters_at(&self, target: usize, num_counters: usize) -> Vec<usize> {
+        let start = (self.start_id, vec![0; num_counters]);
+        let mut seen = BTreeSet::from([start.clone()]);
+        let mut queue = VecDeque::from([start]);
+        while let Some((state_id, counters)) = queue.pop_front() {
+            if state_id == target {
+                return counters;
+            }
+            for transition in self.states[state_id].active_transitions() {
+                let next_counters = match &transition.label {
+                    TransitionLabel::Counter(op) => match op.apply(&counters) {
+                        Some(next_counters) => next_counters,
+                        None => continue,
+                    },
+                    _ => counters.clone(),
+                };
+                let next = (transition.to, next_counters);
+                if seen.insert(next.clone()) {
+                    queue.push_back(next);
+                }
+            }
+        }
+        vec![0; num_counters]
+    }
+}
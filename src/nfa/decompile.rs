@@ -0,0 +1,308 @@
+// =================
+// DECOMPILING
+// =================
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::class::CharClass;
+
+use super::{Assertion, TransitionLabel, NFA};
+
+// the chars with a meaning in a regex, which have to be escaped to be
+// matched literally
+const RESERVED: [char; 14] = [
+    '\\', '(', ')', '|', '*', '.', '?', '+', '^', '$', '{', '}', '[', ']',
+];
+// the chars with a meaning inside of a [...] class
+const CLASS_RESERVED: [char; 6] = ['\\', '[', ']', '-', '^', '&'];
+
+// a regex being built up by state elimination. the constructors below
+// simplify as they go, so the pattern doesn't fill up with empty strings
+#[derive(Debug, Clone, PartialEq)]
+enum Re {
+    // matches only the empty string, which has no syntax of its own
+    Epsilon,
+    // a letter, wildcard, class or anchor, already written out
+    Atom(String),
+    Concat(Vec<Re>),
+    // never holds Epsilon, an optional union is Optional(Union)
+    Union(Vec<Re>),
+    Optional(Box<Re>),
+    Star(Box<Re>),
+    Plus(Box<Re>),
+}
+
+impl Re {
+    fn concat(left: Re, right: Re) -> Re {
+        let mut parts = vec![];
+        for re in [left, right] {
+            match re {
+                Re::Epsilon => {}
+                Re::Concat(inner) => parts.extend(inner),
+                re => parts.push(re),
+            }
+        }
+        // x followed by x* is x+
+        let mut merged: Vec<Re> = vec![];
+        for part in parts {
+            match (merged.last(), &part) {
+                (Some(last), Re::Star(inner)) if last == inner.as_ref() => {
+                    *merged.last_mut().unwrap() = Re::Plus(inner.clone());
+                }
+                _ => merged.push(part),
+            }
+        }
+        match merged.len() {
+            0 => Re::Epsilon,
+            1 => merged.pop().unwrap(),
+            _ => Re::Concat(merged),
+        }
+    }
+    fn union(left: Re, right: Re) -> Re {
+        let mut optional = false;
+        let mut alternatives: Vec<Re> = vec![];
+        for re in [left, right] {
+            let inner = match re {
+                Re::Epsilon => {
+                    optional = true;
+                    continue;
+                }
+                Re::Optional(inner) => {
+                    optional = true;
+                    *inner
+                }
+                re => re,
+            };
+            let inner = match inner {
+                Re::Union(inner) => inner,
+                re => vec![re],
+            };
+            for alternative in inner {
+                if !alternatives.contains(&alternative) {
+                    alternatives.push(alternative);
+                }
+            }
+        }
+        let re = match alternatives.len() {
+            0 => return Re::Epsilon,
+            1 => alternatives.pop().unwrap(),
+            _ => Re::Union(alternatives),
+        };
+        if optional {
+            Re::optional(re)
+        } else {
+            re
+        }
+    }
+    fn optional(re: Re) -> Re {
+        match re {
+            Re::Epsilon | Re::Optional(_) | Re::Star(_) => re,
+            Re::Plus(inner) => Re::Star(inner),
+            re => Re::Optional(Box::new(re)),
+        }
+    }
+    fn star(re: Re) -> Re {
+        match re {
+            Re::Epsilon => Re::Epsilon,
+            Re::Optional(inner) | Re::Star(inner) | Re::Plus(inner) => Re::Star(inner),
+            re => Re::Star(Box::new(re)),
+        }
+    }
+    // written as an operand of a quantifier, which has to be a single
+    // atom or in parentheses
+    fn write_quantified(&self, quantifier: char) -> String {
+        match self {
+            Re::Atom(atom) => format!("{atom}{quantifier}"),
+            re => format!("({}){quantifier}", re.write()),
+        }
+    }
+    fn write(&self) -> String {
+        match self {
+            // the empty string can't be written on its own, but zero
+            // copies of anything is the empty string
+            Re::Epsilon => ".{0}".to_string(),
+            Re::Atom(atom) => atom.clone(),
+            Re::Concat(parts) => parts
+                .iter()
+                .map(|part| match part {
+                    Re::Union(_) => format!("({})", part.write()),
+                    part => part.write(),
+                })
+                .collect(),
+            Re::Union(alternatives) => alternatives
+                .iter()
+                .map(Re::write)
+                .collect::<Vec<_>>()
+                .join("|"),
+            Re::Optional(inner) => inner.write_quantified('?'),
+            Re::Star(inner) => inner.write_quantified('*'),
+            Re::Plus(inner) => inner.write_quantified('+'),
+        }
+    }
+}
+
+pub(super) fn write_char(c: char) -> String {
+    if RESERVED.contains(&c) {
+        format!("\\{c}")
+    } else {
+        c.to_string()
+    }
+}
+
+// a pattern matching exactly the text, with every reserved char escaped,
+// e.g. a.b* becomes a\.b\*. for building a pattern around user input.
+// not for verbose mode, where whitespace and # would need escaping too
+pub fn escape(text: &str) -> String {
+    text.chars().map(write_char).collect()
+}
+
+fn write_class_char(c: char) -> String {
+    if CLASS_RESERVED.contains(&c) {
+        format!("\\{c}")
+    } else {
+        c.to_string()
+    }
+}
+
+pub(super) fn write_class(class: &CharClass) -> String {
+    let write_ranges = |class: &CharClass| -> String {
+        class
+            .ranges()
+            .iter()
+            .map(|&(low, high)| match low == high {
+                true => write_class_char(low),
+                false => format!("{}-{}", write_class_char(low), write_class_char(high)),
+            })
+            .collect()
+    };
+    // [^a] rather than every char but a
+    let complement = class.complement();
+    if !complement.is_empty() && complement.ranges().len() < class.ranges().len() {
+        format!("[^{}]", write_ranges(&complement))
+    } else {
+        format!("[{}]", write_ranges(class))
+    }
+}
+
+// the regex on the edge between each pair of states, by the state they
+// leave. the states they go into are kept too, so eliminating a state
+// only looks at its own edges
+struct Edges {
+    outgoing: Vec<BTreeMap<usize, Re>>,
+    incoming: Vec<BTreeSet<usize>>,
+}
+
+impl Edges {
+    fn new(num_states: usize) -> Edges {
+        Edges {
+            outgoing: vec![BTreeMap::new(); num_states],
+            incoming: vec![BTreeSet::new(); num_states],
+        }
+    }
+    // an edge next to an existing one between the same states is a union
+    fn add(&mut self, from: usize, to: usize, re: Re) {
+        let re = match self.remove(from, to) {
+            Some(old) => Re::union(old, re),
+            None => re,
+        };
+        self.outgoing[from].insert(to, re);
+        self.incoming[to].insert(from);
+    }
+    fn remove(&mut self, from: usize, to: usize) -> Option<Re> {
+        self.incoming[to].remove(&from);
+        self.outgoing[from].remove(&to)
+    }
+    // the paths through the state, which each become an edge when it's
+    // removed
+    fn paths(&self, id: usize) -> usize {
+        self.incoming[id].len() * self.outgoing[id].len()
+    }
+}
+
+impl NFA {
+    // a pattern accepting the same strings as the NFA, found by state
+    // elimination: each state is removed in turn, and the paths through it
+    // become edges labeled with regexes, until a single edge from the start
+    // to the end is left. the pattern is correct but usually not what a
+    // person would write, and a wildcard is still a ., so it needs the same
+    // wildcard_set to mean the same thing. None when no pattern can be
+    // written for the NFA: the empty language, or the word boundaries of
+    // whole_word and the line anchors of multiline, which have no syntax
+    pub fn to_regex(&self) -> Option<String> {
+        if self.has_counters() {
+            return self.expand_counters().to_regex();
+        }
+        let mut nfa = self.clone();
+        nfa.simplify();
+
+        let num_states = nfa.states.len();
+        let (start, end) = (num_states, num_states + 1);
+        let mut edges = Edges::new(num_states + 2);
+
+        edges.add(start, nfa.start_id, Re::Epsilon);
+        for (id, state) in nfa.states.iter().enumerate() {
+            if state.accepting {
+                edges.add(id, end, Re::Epsilon);
+            }
+            for transition in state.active_transitions() {
+                let atom = match &transition.label {
+                    TransitionLabel::Letter(c) => write_char(*c),
+                    TransitionLabel::Wildcard => ".".to_string(),
+                    TransitionLabel::Class(class) => write_class(class),
+                    TransitionLabel::Assertion(Assertion::StartText) => "^".to_string(),
+                    TransitionLabel::Assertion(Assertion::EndText) => "$".to_string(),
+                    TransitionLabel::Assertion(
+                        Assertion::WordBoundary | Assertion::StartLine | Assertion::EndLine,
+                    ) => return None,
+                    TransitionLabel::Epsilon => {
+                        edges.add(id, transition.to, Re::Epsilon);
+                        continue;
+                    }
+                    // simplify expanded the counters
+                    TransitionLabel::Counter(_) => unreachable!(),
+                };
+                edges.add(id, transition.to, Re::Atom(atom));
+            }
+        }
+
+        let mut remaining: Vec<usize> = (0..num_states).collect();
+        while !remaining.is_empty() {
+            // removing the state with the fewest paths through it first
+            // keeps the pattern smaller
+            let idx = (0..remaining.len())
+                .min_by_key(|&idx| edges.paths(remaining[idx]))
+                .unwrap();
+            let id = remaining.remove(idx);
+
+            let self_loop = edges.remove(id, id).map(Re::star);
+            let incoming: Vec<(usize, Re)> = std::mem::take(&mut edges.incoming[id])
+                .into_iter()
+                .map(|from| (from, edges.outgoing[from].remove(&id).unwrap()))
+                .collect();
+            let outgoing: Vec<(usize, Re)> = std::mem::take(&mut edges.outgoing[id])
+                .into_iter()
+                .collect();
+            for (to, _) in &outgoing {
+                edges.incoming[*to].remove(&id);
+            }
+
+            for (from, mut before) in incoming {
+                for (i, (to, after)) in outgoing.iter().enumerate() {
+                    // the last path can have the edge itself, a long chain
+                    // of states would copy it over and over otherwise
+                    let mut re = match i + 1 == outgoing.len() {
+                        true => std::mem::replace(&mut before, Re::Epsilon),
+                        false => before.clone(),
+                    };
+                    if let Some(self_loop) = &self_loop {
+                        re = Re::concat(re, self_loop.clone());
+                    }
+                    re = Re::concat(re, after.clone());
+                    edges.add(from, *to, re);
+                }
+            }
+        }
+
+        edges.remove(start, end).map(|re| re.write())
+    }
+}
@@ -0,0 +1,483 @@
+// =================
+// DFA
+// =================
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::class::{next_char, prev_char};
+use crate::error::RegexError;
+
+use super::{
+    is_line_terminator, is_word_char, Assertion, Matcher, Transition, TransitionLabel,
+    DEFAULT_MAX_STATES, LINE_TERMINATORS, NFA,
+};
+
+// every serialized DFA starts with these, then the version of the format.
+// from_bytes only reads the current version
+const MAGIC: &[u8; 4] = b"NFAD";
+const VERSION: u8 = 1;
+
+// a deterministic automaton accepting the same strings as an NFA, with as
+// few states as possible. chars are put into classes that every state
+// treats the same, so the table has a column per class instead of per char
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct DFA {
+    // sorted ranges of chars, with the class of each. a char in none of
+    // them can't be part of a match
+    ranges: Vec<(char, char, usize)>,
+    num_classes: usize,
+    // the next state for every state and class, one row per state. the
+    // start state is always 0
+    table: Vec<usize>,
+    accepting: Vec<bool>,
+}
+
+// which assertions an NFA has, and so what they can tell apart in a char
+#[derive(Clone, Copy, Default)]
+struct AssertionKinds {
+    word_boundary: bool,
+    lines: bool,
+}
+
+impl AssertionKinds {
+    // what the assertions see in a char: whether it's a word char (for
+    // word boundaries) and which line terminator it is (for multiline
+    // anchors). \r and \n differ from the others since \r\n is one line
+    // break, the other terminators all look like \u{2028}
+    fn of(&self, c: char) -> (bool, Option<char>) {
+        let line = match c {
+            '\r' | '\n' => Some(c),
+            c if is_line_terminator(c) => Some('\u{2028}'),
+            _ => None,
+        };
+        (
+            self.word_boundary && is_word_char(c),
+            line.filter(|_| self.lines),
+        )
+    }
+}
+
+impl NFA {
+    // the transitions of the states that can be reached from the start,
+    // patch_group leaves states behind that nothing goes to anymore
+    fn reachable_transitions(&self) -> impl Iterator<Item = &Transition> {
+        self.reachable_from(self.start_id)
+            .into_iter()
+            .flat_map(|id| self.states[id].active_transitions())
+    }
+    fn assertion_kinds(&self) -> AssertionKinds {
+        let has = |assertion| {
+            self.reachable_transitions()
+                .any(|transition| transition.label == TransitionLabel::Assertion(assertion))
+        };
+        AssertionKinds {
+            word_boundary: has(Assertion::WordBoundary),
+            lines: has(Assertion::StartLine) || has(Assertion::EndLine),
+        }
+    }
+    // splits the chars into ranges that every transition takes all or none
+    // of (and that the assertions see the same, see AssertionKinds), and
+    // numbers the classes of ranges that behave the same. chars no
+    // transition takes are left out
+    fn char_classes(&self) -> (Vec<(char, char, usize)>, usize) {
+        NFA::joint_char_classes(&[self])
+    }
+    // a char from each class of char_classes, to feed for it
+    fn representatives(ranges: &[(char, char, usize)], num_classes: usize) -> Vec<char> {
+        let mut representatives = vec!['\0'; num_classes];
+        for &(low, _, class) in ranges.iter().rev() {
+            representatives[class] = low;
+        }
+        representatives
+    }
+    // like char_classes, but for the transitions of all the NFAs at once,
+    // so a char from each class is all it takes to run them side by side
+    pub(super) fn joint_representatives(nfas: &[&NFA]) -> Vec<char> {
+        let (ranges, num_classes) = NFA::joint_char_classes(nfas);
+        NFA::representatives(&ranges, num_classes)
+    }
+    fn joint_char_classes(nfas: &[&NFA]) -> (Vec<(char, char, usize)>, usize) {
+        // each label along with the NFA it's in, which decides what a
+        // wildcard takes
+        let labels: BTreeSet<(usize, &TransitionLabel)> = nfas
+            .iter()
+            .enumerate()
+            .flat_map(|(i, nfa)| nfa.reachable_transitions().map(move |t| (i, &t.label)))
+            .filter(|(_, label)| {
+                matches!(
+                    label,
+                    TransitionLabel::Letter(_)
+                        | TransitionLabel::Wildcard
+                        | TransitionLabel::Class(_)
+                )
+            })
+            .collect();
+        let kinds = nfas.iter().fold(AssertionKinds::default(), |kinds, nfa| {
+            let other = nfa.assertion_kinds();
+            AssertionKinds {
+                word_boundary: kinds.word_boundary || other.word_boundary,
+                lines: kinds.lines || other.lines,
+            }
+        });
+
+        // the chars a new range starts at
+        let mut starts = BTreeSet::from(['\0']);
+        let mut cut = |low: char, high: char| {
+            starts.insert(low);
+            starts.extend(next_char(high));
+        };
+        for &(i, label) in &labels {
+            match label {
+                TransitionLabel::Letter(c) => cut(*c, *c),
+                TransitionLabel::Class(class) => {
+                    for &(low, high) in class.ranges() {
+                        cut(low, high);
+                    }
+                }
+                TransitionLabel::Wildcard => {
+                    for &c in nfas[i].wildcard_set.iter().flatten() {
+                        cut(c, c);
+                    }
+                }
+                _ => {}
+            }
+        }
+        for c in LINE_TERMINATORS {
+            cut(c, c);
+        }
+        if kinds.word_boundary {
+            let mut was_word = false;
+            for c in '\0'..=char::MAX {
+                if is_word_char(c) != was_word {
+                    starts.insert(c);
+                    was_word = !was_word;
+                }
+            }
+        }
+
+        let starts: Vec<char> = starts.into_iter().collect();
+        let mut classes: BTreeMap<Vec<bool>, usize> = BTreeMap::new();
+        let mut ranges: Vec<(char, char, usize)> = vec![];
+        for (i, &low) in starts.iter().enumerate() {
+            let high = match starts.get(i + 1) {
+                Some(&next) => prev_char(next).unwrap(),
+                None => char::MAX,
+            };
+            // which labels take the range, and how the assertions see it
+            let mut signature: Vec<bool> = labels
+                .iter()
+                .map(|&(i, label)| nfas[i].consumes(label, low))
+                .collect();
+            if !signature.contains(&true) {
+                continue;
+            }
+            let (word, line) = kinds.of(low);
+            signature.push(word);
+            signature.extend(['\r', '\n', '\u{2028}'].map(|c| line == Some(c)));
+            let next_class = classes.len();
+            let class = *classes.entry(signature).or_insert(next_class);
+            match ranges.last_mut() {
+                Some((_, last_high, last_class))
+                    if *last_class == class && next_char(*last_high) == Some(low) =>
+                {
+                    *last_high = high
+                }
+                _ => ranges.push((low, high, class)),
+            }
+        }
+        (ranges, classes.len())
+    }
+    // the minimal DFA for the NFA, by subset construction over the states
+    // a Matcher can be in. errors with TooManyStates if the subset
+    // construction gets more than DEFAULT_MAX_STATES states, which some
+    // small NFAs do, e.g. (a|b)*a(a|b){20}
+    pub fn to_dfa(&self) -> Result<DFA, RegexError> {
+        let (ranges, num_classes) = self.char_classes();
+        let representatives = NFA::representatives(&ranges, num_classes);
+        // two matchers are in the same DFA state when they have the same
+        // states active and the assertions see the last char the same way
+        let kinds = self.assertion_kinds();
+        let key = |matcher: &Matcher| {
+            let prev = matcher.prev().map(|c| kinds.of(c));
+            (matcher.active().clone(), prev)
+        };
+
+        let mut matchers = vec![self.matcher()];
+        let mut ids = BTreeMap::from([(key(&matchers[0]), 0)]);
+        let mut table = vec![];
+        let mut accepting = vec![];
+        while accepting.len() < matchers.len() {
+            let matcher = matchers[accepting.len()].clone();
+            accepting.push(matcher.is_match());
+            for &c in &representatives {
+                let mut next = matcher.clone();
+                next.feed(c);
+                let next_id = *ids.entry(key(&next)).or_insert_with(|| {
+                    matchers.push(next);
+                    matchers.len() - 1
+                });
+                table.push(next_id);
+            }
+            if matchers.len() > DEFAULT_MAX_STATES {
+                return Err(RegexError::TooManyStates);
+            }
+        }
+
+        let dfa = DFA {
+            ranges,
+            num_classes,
+            table,
+            accepting,
+        };
+        Ok(dfa.minimize())
+    }
+}
+
+// the fewest bytes that fit every state id of a table with num_states
+fn state_width(num_states: usize) -> usize {
+    match num_states {
+        0..=0x100 => 1,
+        0x101..=0x10000 => 2,
+        _ => 4,
+    }
+}
+
+// LEB128, 7 bits a byte starting from the low ones, with the high bit set
+// on every byte but the last
+fn write_varint(bytes: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        bytes.push((n & 0x7F) as u8 | 0x80);
+        n >>= 7;
+    }
+    bytes.push(n as u8);
+}
+
+// reads the parts of a serialized DFA off the front of its bytes
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], RegexError> {
+        if len > self.bytes.len() {
+            return Err(RegexError::InvalidDfa);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+    // a varint of at most 32 bits
+    fn varint(&mut self) -> Result<usize, RegexError> {
+        let mut n: u64 = 0;
+        for shift in (0..35).step_by(7) {
+            let byte = self.take(1)?[0];
+            n |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return u32::try_from(n)
+                    .map(|n| n as usize)
+                    .map_err(|_| RegexError::InvalidDfa);
+            }
+        }
+        Err(RegexError::InvalidDfa)
+    }
+}
+
+impl DFA {
+    // the minimal DFA for a table built some other way, e.g. from
+    // derivatives (see Ast::to_dfa). the start state has to be 0
+    pub(crate) fn from_table(
+        ranges: Vec<(char, char, usize)>,
+        num_classes: usize,
+        table: Vec<usize>,
+        accepting: Vec<bool>,
+    ) -> DFA {
+        let dfa = DFA {
+            ranges,
+            num_classes,
+            table,
+            accepting,
+        };
+        dfa.minimize()
+    }
+    // merges the states no input can tell apart, with Moore's algorithm:
+    // accepting and other states start out in two blocks, and blocks are
+    // split by which blocks their states go to on each class until nothing
+    // splits anymore. blocks are numbered in order of their first state,
+    // so the start state stays 0
+    fn minimize(self) -> DFA {
+        let num_states = self.num_states();
+        let mut block: Vec<usize> = self
+            .accepting
+            .iter()
+            .map(|&accepting| accepting as usize)
+            .collect();
+        let mut num_blocks = 0;
+        loop {
+            let mut blocks: BTreeMap<Vec<usize>, usize> = BTreeMap::new();
+            let next_block: Vec<usize> = (0..num_states)
+                .map(|state| {
+                    let mut signature = vec![block[state]];
+                    signature.extend(self.row(state).iter().map(|&to| block[to]));
+                    let next_id = blocks.len();
+                    *blocks.entry(signature).or_insert(next_id)
+                })
+                .collect();
+            block = next_block;
+            if blocks.len() == num_blocks {
+                break;
+            }
+            num_blocks = blocks.len();
+        }
+
+        let mut table = vec![0; num_blocks * self.num_classes];
+        let mut accepting = vec![false; num_blocks];
+        for state in 0..num_states {
+            let row = &mut table[block[state] * self.num_classes..][..self.num_classes];
+            for (next, &to) in row.iter_mut().zip(self.row(state)) {
+                *next = block[to];
+            }
+            accepting[block[state]] = self.accepting[state];
+        }
+        DFA {
+            table,
+            accepting,
+            ..self
+        }
+    }
+    fn row(&self, state: usize) -> &[usize] {
+        &self.table[state * self.num_classes..][..self.num_classes]
+    }
+    fn class_of(&self, c: char) -> Option<usize> {
+        let i = self.ranges.partition_point(|&(_, high, _)| high < c);
+        let &(low, _, class) = self.ranges.get(i)?;
+        (low <= c).then_some(class)
+    }
+    pub fn num_states(&self) -> usize {
+        self.accepting.len()
+    }
+    // whether the whole input matches, like NFA::is_match, but one table
+    // lookup per char
+    pub fn is_match(&self, input: &str) -> bool {
+        let mut state = 0;
+        for c in input.chars() {
+            match self.class_of(c) {
+                Some(class) => state = self.row(state)[class],
+                None => return false,
+            }
+        }
+        self.accepting[state]
+    }
+    // the DFA in a compact binary format, for shipping it precompiled:
+    // - the magic bytes NFAD, then the version of the format as a byte
+    // - the number of classes and the number of ranges, then each range as
+    //   how far it starts after the last one ends, its length - 1 and its
+    //   class
+    // - the number of states, then the table row by row, every entry
+    //   little endian in as few bytes as fit any state id (1, 2 or 4)
+    // - a bitset of the accepting states, the lowest bit of the first byte
+    //   being state 0
+    // every count, range and class is a LEB128 varint
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        write_varint(&mut bytes, self.num_classes);
+        write_varint(&mut bytes, self.ranges.len());
+        let mut end = 0;
+        for &(low, high, class) in &self.ranges {
+            write_varint(&mut bytes, (low as u32 - end) as usize);
+            write_varint(&mut bytes, (high as u32 - low as u32) as usize);
+            write_varint(&mut bytes, class);
+            end = high as u32 + 1;
+        }
+        write_varint(&mut bytes, self.num_states());
+        let width = state_width(self.num_states());
+        for &to in &self.table {
+            bytes.extend(&to.to_le_bytes()[..width]);
+        }
+        let mut bitset = vec![0u8; self.num_states().div_ceil(8)];
+        for (state, _) in self.accepting.iter().enumerate().filter(|(_, &a)| a) {
+            bitset[state / 8] |= 1 << (state % 8);
+        }
+        bytes.extend(bitset);
+        bytes
+    }
+    // reads a DFA written by to_bytes. errors with UnsupportedDfaVersion
+    // for another version of the format, and InvalidDfa for anything that
+    // to_bytes couldn't have written: bytes missing or left over, ranges
+    // out of order or not made of chars, or classes and states that don't
+    // exist
+    pub fn from_bytes(bytes: &[u8]) -> Result<DFA, RegexError> {
+        let mut reader = Reader { bytes };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(RegexError::InvalidDfa);
+        }
+        let version = reader.take(1)?[0];
+        if version != VERSION {
+            return Err(RegexError::UnsupportedDfaVersion(version));
+        }
+
+        let num_classes = reader.varint()?;
+        let num_ranges = reader.varint()?;
+        let to_char = |n: u64| {
+            u32::try_from(n)
+                .ok()
+                .and_then(char::from_u32)
+                .ok_or(RegexError::InvalidDfa)
+        };
+        let mut ranges = vec![];
+        let mut end = 0;
+        for _ in 0..num_ranges {
+            let low = end + reader.varint()? as u64;
+            let high = low + reader.varint()? as u64;
+            let class = reader.varint()?;
+            if class >= num_classes {
+                return Err(RegexError::InvalidDfa);
+            }
+            ranges.push((to_char(low)?, to_char(high)?, class));
+            end = high + 1;
+        }
+
+        let num_states = reader.varint()?;
+        if num_states == 0 {
+            return Err(RegexError::InvalidDfa);
+        }
+        let width = state_width(num_states);
+        let table_len = num_states
+            .checked_mul(num_classes)
+            .and_then(|len| len.checked_mul(width))
+            .ok_or(RegexError::InvalidDfa)?;
+        let table: Vec<usize> = reader
+            .take(table_len)?
+            .chunks(width)
+            .map(|entry| {
+                entry
+                    .iter()
+                    .rev()
+                    .fold(0, |n, &byte| n << 8 | byte as usize)
+            })
+            .collect();
+        if table.iter().any(|&to| to >= num_states) {
+            return Err(RegexError::InvalidDfa);
+        }
+        let bitset = reader.take(num_states.div_ceil(8))?;
+        let accepting: Vec<bool> = (0..num_states)
+            .map(|state| bitset[state / 8] >> (state % 8) & 1 == 1)
+            .collect();
+        // the bits past the last state are never set
+        let unused_bits = bitset.last().unwrap() >> (num_states % 8);
+        if num_states % 8 != 0 && unused_bits != 0 {
+            return Err(RegexError::InvalidDfa);
+        }
+        if !reader.bytes.is_empty() {
+            return Err(RegexError::InvalidDfa);
+        }
+
+        Ok(DFA {
+            ranges,
+            num_classes,
+            table,
+            accepting,
+        })
+    }
+}
@@ -0,0 +1,78 @@
+// =================
+// GRAPH
+// =================
+
+use crate::ast::json_str;
+
+use super::counter::CounterOp;
+use super::decompile::{write_char, write_class};
+use super::{Assertion, TransitionLabel, NFA};
+
+impl NFA {
+    // the automaton as a node-link graph in JSON, the shape force-directed
+    // layouts like D3's take: {"nodes":[{"id","accepting","start"}],
+    // "links":[{"source","target","label"}]}. there's a node per state,
+    // with the state's id, and a link per transition, in order, labeled
+    // with the pattern for what it consumes (ε for epsilons)
+    pub fn to_graph_json(&self) -> String {
+        let nodes: Vec<String> = self
+            .states
+            .iter()
+            .enumerate()
+            .map(|(id, state)| {
+                format!(
+                    r#"{{"id":{id},"accepting":{},"start":{}}}"#,
+                    state.accepting,
+                    id == self.start_id
+                )
+            })
+            .collect();
+        let mut links = vec![];
+        for (id, state) in self.states.iter().enumerate() {
+            for transition in state.active_transitions() {
+                links.push(format!(
+                    r#"{{"source":{id},"target":{},"label":{}}}"#,
+                    transition.to,
+                    json_str(&label_text(&transition.label))
+                ));
+            }
+        }
+        format!(
+            r#"{{"nodes":[{}],"links":[{}]}}"#,
+            nodes.join(","),
+            links.join(",")
+        )
+    }
+    // the transitions out of a state as (label, target) pairs, labeled
+    // like the links above, so tests can read a state's edges directly
+    #[cfg(test)]
+    pub(crate) fn edges_from(&self, state_id: usize) -> Vec<(String, usize)> {
+        self.states[state_id]
+            .active_transitions()
+            .iter()
+            .map(|transition| (label_text(&transition.label), transition.to))
+            .collect()
+    }
+}
+
+fn label_text(label: &TransitionLabel) -> String {
+    match label {
+        TransitionLabel::Letter(c) => write_char(*c),
+        TransitionLabel::Wildcard => ".".to_string(),
+        TransitionLabel::Class(class) => write_class(class),
+        TransitionLabel::Epsilon => "ε".to_string(),
+        TransitionLabel::Assertion(assertion) => match assertion {
+            Assertion::WordBoundary => "word boundary",
+            Assertion::StartText => "^",
+            Assertion::EndText => "$",
+            Assertion::StartLine => "^ (line)",
+            Assertion::EndLine => "$ (line)",
+        }
+        .to_string(),
+        TransitionLabel::Counter(op) => match *op {
+            CounterOp::Enter { counter, .. } => format!("enter #{counter}"),
+            CounterOp::Increment { counter, .. } => format!("count #{counter}"),
+            CounterOp::Exit { counter, min } => format!("exit #{counter} after {min}"),
+        },
+    }
+}
@@ -0,0 +1,139 @@
+// =================
+// GRAPHEMES
+// =================
+
+use std::collections::{BTreeSet, VecDeque};
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::{Transition, TransitionLabel, NFA};
+
+// the input split into grapheme clusters (what a person sees as one
+// character, like an e followed by a combining accent). boundaries[i] is
+// the char index grapheme i starts at and offsets[i] its byte offset, both
+// with one extra entry for the end of the input
+struct Graphemes {
+    chars: Vec<char>,
+    boundaries: Vec<usize>,
+    offsets: Vec<usize>,
+    // for every char index, the grapheme it is in
+    grapheme_of: Vec<usize>,
+}
+
+impl Graphemes {
+    fn new(input: &str) -> Graphemes {
+        let chars: Vec<char> = input.chars().collect();
+        let mut boundaries = vec![];
+        let mut offsets = vec![];
+        let mut grapheme_of = vec![];
+        for (grapheme, (offset, cluster)) in input.grapheme_indices(true).enumerate() {
+            boundaries.push(grapheme_of.len());
+            offsets.push(offset);
+            grapheme_of.extend(cluster.chars().map(|_| grapheme));
+        }
+        boundaries.push(chars.len());
+        offsets.push(input.len());
+        Graphemes {
+            chars,
+            boundaries,
+            offsets,
+            grapheme_of,
+        }
+    }
+    fn num_graphemes(&self) -> usize {
+        self.boundaries.len() - 1
+    }
+    // the grapheme idx is in, or num_graphemes at the end of the input
+    fn grapheme_at(&self, idx: usize) -> usize {
+        self.grapheme_of
+            .get(idx)
+            .copied()
+            .unwrap_or(self.num_graphemes())
+    }
+    fn is_boundary(&self, idx: usize) -> bool {
+        self.boundaries[self.grapheme_at(idx)] == idx
+    }
+    // the char index the grapheme containing idx ends at
+    fn cluster_end(&self, idx: usize) -> usize {
+        self.boundaries[self.grapheme_at(idx) + 1]
+    }
+}
+
+impl NFA {
+    // next_idx, but a wildcard or class at the start of a grapheme takes
+    // the whole grapheme when its first char matches, and can't be taken
+    // in the middle of one. letters still take one char, so a pattern
+    // can spell out the chars of a cluster
+    fn next_grapheme_idx(
+        &self,
+        transition: &Transition,
+        graphemes: &Graphemes,
+        idx: usize,
+    ) -> Option<usize> {
+        match transition.label {
+            TransitionLabel::Wildcard | TransitionLabel::Class(_) => {
+                let takes = idx < graphemes.chars.len()
+                    && graphemes.is_boundary(idx)
+                    && self.consumes(&transition.label, graphemes.chars[idx]);
+                takes.then(|| graphemes.cluster_end(idx))
+            }
+            _ => self.next_idx(transition, &graphemes.chars, idx),
+        }
+    }
+    // the furthest grapheme boundary a match starting at start can end at.
+    // the same BFS as match_ends, over next_grapheme_idx
+    fn grapheme_match_end(&self, graphemes: &Graphemes, start: usize) -> Option<usize> {
+        let mut visited: BTreeSet<(usize, usize)> = BTreeSet::new();
+        let mut queue = VecDeque::from([(start, self.start_id)]);
+        visited.insert((start, self.start_id));
+
+        let mut end = None;
+        while let Some((idx, state_id)) = queue.pop_front() {
+            if self.states[state_id].accepting && graphemes.is_boundary(idx) {
+                end = end.max(Some(idx));
+            }
+            for transition in self.states[state_id].active_transitions() {
+                if let Some(next_idx) = self.next_grapheme_idx(transition, graphemes, idx) {
+                    if visited.insert((next_idx, transition.to)) {
+                        queue.push_back((next_idx, transition.to));
+                    }
+                }
+            }
+        }
+        end
+    }
+    // leftmost-longest match starting at or after grapheme from, as
+    // grapheme indices
+    fn find_grapheme_span(&self, graphemes: &Graphemes, from: usize) -> Option<(usize, usize)> {
+        (from..=graphemes.num_graphemes()).find_map(|grapheme| {
+            let end = self.grapheme_match_end(graphemes, graphemes.boundaries[grapheme])?;
+            Some((grapheme, graphemes.grapheme_at(end)))
+        })
+    }
+    // find, but over grapheme clusters instead of chars: . and classes
+    // match a whole cluster (by its first char), and matches start and end
+    // between clusters, never inside one. the span is in bytes of the
+    // input, so &input[start..end] is the matched text, accents and all
+    pub fn find_graphemes(&self, input: &str) -> Option<(usize, usize)> {
+        if self.has_counters() {
+            return self.expand_counters().find_graphemes(input);
+        }
+        let graphemes = Graphemes::new(input);
+        let (start, end) = self.find_grapheme_span(&graphemes, 0)?;
+        Some((graphemes.offsets[start], graphemes.offsets[end]))
+    }
+    // find_iter over grapheme clusters, spans in bytes like find_graphemes
+    pub fn find_iter_graphemes(&self, input: &str) -> Vec<(usize, usize)> {
+        if self.has_counters() {
+            return self.expand_counters().find_iter_graphemes(input);
+        }
+        let graphemes = Graphemes::new(input);
+        let mut spans = vec![];
+        let mut from = 0;
+        while let Some((start, end)) = self.find_grapheme_span(&graphemes, from) {
+            spans.push((graphemes.offsets[start], graphemes.offsets[end]));
+            from = if end == start { end + 1 } else { end };
+        }
+        spans
+    }
+}
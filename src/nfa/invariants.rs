@@ -0,0 +1,58 @@
+// =================
+// INVARIANTS
+// =================
+
+use super::NFA;
+
+impl NFA {
+    // checks what everything else takes for granted about the states, and
+    // says which one is broken. debug builds check every NFA once it's
+    // built or changed (see update_first_char_set), so a construction bug
+    // panics where it happens instead of as an index out of bounds later on
+    pub(crate) fn validate_invariants(&self) -> Result<(), String> {
+        let num_states = self.states.len();
+        if self.start_id >= num_states {
+            return Err(format!(
+                "the start state {} is not one of the {num_states} states",
+                self.start_id
+            ));
+        }
+        for (id, state) in self.states.iter().enumerate() {
+            for transition in state.active_transitions() {
+                if transition.to >= num_states {
+                    return Err(format!(
+                        "state {id} has a transition to state {}, but there are only {num_states} states",
+                        transition.to
+                    ));
+                }
+            }
+        }
+        for (group, boundary) in self.groups.iter().enumerate() {
+            if boundary.start_id >= num_states || boundary.out_id >= num_states {
+                return Err(format!(
+                    "group {group} starts or ends on a state that doesn't exist"
+                ));
+            }
+            if group + boundary.num_nested >= self.groups.len() {
+                return Err(format!(
+                    "group {group} has {} nested groups, but there are only {} groups after it",
+                    boundary.num_nested,
+                    self.groups.len() - group - 1
+                ));
+            }
+        }
+        // trimming the empty language (see simplify) leaves a lone start
+        // state, anything else needs somewhere to accept
+        let trimmed_empty = num_states == 1 && self.states[0].active_transitions().is_empty();
+        if !trimmed_empty && !self.states.iter().any(|state| state.accepting) {
+            return Err("no state is accepting".to_string());
+        }
+        Ok(())
+    }
+    // points the first transition of the start state somewhere else
+    // without any checks, so tests can build a broken NFA
+    #[cfg(test)]
+    pub(crate) fn redirect_start_transition(&mut self, to: usize) {
+        self.states[self.start_id].transitions[0].to = to;
+    }
+}
@@ -0,0 +1,409 @@
+// =================
+// STREAMING
+// =================
+
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::io::{self, Read};
+
+use super::{Assertion, TransitionLabel, NFA};
+
+// a class with more chars than this (e.g. [^a]) is treated like an
+// unrestricted wildcard when listing what could come next
+const MAX_LISTED_CLASS_CHARS: usize = 256;
+
+// matches input fed to it one char at a time, without needing all of the
+// input up front. instead of a BFS over (idx, state) like is_match, we keep
+// the set of states we could be in after the chars fed so far (closed over
+// epsilon transitions), so each char is only looked at once.
+// an assertion needs to see the char after it, so states waiting on one
+// stay in the active set until the next char (or the end) comes
+#[derive(Clone)]
+pub struct Matcher<'a> {
+    // an NFA with counters is expanded first, the active set has no room
+    // for their counts
+    nfa: Cow<'a, NFA>,
+    active: BTreeSet<usize>,
+    position: usize,
+    // the last char fed, for assertions
+    prev: Option<char>,
+}
+
+// a snapshot of a Matcher to roll back to, e.g. when an editor deletes
+// the char that was just typed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    active: BTreeSet<usize>,
+    position: usize,
+    prev: Option<char>,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(nfa: &'a NFA) -> Matcher<'a> {
+        let nfa = match nfa.has_counters() {
+            true => Cow::Owned(nfa.expand_counters()),
+            false => Cow::Borrowed(nfa),
+        };
+        let mut active = BTreeSet::new();
+        active.insert(nfa.start_id);
+        nfa.epsilon_closure(&mut active);
+        Matcher {
+            nfa,
+            active,
+            position: 0,
+            prev: None,
+        }
+    }
+    pub fn feed(&mut self, c: char) {
+        let mut active = std::mem::take(&mut self.active);
+        self.nfa
+            .closure_where(&mut active, |assertion| assertion.holds(self.prev, Some(c)));
+
+        let mut next = BTreeSet::new();
+        for &state_id in &active {
+            for transition in self.nfa.states[state_id].active_transitions() {
+                if self.nfa.consumes(&transition.label, c) {
+                    next.insert(transition.to);
+                }
+            }
+        }
+        self.nfa.epsilon_closure(&mut next);
+        self.active = next;
+        self.position += 1;
+        self.prev = Some(c);
+    }
+    pub fn feed_str(&mut self, input: &str) {
+        for c in input.chars() {
+            self.feed(c);
+        }
+    }
+    // whether the chars fed so far are accepted
+    pub fn is_match(&self) -> bool {
+        let mut active = self.active.clone();
+        self.nfa
+            .closure_where(&mut active, |assertion| assertion.holds(self.prev, None));
+        active.iter().any(|&id| self.nfa.states[id].accepting)
+    }
+    // true when no more input can lead to a match
+    pub fn is_dead(&self) -> bool {
+        self.active.is_empty()
+    }
+    // whether more input could still make the chars fed so far match:
+    // from where the matcher is, either it accepts or the next char can
+    // lead to a state that reaches an accepting one. stricter than
+    // !is_dead, which still holds on paths that can never finish, like b^c
+    // after "b" since ^ can't hold anymore. a multiline ^ also holds right
+    // after a line terminator, but the char before it here is the b, so
+    // b^c can't match in multiline either. assertions after the next char
+    // are assumed to hold, so a$b can still match after "a"
+    pub fn can_still_match(&self) -> bool {
+        let mut active = self.active.clone();
+        // a ^ only looks at the char before it, which is known
+        self.nfa
+            .closure_where(&mut active, |assertion| match assertion {
+                Assertion::StartText | Assertion::StartLine => assertion.holds(self.prev, None),
+                _ => true,
+            });
+        let live = self.nfa.live_states();
+        active.iter().any(|&state_id| {
+            let state = &self.nfa.states[state_id];
+            state.accepting
+                || state.active_transitions().iter().any(|transition| {
+                    let consumes = matches!(
+                        transition.label,
+                        TransitionLabel::Letter(_)
+                            | TransitionLabel::Wildcard
+                            | TransitionLabel::Class(_)
+                    );
+                    consumes && live[transition.to]
+                })
+        })
+    }
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            active: self.active.clone(),
+            position: self.position,
+            prev: self.prev,
+        }
+    }
+    // goes back to where the matcher was at the checkpoint, without
+    // feeding it everything again. the checkpoint should come from a
+    // matcher for the same NFA
+    pub fn restore(&mut self, checkpoint: Checkpoint) {
+        self.active = checkpoint.active;
+        self.position = checkpoint.position;
+        self.prev = checkpoint.prev;
+    }
+    // number of chars fed so far
+    pub fn position(&self) -> usize {
+        self.position
+    }
+    // the states the chars fed so far can end in
+    pub(super) fn active(&self) -> &BTreeSet<usize> {
+        &self.active
+    }
+    // the last char fed, None at the start
+    pub(super) fn prev(&self) -> Option<char> {
+        self.prev
+    }
+    // the chars the next char could be to keep matching, and whether the
+    // next char could be anything (an unrestricted wildcard, or a class too
+    // big to list like [^a])
+    pub(super) fn next_letters(&self) -> (BTreeSet<char>, bool) {
+        // we don't know the next char yet, so go past every assertion
+        let mut active = self.active.clone();
+        self.nfa.closure_where(&mut active, |_| true);
+        self.nfa.letters_from(&active)
+    }
+    // what the next char could be to keep matching, in words
+    fn expected(&self) -> String {
+        let (letters, any) = self.next_letters();
+        let letters: Vec<String> = letters.iter().map(|c| format!("`{c}`")).collect();
+        match (any, letters.as_slice()) {
+            (true, _) => "any char".to_string(),
+            (false, []) => "nothing".to_string(),
+            (false, [letter]) => letter.clone(),
+            (false, letters) => format!("one of {}", letters.join(", ")),
+        }
+    }
+}
+
+impl NFA {
+    pub fn matcher(&self) -> Matcher<'_> {
+        Matcher::new(self)
+    }
+    // the chars the transitions out of the states consume, and whether
+    // they could consume any char, see Matcher::next_letters
+    fn letters_from(&self, states: &BTreeSet<usize>) -> (BTreeSet<char>, bool) {
+        let mut letters = BTreeSet::new();
+        let mut any = false;
+        for &state_id in states {
+            for transition in self.states[state_id].active_transitions() {
+                match &transition.label {
+                    TransitionLabel::Letter(c) => {
+                        letters.insert(*c);
+                    }
+                    TransitionLabel::Wildcard => match &self.wildcard_set {
+                        Some(set) => letters.extend(set),
+                        None => any = true,
+                    },
+                    TransitionLabel::Class(class) => {
+                        if class.chars().nth(MAX_LISTED_CLASS_CHARS).is_some() {
+                            any = true;
+                        } else {
+                            letters.extend(class.chars());
+                        }
+                    }
+                    TransitionLabel::Epsilon
+                    | TransitionLabel::Assertion(_)
+                    | TransitionLabel::Counter(_) => {}
+                }
+            }
+        }
+        (letters, any)
+    }
+    // the chars every match starts with, for first_char_set. None when
+    // that could be any char, or when there can be an empty match, which
+    // doesn't start with a char at all. counters are gone past like
+    // epsilons, so this works without expanding them, and can only list
+    // more chars than there really are
+    pub(super) fn first_char_filter(&self) -> Option<BTreeSet<char>> {
+        let mut start = BTreeSet::from([self.start_id]);
+        self.consuming_nothing_closure(&mut start);
+        if start
+            .iter()
+            .any(|&state_id| self.states[state_id].accepting)
+        {
+            return None;
+        }
+        let (letters, any) = self.letters_from(&start);
+        (!any).then_some(letters)
+    }
+    // the literal every match starts with, for literal_prefix. empty when
+    // a match can start in more than one way, e.g. ab|ac starts with a but
+    // then goes on with either b or c. like first_char_filter, everything
+    // that consumes nothing is gone past, which can only make it shorter
+    pub(super) fn literal_prefix_filter(&self) -> String {
+        let mut prefix = String::new();
+        let mut states = BTreeSet::from([self.start_id]);
+        // a prefix longer than there are states means a loop of letters
+        // that can't be left, and never accepts
+        for _ in 0..=self.states.len() {
+            self.consuming_nothing_closure(&mut states);
+            if states
+                .iter()
+                .any(|&state_id| self.states[state_id].accepting)
+            {
+                break;
+            }
+            let mut letter = None;
+            let mut next = BTreeSet::new();
+            for &state_id in &states {
+                for transition in self.states[state_id].active_transitions() {
+                    match transition.label {
+                        TransitionLabel::Letter(c) if letter.is_none_or(|letter| letter == c) => {
+                            letter = Some(c);
+                            next.insert(transition.to);
+                        }
+                        TransitionLabel::Letter(_)
+                        | TransitionLabel::Wildcard
+                        | TransitionLabel::Class(_) => return prefix,
+                        _ => {}
+                    }
+                }
+            }
+            match letter {
+                Some(c) => prefix.push(c),
+                None => break,
+            }
+            states = next;
+        }
+        prefix
+    }
+    // adds every state reachable through transitions that consume nothing,
+    // whether or not their assertions hold or counters allow it
+    fn consuming_nothing_closure(&self, states: &mut BTreeSet<usize>) {
+        let mut stack: Vec<usize> = states.iter().copied().collect();
+        while let Some(state_id) = stack.pop() {
+            for transition in self.states[state_id].active_transitions() {
+                let consumes_nothing = matches!(
+                    transition.label,
+                    TransitionLabel::Epsilon
+                        | TransitionLabel::Assertion(_)
+                        | TransitionLabel::Counter(_)
+                );
+                if consumes_nothing && states.insert(transition.to) {
+                    stack.push(transition.to);
+                }
+            }
+        }
+    }
+    // whether the input could be extended into a full match, for
+    // validating a form field while it is typed: for [0-9]{3}-[0-9]{4},
+    // "555-" is a valid prefix and "55a" is not. a full match is a valid
+    // prefix too
+    pub fn is_valid_prefix(&self, input: &str) -> bool {
+        let mut matcher = self.matcher();
+        matcher.feed_str(input);
+        matcher.can_still_match()
+    }
+    // the chars an accepted string can start with, see next_chars
+    pub fn first_chars(&self) -> BTreeSet<char> {
+        self.next_chars("")
+    }
+    // the chars that can come right after the prefix on the way to a
+    // match, for "what can I type next". a wildcard adds its wildcard_set,
+    // but an unrestricted one could be any char so it adds nothing, a
+    // pattern like a. has no next chars after "a"
+    pub fn next_chars(&self, prefix: &str) -> BTreeSet<char> {
+        let mut matcher = self.matcher();
+        matcher.feed_str(prefix);
+        matcher.next_letters().0
+    }
+    // adds every state reachable through only epsilon transitions
+    fn epsilon_closure(&self, states: &mut BTreeSet<usize>) {
+        self.closure_where(states, |_| false);
+    }
+    // adds every state reachable through epsilon transitions and the
+    // assertion transitions follow says to take
+    pub(super) fn closure_where(
+        &self,
+        states: &mut BTreeSet<usize>,
+        follow: impl Fn(&Assertion) -> bool,
+    ) {
+        let mut stack: Vec<usize> = states.iter().copied().collect();
+        while let Some(state_id) = stack.pop() {
+            for transition in self.states[state_id].active_transitions() {
+                let take = match &transition.label {
+                    TransitionLabel::Epsilon => true,
+                    TransitionLabel::Assertion(assertion) => follow(assertion),
+                    _ => false,
+                };
+                if take && states.insert(transition.to) {
+                    stack.push(transition.to);
+                }
+            }
+        }
+    }
+    // where a full match of the input fails: a matcher fed everything
+    // before the char no active path could take, along with that char, or
+    // fed the whole input and None when it ended without a match. None if
+    // the whole input matches
+    fn failure(&self, input: &str) -> Option<(Matcher<'_>, Option<char>)> {
+        let mut matcher = self.matcher();
+        for c in input.chars() {
+            let before = matcher.clone();
+            matcher.feed(c);
+            if matcher.is_dead() {
+                return Some((before, Some(c)));
+            }
+        }
+        (!matcher.is_match()).then_some((matcher, None))
+    }
+    // the char index a full match of the input fails at, where every path
+    // through the NFA has died, or the length of the input if it ends too
+    // soon. None if the whole input matches. abc over abx fails at 2
+    pub fn match_failure_point(&self, input: &str) -> Option<usize> {
+        let (matcher, _) = self.failure(input)?;
+        Some(matcher.position())
+    }
+    // why the input does or doesn't (fully) match, for teaching: how far
+    // into the input the match got, and what it expected to see there, e.g.
+    // "no match at position 2: expected `c` but found `x`"
+    pub fn explain(&self, input: &str) -> String {
+        match self.failure(input) {
+            None => "match".to_string(),
+            Some((matcher, Some(c))) => format!(
+                "no match at position {}: expected {} but found `{c}`",
+                matcher.position(),
+                matcher.expected()
+            ),
+            Some((matcher, None)) => format!(
+                "no match at position {}: expected {} but the input ended",
+                matcher.position(),
+                matcher.expected()
+            ),
+        }
+    }
+    // full match against the UTF-8 text from the reader, streamed through
+    // a Matcher so the whole input is never in memory. stops reading early
+    // once nothing can match. errors if reading fails or the text is not UTF-8
+    pub fn is_match_reader<R: Read>(&self, mut reader: R) -> io::Result<bool> {
+        let mut matcher = self.matcher();
+        let mut buf = [0u8; 4096];
+        // bytes of a char that was split across reads
+        let mut pending: Vec<u8> = vec![];
+
+        loop {
+            let read = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+            pending.extend_from_slice(&buf[..read]);
+
+            let valid_up_to = match std::str::from_utf8(&pending) {
+                Ok(text) => text.len(),
+                // the end is an incomplete char, the rest comes in the next read
+                Err(err) if err.error_len().is_none() => err.valid_up_to(),
+                Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+            };
+            // already validated above
+            matcher.feed_str(std::str::from_utf8(&pending[..valid_up_to]).unwrap());
+            pending.drain(..valid_up_to);
+
+            if matcher.is_dead() {
+                return Ok(false);
+            }
+        }
+
+        if !pending.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "stream ended in the middle of a char",
+            ));
+        }
+        Ok(matcher.is_match())
+    }
+}
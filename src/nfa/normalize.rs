@@ -0,0 +1,240 @@
+// =================
+// NORMALIZATION
+// =================
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::rc::Rc;
+
+use crate::class::CharClass;
+
+use super::{State, TransitionLabel, NFA};
+
+// folds a char for case-insensitive matching: lowercase, except for chars
+// whose folded form is longer, like ß which folds to ss
+pub fn case_fold(c: char) -> String {
+    match c {
+        'ß' | 'ẞ' => "ss".to_string(),
+        _ => c.to_lowercase().collect(),
+    }
+}
+
+// how a piece of text is capitalized, see casing
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Casing {
+    // COLOR
+    Upper,
+    // Color
+    Title,
+}
+
+// Upper when there are at least two cased chars and all are uppercase,
+// Title when only the first one is. None for lowercase, mixed case, or
+// text without cased chars, which is left alone
+fn casing(text: &str) -> Option<Casing> {
+    let cased: Vec<char> = text
+        .chars()
+        .filter(|c| c.is_uppercase() || c.is_lowercase())
+        .collect();
+    match cased.as_slice() {
+        [first, rest @ ..] if first.is_uppercase() => {
+            if !rest.is_empty() && rest.iter().all(|c| c.is_uppercase()) {
+                Some(Casing::Upper)
+            } else if rest.iter().all(|c| c.is_lowercase()) {
+                Some(Casing::Title)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+fn apply_casing(text: &str, casing: Casing) -> String {
+    match casing {
+        Casing::Upper => text.to_uppercase(),
+        Casing::Title => {
+            let mut chars = text.chars();
+            match chars.next() {
+                Some(first) => first
+                    .to_uppercase()
+                    .chain(chars.as_str().to_lowercase().chars())
+                    .collect(),
+                None => String::new(),
+            }
+        }
+    }
+}
+
+// an input after folding every char, and for each folded char the index
+// of the original char it came from
+struct Folded {
+    chars: Vec<char>,
+    origins: Vec<usize>,
+    original_len: usize,
+}
+
+impl Folded {
+    fn new(input: &str, fold: impl Fn(char) -> String) -> Folded {
+        let mut chars = vec![];
+        let mut origins = vec![];
+        let mut original_len = 0;
+        for (idx, c) in input.chars().enumerate() {
+            for folded in fold(c).chars() {
+                chars.push(folded);
+                origins.push(idx);
+            }
+            original_len = idx + 1;
+        }
+        Folded {
+            chars,
+            origins,
+            original_len,
+        }
+    }
+    // turns a span of folded chars into a span of original chars. a span
+    // that starts or ends in the middle of one char's folded form (e.g. one
+    // s of the ss from ß) is widened to the whole original char
+    fn original_span(&self, (start, end): (usize, usize)) -> (usize, usize) {
+        let to_original = |idx: usize| self.origins.get(idx).copied().unwrap_or(self.original_len);
+        let original_start = to_original(start);
+        let original_end = if end == start {
+            original_start
+        } else {
+            self.origins[end - 1] + 1
+        };
+        (original_start, original_end)
+    }
+}
+
+// the chars fold changes, grouped by what they fold to. found by folding
+// every char there is, which takes a while, so see FoldGroups::of
+struct FoldGroups {
+    fold: fn(char) -> String,
+    groups: BTreeMap<String, Vec<char>>,
+}
+
+thread_local! {
+    // the groups of every fold compiled with so far, by the address of the
+    // fold fn, so compiling with the same fold again doesn't fold every
+    // char again
+    static FOLD_GROUPS: RefCell<BTreeMap<usize, Rc<FoldGroups>>> =
+        const { RefCell::new(BTreeMap::new()) };
+}
+
+impl FoldGroups {
+    fn of(fold: fn(char) -> String) -> Rc<FoldGroups> {
+        FOLD_GROUPS.with(|cache| {
+            cache
+                .borrow_mut()
+                .entry(fold as usize)
+                .or_insert_with(|| Rc::new(FoldGroups::new(fold)))
+                .clone()
+        })
+    }
+    fn new(fold: fn(char) -> String) -> FoldGroups {
+        let mut groups: BTreeMap<String, Vec<char>> = BTreeMap::new();
+        for c in '\0'..=char::MAX {
+            let folded = fold(c);
+            if !folded.chars().eq([c]) {
+                groups.entry(folded).or_default().push(c);
+            }
+        }
+        FoldGroups { fold, groups }
+    }
+    // every char that folds to key: its group, and the char key is made
+    // of if fold leaves that one alone
+    fn folding_to<'a>(&'a self, key: &'a str) -> impl Iterator<Item = char> + 'a {
+        let mut key_chars = key.chars();
+        let key_char = match (key_chars.next(), key_chars.next()) {
+            (Some(c), None) if (self.fold)(c) == key => Some(c),
+            _ => None,
+        };
+        self.groups
+            .get(key)
+            .into_iter()
+            .flatten()
+            .copied()
+            .chain(key_char)
+    }
+    // the label, but also taking every char that folds the same as one it
+    // takes. a letter with nothing to fold with stays a letter
+    fn fold_label(&self, label: &TransitionLabel) -> TransitionLabel {
+        match label {
+            TransitionLabel::Letter(c) => {
+                let key = (self.fold)(*c);
+                let chars: BTreeSet<char> = self.folding_to(&key).chain([*c]).collect();
+                if chars.len() == 1 {
+                    return label.clone();
+                }
+                TransitionLabel::Class(CharClass::new(chars.into_iter().map(|c| (c, c)).collect()))
+            }
+            TransitionLabel::Class(class) => {
+                // only the chars fold changes can add anything, so look at
+                // the groups rather than every char of the class
+                let mut ranges = class.ranges().to_vec();
+                for key in self.groups.keys() {
+                    let chars: Vec<char> = self.folding_to(key).collect();
+                    if chars.iter().any(|&c| class.contains(c)) {
+                        ranges.extend(chars.iter().map(|&c| (c, c)));
+                    }
+                }
+                TransitionLabel::Class(CharClass::new(ranges))
+            }
+            _ => label.clone(),
+        }
+    }
+}
+
+// bakes fold into the states, see CompileOptions::fold: every letter and
+// class also takes the chars that fold the same as the ones it takes, so
+// matching needs no folding. only whole chars fold together, so with
+// case_fold the ß (which folds to ss) still only matches ß and ẞ
+pub(super) fn bake_fold(states: &mut [State], fold: fn(char) -> String) {
+    let groups = FoldGroups::of(fold);
+    for state in states {
+        for transition in &mut state.transitions {
+            transition.label = groups.fold_label(&transition.label);
+        }
+    }
+}
+
+impl NFA {
+    // like find, but matches against the input with fold applied to every
+    // char (e.g. case_fold, or stripping accents) while the span still
+    // points into the original input, in chars. fold may change how many
+    // chars there are, so the pattern should be written in folded form
+    pub fn find_folded(
+        &self,
+        input: &str,
+        fold: impl Fn(char) -> String,
+    ) -> Option<(usize, usize)> {
+        let folded = Folded::new(input, fold);
+        let span = self.find_from_chars(&folded.chars, 0)?;
+        Some(folded.original_span(span))
+    }
+    // like find_iter, but with fold applied to the input, see find_folded
+    pub fn find_iter_folded(
+        &self,
+        input: &str,
+        fold: impl Fn(char) -> String,
+    ) -> Vec<(usize, usize)> {
+        let folded = Folded::new(input, fold);
+        self.find_iter_chars(&folded.chars)
+            .into_iter()
+            .map(|span| folded.original_span(span))
+            .collect()
+    }
+    // like replace_all, but a match in uppercase or title case gets the
+    // replacement in the same case, so replacing color with colour turns
+    // Color into Colour and COLOR into COLOUR. other matches get the
+    // replacement as is. the matching itself is not changed, so the
+    // pattern has to match each casing, e.g. [cC]olor|COLOR
+    pub fn replace_all_preserving_case(&self, input: &str, replacement: &str) -> String {
+        let spans = self.find_iter(input);
+        self.replace_spans(input, spans, |matched| match casing(matched) {
+            Some(casing) => apply_casing(replacement, casing),
+            None => replacement.to_string(),
+        })
+    }
+}
@@ -0,0 +1,455 @@
+// =================
+// PROGRAM
+// =================
+
+use crate::class::CharClass;
+
+use super::{Assertion, Captures, MatchKind, MatchStats, TransitionLabel, NFA};
+
+// one instruction of a program. the ones that consume a char or check an
+// assertion fall through to the next instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Inst {
+    Char(char),
+    Any,
+    // a class, or a wildcard restricted to the wildcard set
+    Class(CharClass),
+    Assert(Assertion),
+    // records where the input is in a capture slot, two slots per group
+    // for where it starts and ends
+    Save(usize),
+    // try both, the first one first
+    Split(usize, usize),
+    Jmp(usize),
+    Match,
+    // a dead end, for states with no way out that aren't accepting
+    Fail,
+}
+
+// the NFA flattened into a list of instructions run by a thread-list VM,
+// so matching walks one contiguous vec rather than states and their
+// transitions. each state becomes a block of Splits over its transitions
+// in order, each transition being its label followed by a Jmp to the block
+// of the state it goes to, and a Match at the end if it accepts. a state
+// where groups start or end saves into their slots first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    insts: Vec<Inst>,
+    start: usize,
+    slots: usize,
+    // the start of a lazy .* loop in front of start, for programs that
+    // find matches anywhere in the input, see to_unanchored_program
+    unanchored_start: Option<usize>,
+    match_kind: MatchKind,
+}
+
+// the pcs the VM is at, in the order they were added, with a flag per pc
+// so each one is only added once per step
+struct Threads {
+    pcs: Vec<usize>,
+    on: Vec<bool>,
+}
+
+impl Threads {
+    fn new(len: usize) -> Threads {
+        Threads {
+            pcs: vec![],
+            on: vec![false; len],
+        }
+    }
+    fn clear(&mut self) {
+        for &pc in &self.pcs {
+            self.on[pc] = false;
+        }
+        self.pcs.clear();
+    }
+}
+
+// Threads, with something carried along by the thread at each pc (tags[i]
+// is for pcs[i]), like its capture slots or where its match started
+struct TaggedThreads<T> {
+    threads: Threads,
+    tags: Vec<T>,
+}
+
+impl<T> TaggedThreads<T> {
+    fn new(len: usize) -> TaggedThreads<T> {
+        TaggedThreads {
+            threads: Threads::new(len),
+            tags: vec![],
+        }
+    }
+    fn clear(&mut self) {
+        self.threads.clear();
+        self.tags.clear();
+    }
+    // drops every thread from the len-th on
+    fn truncate(&mut self, len: usize) {
+        for &pc in &self.threads.pcs[len..] {
+            self.threads.on[pc] = false;
+        }
+        self.threads.pcs.truncate(len);
+        self.tags.truncate(len);
+    }
+}
+
+impl NFA {
+    // the NFA as a program for the VM. counted loops are expanded first
+    pub fn to_program(&self) -> Program {
+        if self.has_counters() {
+            return self.expand_counters().to_program();
+        }
+
+        // the slots each state saves into, from the groups that start or
+        // end on it
+        let mut saves = vec![vec![]; self.states.len()];
+        for (group, boundary) in self.groups.iter().enumerate() {
+            saves[boundary.start_id].push(2 * group);
+            saves[boundary.out_id].push(2 * group + 1);
+        }
+
+        // how many instructions each transition and each state takes up
+        let transition_len = |label: &TransitionLabel| match label {
+            TransitionLabel::Epsilon => 1,
+            _ => 2,
+        };
+        let state_len: Vec<usize> = self
+            .states
+            .iter()
+            .zip(&saves)
+            .map(|(state, saves)| {
+                let alternatives = state.active_transitions().len() + state.accepting as usize;
+                let body: usize = state
+                    .active_transitions()
+                    .iter()
+                    .map(|transition| transition_len(&transition.label))
+                    .sum::<usize>()
+                    + state.accepting as usize;
+                saves.len() + alternatives.saturating_sub(1) + body.max(1)
+            })
+            .collect();
+        let mut offsets = Vec::with_capacity(self.states.len());
+        let mut len = 0;
+        for state_len in &state_len {
+            offsets.push(len);
+            len += state_len;
+        }
+
+        let wildcard = self
+            .wildcard_set
+            .as_ref()
+            .map(|set| Inst::Class(CharClass::new(set.iter().map(|&c| (c, c)).collect())));
+        let mut insts = Vec::with_capacity(len);
+        for ((state, &base), saves) in self.states.iter().zip(&offsets).zip(&saves) {
+            insts.extend(saves.iter().map(|&slot| Inst::Save(slot)));
+            let base = base + saves.len();
+            let alternatives = state.active_transitions().len() + state.accepting as usize;
+            if alternatives == 0 {
+                insts.push(Inst::Fail);
+                continue;
+            }
+
+            // where each alternative starts, after the splits choosing them
+            let mut starts = Vec::with_capacity(alternatives);
+            let mut pc = base + alternatives - 1;
+            for transition in state.active_transitions() {
+                starts.push(pc);
+                pc += transition_len(&transition.label);
+            }
+            starts.push(pc);
+
+            for i in 0..alternatives - 1 {
+                let rest = if i + 2 == alternatives {
+                    starts[i + 1]
+                } else {
+                    base + i + 1
+                };
+                insts.push(Inst::Split(starts[i], rest));
+            }
+            for transition in state.active_transitions() {
+                match &transition.label {
+                    TransitionLabel::Letter(c) => insts.push(Inst::Char(*c)),
+                    TransitionLabel::Wildcard => {
+                        insts.push(wildcard.clone().unwrap_or(Inst::Any));
+                    }
+                    TransitionLabel::Class(class) => insts.push(Inst::Class(class.clone())),
+                    TransitionLabel::Assertion(assertion) => {
+                        insts.push(Inst::Assert(*assertion));
+                    }
+                    TransitionLabel::Epsilon | TransitionLabel::Counter(_) => {}
+                }
+                insts.push(Inst::Jmp(offsets[transition.to]));
+            }
+            if state.accepting {
+                insts.push(Inst::Match);
+            }
+        }
+
+        Program {
+            insts,
+            start: offsets[self.start_id],
+            slots: 2 * self.groups.len(),
+            unanchored_start: None,
+            match_kind: self.match_kind,
+        }
+    }
+    // to_program with a lazy .* in front, so Program::find finds a match
+    // anywhere in the input in one pass instead of starting over at every
+    // position. is_match and captures skip it and still match the whole
+    // input
+    pub fn to_unanchored_program(&self) -> Program {
+        let mut program = self.to_program();
+        // loop: try the pattern first, else skip a char and loop again
+        let prefix = program.insts.len();
+        program.insts.extend([
+            Inst::Split(program.start, prefix + 1),
+            Inst::Any,
+            Inst::Jmp(prefix),
+        ]);
+        program.unanchored_start = Some(prefix);
+        program
+    }
+}
+
+impl Program {
+    // how many instructions the program has
+    pub fn len(&self) -> usize {
+        self.insts.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.insts.is_empty()
+    }
+    // whether the program has the .* in front from to_unanchored_program
+    pub fn is_anchored(&self) -> bool {
+        self.unanchored_start.is_none()
+    }
+    // whether the whole input matches, same as NFA::is_match
+    pub fn is_match(&self, input: &str) -> bool {
+        let mut current = TaggedThreads::new(self.insts.len());
+        let mut next = TaggedThreads::new(self.insts.len());
+        let mut chars = input.chars().peekable();
+        self.add(&mut current, self.start, (), None, chars.peek().copied());
+
+        while let Some(c) = chars.next() {
+            if current.threads.pcs.is_empty() {
+                return false;
+            }
+            let after = chars.peek().copied();
+            for &pc in &current.threads.pcs {
+                if self.consumes(pc, c) {
+                    self.add(&mut next, pc + 1, (), Some(c), after);
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+
+        current
+            .threads
+            .pcs
+            .iter()
+            .any(|&pc| self.insts[pc] == Inst::Match)
+    }
+    // the span of the leftmost match, the longest one unless the match
+    // kind is LeftmostFirst, same as NFA::find. an unanchored program
+    // finds it in a single pass: each thread knows where its match
+    // started, and the threads from the .* in front, which start later,
+    // always come after the others. an anchored program only finds a
+    // match that starts at 0
+    pub fn find(&self, input: &str) -> Option<(usize, usize)> {
+        let mut current = TaggedThreads::new(self.insts.len());
+        let mut next = TaggedThreads::new(self.insts.len());
+        let mut chars = input.chars().peekable();
+        let seed = self.unanchored_start.unwrap_or(self.start);
+        self.add(&mut current, seed, 0, None, chars.peek().copied());
+
+        let mut found = None;
+        let mut idx = 0;
+        loop {
+            if let Some(i) = current
+                .threads
+                .pcs
+                .iter()
+                .position(|&pc| self.insts[pc] == Inst::Match)
+            {
+                // threads are in order of where they started, so the first
+                // to accept started leftmost. LeftmostFirst drops the threads
+                // after it, which could only be worse, and LeftmostLongest
+                // those that started later
+                let start = current.tags[i];
+                found = Some((start, idx));
+                let keep = match self.match_kind {
+                    MatchKind::LeftmostFirst => i + 1,
+                    MatchKind::LeftmostLongest => {
+                        // the .* is always the last thread, and would go on
+                        // to start later matches
+                        let keep = current.tags.partition_point(|&s| s <= start);
+                        let prefix = self.unanchored_start.map(|prefix| prefix + 1);
+                        keep - (current.threads.pcs[..keep].last().copied() == prefix) as usize
+                    }
+                };
+                current.truncate(keep);
+            }
+            let Some(c) = chars.next() else { break };
+            if current.threads.pcs.is_empty() {
+                break;
+            }
+            let after = chars.peek().copied();
+            for (&pc, &start) in current.threads.pcs.iter().zip(&current.tags) {
+                if self.consumes(pc, c) {
+                    // the .* skipping a char moves where a match would start
+                    let start = if Some(pc) == self.unanchored_start.map(|prefix| prefix + 1) {
+                        idx + 1
+                    } else {
+                        start
+                    };
+                    self.add(&mut next, pc + 1, start, Some(c), after);
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+            idx += 1;
+        }
+        found
+    }
+    // whether the instruction at pc consumes c
+    fn consumes(&self, pc: usize, c: char) -> bool {
+        match &self.insts[pc] {
+            Inst::Char(letter) => *letter == c,
+            Inst::Any => true,
+            Inst::Class(class) => class.contains(c),
+            _ => false,
+        }
+    }
+    // adds pc and everything reachable from it without consuming a char,
+    // at the position between before and after, all tagged with tag
+    fn add<T: Clone>(
+        &self,
+        threads: &mut TaggedThreads<T>,
+        pc: usize,
+        tag: T,
+        before: Option<char>,
+        after: Option<char>,
+    ) {
+        let mut stack = vec![pc];
+        while let Some(pc) = stack.pop() {
+            if threads.threads.on[pc] {
+                continue;
+            }
+            threads.threads.on[pc] = true;
+            threads.threads.pcs.push(pc);
+            threads.tags.push(tag.clone());
+            match &self.insts[pc] {
+                Inst::Jmp(to) => stack.push(*to),
+                Inst::Save(_) => stack.push(pc + 1),
+                Inst::Split(first, second) => {
+                    stack.push(*second);
+                    stack.push(*first);
+                }
+                Inst::Assert(assertion) if assertion.holds(before, after) => stack.push(pc + 1),
+                _ => {}
+            }
+        }
+    }
+    // the span of each group when the whole input matches, same as
+    // NFA::captures. every thread carries its own slots and the threads
+    // are stepped in priority order, so the one that matches first is the
+    // one a backtracking engine would find, without ever backtracking
+    pub fn captures(&self, input: &str) -> Option<Captures> {
+        self.captures_with_stats(input).0
+    }
+    // captures, along with counters like NFA::match_with_stats: the threads
+    // stepped (one per pc and position), the most threads at once, and the
+    // positions the threads were added at. each pc has a thread at most
+    // once per position, so the threads grow linearly with the input
+    pub fn captures_with_stats(&self, input: &str) -> (Option<Captures>, MatchStats) {
+        let mut stats = MatchStats::default();
+        let mut count = |threads: &TaggedThreads<Vec<Option<usize>>>| {
+            stats.states_dequeued += threads.threads.pcs.len();
+            stats.max_frontier_size = stats.max_frontier_size.max(threads.threads.pcs.len());
+            stats.closures_computed += 1;
+        };
+        let mut current = TaggedThreads::new(self.insts.len());
+        let mut next = TaggedThreads::new(self.insts.len());
+        let mut chars = input.chars().peekable();
+        let first = vec![None; self.slots];
+        self.add_thread(
+            &mut current,
+            self.start,
+            first,
+            0,
+            None,
+            chars.peek().copied(),
+        );
+        count(&current);
+
+        let mut idx = 0;
+        while let Some(c) = chars.next() {
+            if current.threads.pcs.is_empty() {
+                return (None, stats);
+            }
+            idx += 1;
+            let after = chars.peek().copied();
+            for (&pc, slots) in current.threads.pcs.iter().zip(&current.tags) {
+                if self.consumes(pc, c) {
+                    self.add_thread(&mut next, pc + 1, slots.clone(), idx, Some(c), after);
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+            count(&current);
+        }
+
+        let matched = current
+            .threads
+            .pcs
+            .iter()
+            .position(|&pc| self.insts[pc] == Inst::Match);
+        let captures = matched.map(|matched| {
+            current.tags[matched]
+                .chunks(2)
+                .map(|slots| match (slots[0], slots[1]) {
+                    (Some(start), Some(end)) => Some((start, end)),
+                    _ => None,
+                })
+                .collect()
+        });
+        (captures, stats)
+    }
+    // add, but each pc gets the slots of the path that reached it first,
+    // with the Saves on the way done at idx
+    fn add_thread(
+        &self,
+        threads: &mut TaggedThreads<Vec<Option<usize>>>,
+        pc: usize,
+        slots: Vec<Option<usize>>,
+        idx: usize,
+        before: Option<char>,
+        after: Option<char>,
+    ) {
+        let mut stack = vec![(pc, slots)];
+        while let Some((pc, mut slots)) = stack.pop() {
+            if threads.threads.on[pc] {
+                continue;
+            }
+            threads.threads.on[pc] = true;
+            threads.threads.pcs.push(pc);
+            match &self.insts[pc] {
+                Inst::Jmp(to) => stack.push((*to, slots.clone())),
+                Inst::Save(slot) => {
+                    slots[*slot] = Some(idx);
+                    stack.push((pc + 1, slots.clone()));
+                }
+                Inst::Split(first, second) => {
+                    stack.push((*second, slots.clone()));
+                    stack.push((*first, slots.clone()));
+                }
+                Inst::Assert(assertion) if assertion.holds(before, after) => {
+                    stack.push((pc + 1, slots.clone()));
+                }
+                _ => {}
+            }
+            threads.tags.push(slots);
+        }
+    }
+}
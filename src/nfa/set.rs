@@ -0,0 +1,75 @@
+// =================
+// SETS
+// =================
+
+use crate::error::RegexError;
+
+use super::{Compiled, MatchKind, State, Transition, TransitionLabel, NFA};
+
+// many patterns compiled into one automaton, so an input is matched against
+// all of them in a single pass instead of once per pattern. a new start
+// state has an epsilon to each pattern's start, and every state remembers
+// which pattern it belongs to so accepting states can say what matched
+pub struct RegexSet {
+    nfa: NFA,
+    // the index of the pattern each state came from
+    patterns: Vec<Option<usize>>,
+    num_patterns: usize,
+}
+
+impl RegexSet {
+    pub fn new(patterns: &[&str]) -> Result<RegexSet, RegexError> {
+        let mut states = vec![State::new()];
+        let mut state_patterns = vec![None];
+        for (pattern_id, pattern) in patterns.iter().enumerate() {
+            let nfa = NFA::from_regex(pattern)?;
+            let offset = states.len();
+            states[0].add_transition(Transition {
+                label: TransitionLabel::Epsilon,
+                to: nfa.start_id + offset,
+            });
+            for mut state in nfa.states {
+                for transition in &mut state.transitions {
+                    transition.to += offset;
+                }
+                states.push(state);
+                state_patterns.push(Some(pattern_id));
+            }
+        }
+
+        let mut nfa = NFA {
+            start_id: 0,
+            states,
+            groups: vec![],
+            wildcard_set: None,
+            first_char_set: None,
+            literal_prefix: String::new(),
+            match_kind: MatchKind::LeftmostLongest,
+            compiled: Compiled::default(),
+        };
+        nfa.update_first_char_set();
+        Ok(RegexSet {
+            nfa,
+            patterns: state_patterns,
+            num_patterns: patterns.len(),
+        })
+    }
+    // the indices of every pattern the whole input matches, in order
+    pub fn matching(&self, input: &str) -> Vec<usize> {
+        let mut matcher = self.nfa.matcher();
+        matcher.feed_str(input);
+
+        // one bit per pattern, set for those with an accepting state active
+        let mut matched = vec![0u64; self.num_patterns.div_ceil(64)];
+        for &state_id in matcher.active() {
+            if let (true, Some(pattern_id)) =
+                (self.nfa.states[state_id].accepting, self.patterns[state_id])
+            {
+                matched[pattern_id / 64] |= 1 << (pattern_id % 64);
+            }
+        }
+        (0..self.num_patterns)
+            .filter(|&pattern_id| matched[pattern_id / 64] & (1 << (pattern_id % 64)) != 0)
+            .collect()
+    }
+}
@@ -0,0 +1,261 @@
+// =================
+// SIMPLIFYING
+// =================
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::hash::{Hash, Hasher};
+
+use super::{State, Transition, TransitionLabel, NFA};
+
+impl NFA {
+    // the states reachable from state_id over epsilons (itself included),
+    // in the order the transitions were added so priorities are kept
+    fn ordered_epsilon_closure(&self, state_id: usize) -> Vec<usize> {
+        let mut closure = vec![];
+        let mut seen = BTreeSet::new();
+        let mut stack = vec![state_id];
+        while let Some(id) = stack.pop() {
+            if !seen.insert(id) {
+                continue;
+            }
+            closure.push(id);
+            for transition in self.states[id].active_transitions().iter().rev() {
+                if transition.label == TransitionLabel::Epsilon {
+                    stack.push(transition.to);
+                }
+            }
+        }
+        closure
+    }
+    pub fn is_epsilon_free(&self) -> bool {
+        !self.has_counters()
+            && self.states.iter().all(|state| {
+                state
+                    .active_transitions()
+                    .iter()
+                    .all(|transition| transition.label != TransitionLabel::Epsilon)
+            })
+    }
+    // epsilon elimination: every state takes over the consuming transitions
+    // (and accepting) of the states it reaches over epsilons, then the
+    // states nothing consumes into anymore are dropped. the language stays
+    // the same, but there are no epsilon transitions left and usually far
+    // fewer states. the group boundaries don't survive this, so afterwards
+    // the NFA has no groups to capture or patch
+    pub fn remove_epsilon_states(&mut self) {
+        // counters consume nothing either, so they go too
+        if self.has_counters() {
+            *self = self.expand_counters();
+        }
+        let states: Vec<State> = (0..self.states.len())
+            .map(|id| {
+                let mut state = State::new();
+                for closure_id in self.ordered_epsilon_closure(id) {
+                    let closure_state = &self.states[closure_id];
+                    if closure_state.accepting {
+                        state.set_accepting(true);
+                    }
+                    for transition in closure_state.active_transitions() {
+                        if transition.label != TransitionLabel::Epsilon
+                            && !state.active_transitions().contains(transition)
+                        {
+                            state.add_transition(transition.clone());
+                        }
+                    }
+                }
+                state
+            })
+            .collect();
+
+        self.states = states;
+        self.keep_reachable(|_| true);
+        self.update_first_char_set();
+    }
+    // keeps the states reachable from the start that keep says to keep,
+    // renumbered in the order a BFS finds them so the start becomes state 0.
+    // transitions into dropped states are dropped too. the start is always
+    // kept, and the group boundaries never are since their ids are stale
+    fn keep_reachable(&mut self, keep: impl Fn(usize) -> bool) {
+        let mut new_ids: BTreeMap<usize, usize> = BTreeMap::new();
+        let mut order = vec![];
+        let mut queue = VecDeque::from([self.start_id]);
+        new_ids.insert(self.start_id, 0);
+        while let Some(id) = queue.pop_front() {
+            order.push(id);
+            for transition in self.states[id].active_transitions() {
+                if keep(transition.to) && !new_ids.contains_key(&transition.to) {
+                    new_ids.insert(transition.to, new_ids.len());
+                    queue.push_back(transition.to);
+                }
+            }
+        }
+
+        self.states = order
+            .into_iter()
+            .map(|id| {
+                let mut state = State::new();
+                state.set_accepting(self.states[id].accepting);
+                for transition in self.states[id].active_transitions() {
+                    if let Some(&to) = new_ids.get(&transition.to) {
+                        state.add_transition(Transition {
+                            label: transition.label.clone(),
+                            to,
+                        });
+                    }
+                }
+                state
+            })
+            .collect();
+        self.start_id = 0;
+        self.groups.clear();
+    }
+    // drops the states that can't be reached from the start or can't
+    // reach an accepting state, neither can be part of a match. wildcards
+    // that match nothing are dropped first, they can never be taken
+    fn prune(&mut self) {
+        if self.wildcard_set.as_ref().is_some_and(|set| set.is_empty()) {
+            for state in &mut self.states {
+                state
+                    .transitions
+                    .retain(|transition| transition.label != TransitionLabel::Wildcard);
+            }
+        }
+
+        let live = self.live_states();
+        self.keep_reachable(|id| live[id]);
+    }
+    // merges bisimilar states, which accept the same strings for the same
+    // reasons: both accepting or not, and for every transition one of them
+    // has, the other has one with the same label to a state bisimilar to
+    // where it goes. found by partition refinement, starting from accepting
+    // and non-accepting states and splitting blocks whose states go to
+    // different blocks, until no block splits. unlike determinizing, this
+    // can only shrink the NFA. epsilons and assertions are labels like any
+    // other, and like remove_epsilon_states the NFA has no groups afterwards
+    pub fn merge_bisimilar_states(&mut self) {
+        let num_states = self.states.len();
+        let mut blocks: Vec<usize> = self.states.iter().map(|s| s.accepting as usize).collect();
+        let mut num_blocks = blocks.iter().collect::<BTreeSet<_>>().len();
+        loop {
+            // a state's block and where its transitions go decide its next
+            // block, so blocks only ever split
+            let mut signatures: BTreeMap<(usize, Vec<(TransitionLabel, usize)>), usize> =
+                BTreeMap::new();
+            let refined: Vec<usize> = (0..num_states)
+                .map(|id| {
+                    let mut moves: Vec<(TransitionLabel, usize)> = self.states[id]
+                        .active_transitions()
+                        .iter()
+                        .map(|transition| (transition.label.clone(), blocks[transition.to]))
+                        .collect();
+                    moves.sort();
+                    moves.dedup();
+                    let next_block = signatures.len();
+                    *signatures.entry((blocks[id], moves)).or_insert(next_block)
+                })
+                .collect();
+            blocks = refined;
+            if signatures.len() == num_blocks {
+                break;
+            }
+            num_blocks = signatures.len();
+        }
+
+        // every state is merged into the first state of its block
+        let mut firsts: BTreeMap<usize, usize> = BTreeMap::new();
+        let merged_into: Vec<usize> = (0..num_states)
+            .map(|id| *firsts.entry(blocks[id]).or_insert(id))
+            .collect();
+        for state in &mut self.states {
+            let mut transitions: Vec<Transition> = vec![];
+            for mut transition in state.transitions.drain(..) {
+                transition.to = merged_into[transition.to];
+                if !transitions.contains(&transition) {
+                    transitions.push(transition);
+                }
+            }
+            state.transitions = transitions;
+        }
+        self.start_id = merged_into[self.start_id];
+        self.keep_reachable(|id| merged_into[id] == id);
+        self.update_first_char_set();
+    }
+    // drops transitions another transition of the same state already
+    // covers: duplicates, and letters that a wildcard to the same state
+    // matches anyway
+    fn coalesce_transitions(&mut self) {
+        let wildcard_set = &self.wildcard_set;
+        for state in &mut self.states {
+            let wildcard_targets: BTreeSet<usize> = state
+                .active_transitions()
+                .iter()
+                .filter(|transition| transition.label == TransitionLabel::Wildcard)
+                .map(|transition| transition.to)
+                .collect();
+            let mut kept: Vec<Transition> = vec![];
+            for transition in state.transitions.drain(..) {
+                let covered = match transition.label {
+                    TransitionLabel::Letter(c) => {
+                        wildcard_targets.contains(&transition.to)
+                            && wildcard_set.as_ref().is_none_or(|set| set.contains(&c))
+                    }
+                    _ => false,
+                };
+                if !covered && !kept.contains(&transition) {
+                    kept.push(transition);
+                }
+            }
+            state.transitions = kept;
+        }
+    }
+    // shrinks the NFA as much as it can without changing its language:
+    // prunes states that can't be part of a match, removes epsilons (see
+    // remove_epsilon_states), merges bisimilar states, then coalesces
+    // the parallel transitions that leaves. worth it
+    // before serializing or matching many inputs. like
+    // remove_epsilon_states, the NFA has no groups afterwards
+    pub fn simplify(&mut self) {
+        self.prune();
+        self.remove_epsilon_states();
+        self.merge_bisimilar_states();
+        self.coalesce_transitions();
+        self.update_first_char_set();
+    }
+    // a hash of the simplified NFA, with its states numbered in the order
+    // a breadth first search from the start reaches them and each state's
+    // transitions sorted by label. patterns that simplify to the same
+    // automaton hash the same however they were written, like a|b and b|a,
+    // or aa* and a+, so compiled patterns can be deduplicated by it. equal
+    // hashes don't promise equal languages, nor do equal languages promise
+    // equal hashes, is_equivalent is the exact check. the hash is only
+    // stable within one build
+    pub fn canonical_hash(&self) -> u64 {
+        let mut nfa = self.clone();
+        nfa.simplify();
+
+        let mut hasher = DefaultHasher::new();
+        nfa.wildcard_set.hash(&mut hasher);
+        let mut ids = BTreeMap::from([(nfa.start_id, 0)]);
+        let mut order = vec![nfa.start_id];
+        let mut i = 0;
+        while i < order.len() {
+            let state = &nfa.states[order[i]];
+            let mut transitions: Vec<&Transition> = state.active_transitions().iter().collect();
+            transitions.sort_by(|a, b| a.label.cmp(&b.label));
+            state.accepting.hash(&mut hasher);
+            transitions.len().hash(&mut hasher);
+            for transition in transitions {
+                let next_id = ids.len();
+                let id = *ids.entry(transition.to).or_insert_with(|| {
+                    order.push(transition.to);
+                    next_id
+                });
+                transition.label.hash(&mut hasher);
+                id.hash(&mut hasher);
+            }
+            i += 1;
+        }
+        hasher.finish()
+    }
+}
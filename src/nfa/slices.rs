@@ -0,0 +1,83 @@
+// =================
+// MATCH SLICES
+// =================
+
+use std::ops::Range;
+
+use super::{byte_offsets, NFA};
+
+// a match borrowed from the input it was found in, so looking at the
+// matched text doesn't allocate. start and end are char indices like every
+// other span, the text is a slice of the input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match<'a> {
+    input: &'a str,
+    start: usize,
+    end: usize,
+    // where the match is in the input's bytes
+    byte_start: usize,
+    byte_end: usize,
+}
+
+impl<'a> Match<'a> {
+    // the match of chars start..end, offsets being byte_offsets(input)
+    fn new(input: &'a str, offsets: &[usize], (start, end): (usize, usize)) -> Match<'a> {
+        Match {
+            input,
+            start,
+            end,
+            byte_start: offsets[start],
+            byte_end: offsets[end],
+        }
+    }
+    pub fn start(&self) -> usize {
+        self.start
+    }
+    pub fn end(&self) -> usize {
+        self.end
+    }
+    // how many chars the match has
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+    // the bytes of the input the match covers, for slicing it yourself
+    pub fn byte_range(&self) -> Range<usize> {
+        self.byte_start..self.byte_end
+    }
+    // the matched text, borrowed from the input rather than the Match so
+    // it can outlive it
+    pub fn as_str(&self) -> &'a str {
+        &self.input[self.byte_range()]
+    }
+}
+
+impl NFA {
+    // find, but the match borrows its text from the input
+    pub fn find_match<'a>(&self, input: &'a str) -> Option<Match<'a>> {
+        let span = self.find(input)?;
+        Some(Match::new(input, &byte_offsets(input), span))
+    }
+    // find_iter, but each match borrows its text from the input
+    pub fn find_iter_matches<'a>(&self, input: &'a str) -> Vec<Match<'a>> {
+        let offsets = byte_offsets(input);
+        self.find_iter(input)
+            .into_iter()
+            .map(|span| Match::new(input, &offsets, span))
+            .collect()
+    }
+    // captures, but each group that took part in the match borrows its
+    // text from the input
+    pub fn captures_matches<'a>(&self, input: &'a str) -> Option<Vec<Option<Match<'a>>>> {
+        let offsets = byte_offsets(input);
+        let captures = self.captures(input)?;
+        Some(
+            captures
+                .into_iter()
+                .map(|span| Some(Match::new(input, &offsets, span?)))
+                .collect(),
+        )
+    }
+}
@@ -0,0 +1,129 @@
+// =================
+// SYMBOLS
+// =================
+
+// automata over symbols other than chars. an NFA's states and transitions
+// are generic over what a transition reads, and a SymbolNFA<S> is those
+// same states with a Label<S> on each transition, matched by the same
+// breadth first search as NFA::is_match (see breadth_first), so there's
+// one matching algorithm for both. the NFA itself stays over chars: its
+// labels are classes, word and line assertions, counted loops and a
+// wildcard set, which all need to know what a char is, and the parser and
+// everything built on it (captures, the DFA, the VM) read those labels
+
+use super::{breadth_first, State, Transition, TransitionLabel, NFA};
+
+// anything an automaton can read one of at a time. regexes read chars, but
+// the same matching works for token streams, integers or enums, all that's
+// needed is telling two symbols apart
+pub trait Symbol: Eq + Clone {}
+
+impl<T: Eq + Clone> Symbol for T {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Label<S: Symbol> {
+    // consumes exactly this symbol
+    Exact(S),
+    // consumes any one symbol
+    Any,
+    // consumes nothing
+    Epsilon,
+}
+
+// an automaton over any symbol type, built by hand state by state since
+// there's no syntax for it. transitions are tried in the order they were
+// added
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolNFA<S: Symbol> {
+    start_id: usize,
+    states: Vec<State<Label<S>>>,
+}
+
+impl<S: Symbol> Default for SymbolNFA<S> {
+    fn default() -> Self {
+        SymbolNFA::new()
+    }
+}
+
+impl<S: Symbol> SymbolNFA<S> {
+    // a lone start state that doesn't accept, so nothing matches yet
+    pub fn new() -> SymbolNFA<S> {
+        let mut nfa = SymbolNFA {
+            start_id: 0,
+            states: vec![],
+        };
+        nfa.add_state();
+        nfa
+    }
+    // adds a state that doesn't accept, and returns its id
+    pub fn add_state(&mut self) -> usize {
+        self.states.push(State::new());
+        self.states.len() - 1
+    }
+    pub fn start_id(&self) -> usize {
+        self.start_id
+    }
+    pub fn num_states(&self) -> usize {
+        self.states.len()
+    }
+    // panics if either state doesn't exist, like indexing out of bounds
+    pub fn add_transition(&mut self, from: usize, label: Label<S>, to: usize) {
+        assert!(to < self.states.len(), "state {to} doesn't exist");
+        self.states[from].add_transition(Transition { label, to });
+    }
+    pub fn set_accepting(&mut self, state_id: usize, accepting: bool) {
+        self.states[state_id].set_accepting(accepting);
+    }
+    // whether the whole input matches, the same search as NFA::is_match
+    // with symbols in place of chars
+    pub fn is_match(&self, input: &[S]) -> bool {
+        let mut matched = false;
+        breadth_first(
+            &self.states,
+            (0, self.start_id, ()),
+            |idx, state_id| {
+                matched = idx >= input.len() && self.states[state_id].accepting;
+                !matched
+            },
+            |&(idx, _, ()), transition| {
+                let next_idx = match &transition.label {
+                    Label::Epsilon => Some(idx),
+                    Label::Any => (idx < input.len()).then_some(idx + 1),
+                    Label::Exact(symbol) => (input.get(idx) == Some(symbol)).then_some(idx + 1),
+                };
+                next_idx.map(|next_idx| (next_idx, ()))
+            },
+        );
+        matched
+    }
+}
+
+impl NFA {
+    // the same automaton over chars as a SymbolNFA, or None when a
+    // transition needs more than a single symbol to decide on: classes,
+    // assertions, counted loops and wildcards limited to a set
+    pub fn to_symbol_nfa(&self) -> Option<SymbolNFA<char>> {
+        let mut nfa = SymbolNFA {
+            start_id: self.start_id,
+            states: vec![],
+        };
+        for state in &self.states {
+            let mut symbol_state = State::new();
+            for transition in state.active_transitions() {
+                let label = match &transition.label {
+                    TransitionLabel::Letter(c) => Label::Exact(*c),
+                    TransitionLabel::Wildcard if self.wildcard_set.is_none() => Label::Any,
+                    TransitionLabel::Epsilon => Label::Epsilon,
+                    _ => return None,
+                };
+                symbol_state.add_transition(Transition {
+                    label,
+                    to: transition.to,
+                });
+            }
+            symbol_state.set_accepting(state.accepting);
+            nfa.states.push(symbol_state);
+        }
+        Some(nfa)
+    }
+}
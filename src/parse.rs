@@ -2,21 +2,48 @@
 // PARSING
 // =================
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+use crate::class::{parse_class, CharClass};
+use crate::error::RegexError;
+use crate::unicode::parse_property;
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Token {
     Letter(char),
     Wildcard,
+    // [...], any one char of the class
+    Class(CharClass),
     OpenParenthesis,
+    // (?: or (?>, a group that doesn't capture. (?> is an atomic group,
+    // which without backtracking is the same as any other group
+    NonCapturingParenthesis,
     CloseParenthesis,
     Concatenation,
     Union,
     KleeneQuantifier,
     PositiveQuantifier,
     OptionalQuantifier,
+    // ^ and $, which match the start and end of the input
+    StartAnchor,
+    EndAnchor,
+    // {min}, {min,} or {min,max}
+    RepetitionQuantifier(usize, Option<usize>),
+    // a ? right after a quantifier (*?, +?, ??, {min,max}?), which makes
+    // it lazy: it prefers to repeat as few times as it can. comes right
+    // after its quantifier in the postfix too
+    Lazy,
+    // only in the postfix, marks that the fragment on top of the stack
+    // is the group with the given index (counting open parentheses from 0)
+    Group(usize),
+    // an empty operand of a union, which matches the empty string. only
+    // there with CompileOptions::empty_alternatives, see
+    // insert_empty_operands
+    Empty,
 }
 
 #[allow(dead_code)]
 #[derive(PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 enum Associativity {
     Left,
     Right,
@@ -32,79 +59,321 @@ impl Token {
         (Token::Union, 1, Associativity::Left),
     ];
     fn precedence(&self) -> (u8, Associativity) {
+        if let Token::RepetitionQuantifier(_, _) = self {
+            return (3, Associativity::Left);
+        }
         for (token, score, associativity) in Self::PRECEDENCES {
             if *self == token {
                 return (score, associativity);
             }
         }
-        return (4, Associativity::Left);
+        (4, Associativity::Left)
+    }
+    fn is_quantifier(&self) -> bool {
+        matches!(
+            self,
+            Token::KleeneQuantifier
+                | Token::PositiveQuantifier
+                | Token::OptionalQuantifier
+                | Token::RepetitionQuantifier(_, _)
+        )
     }
-    fn has_greater_precedence(&self, other: Token) -> bool {
+    fn is_open_parenthesis(&self) -> bool {
+        matches!(
+            self,
+            Token::OpenParenthesis | Token::NonCapturingParenthesis
+        )
+    }
+    fn has_greater_precedence(&self, other: &Token) -> bool {
         let (precedence, _) = self.precedence();
         let (other_precedence, other_associativity) = other.precedence();
-        return (precedence > other_precedence)
-            | (precedence == other_precedence && other_associativity == Associativity::Left);
+        (precedence > other_precedence)
+            | (precedence == other_precedence && other_associativity == Associativity::Left)
     }
 }
 
 // const RESERVED = ['\\', '(', ')', '|', '*', '.', '?'];
 const NONGROUPING_OPERATORS: [char; 4] = ['|', '*', '?', '+'];
-const TWO_OPERAND_OPERATORS: [char; 1] = ['|'];
 
-pub fn parse_re_to_tokens(re: &String) -> Vec<Token> {
-    let mut tokens: Vec<Token> = vec![];
+// where a token came from in the regex, as a range of char indices.
+// implicit concatenations take up no chars, so their span is empty
+pub type Span = (usize, usize);
+
+pub fn parse_re_to_tokens(re: &str, max_depth: usize) -> Result<Vec<Token>, RegexError> {
+    Ok(parse_re_to_spanned_tokens(re, max_depth)?
+        .into_iter()
+        .map(|(token, _)| token)
+        .collect())
+}
+
+// the regex without the whitespace and comments that don't matter in
+// verbose mode, so patterns can be spread over lines: unescaped whitespace
+// is dropped, and an unescaped # starts a comment up to the end of the
+// line. inside [...] classes nothing is dropped, so [ #] still matches a
+// space or a #, and so does an escaped \  or \#
+pub fn strip_verbose(re: &str) -> String {
+    let mut stripped = String::with_capacity(re.len());
+    // how many classes are open
+    let mut class_depth: usize = 0;
+    let mut chars = re.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                stripped.push(c);
+                stripped.extend(chars.next());
+                continue;
+            }
+            '[' => class_depth += 1,
+            ']' => class_depth = class_depth.saturating_sub(1),
+            _ if class_depth > 0 => {}
+            '#' => {
+                chars.find(|&c| c == '\n');
+                continue;
+            }
+            c if c.is_whitespace() => continue,
+            _ => {}
+        }
+        stripped.push(c);
+    }
+    stripped
+}
+
+// parses a {min}, {min,} or {min,max} quantifier at the start of chars,
+// returning it and how many chars it takes up. anything else starting
+// with { is not a quantifier, so the { is just a literal
+fn parse_repetition(chars: &[char]) -> Option<(Token, usize)> {
+    let close = chars.iter().position(|&c| c == '}')?;
+    let inside: String = chars[1..close].iter().collect();
+    let is_number = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+
+    let (min, max) = match inside.split_once(',') {
+        None => (inside.as_str(), Some(inside.as_str())),
+        Some((min, "")) => (min, None),
+        Some((min, max)) => (min, Some(max)),
+    };
+    if !is_number(min) || !max.is_none_or(is_number) {
+        return None;
+    }
+    let min = min.parse().ok()?;
+    let max = match max {
+        Some(max) => Some(max.parse().ok()?),
+        None => None,
+    };
+    Some((Token::RepetitionQuantifier(min, max), close + 1))
+}
+
+// parses an octal escape at the start of chars, the chars right after a
+// \, returning the char and how many digits it takes up. \0 takes up to
+// two more octal digits (\0 is NUL, \012 a newline), and otherwise it
+// takes exactly three, from \100 to \377 (\101 is A). \1 or \12 would
+// be backreferences elsewhere, which can't be matched without
+// backtracking, so they stay the escaped digits they always were
+pub(crate) fn parse_octal(chars: &[char]) -> Option<(char, usize)> {
+    let is_octal = |c: &char| ('0'..='7').contains(c);
+    let len = match chars.first()? {
+        '0' => {
+            1 + chars[1..]
+                .iter()
+                .take(2)
+                .take_while(|c| is_octal(c))
+                .count()
+        }
+        '1'..='3' if chars.len() >= 3 && chars[1..3].iter().all(is_octal) => 3,
+        _ => return None,
+    };
+    let digits: String = chars[..len].iter().collect();
+    let code = u32::from_str_radix(&digits, 8).ok()?;
+    Some((char::from_u32(code)?, len))
+}
+
+// fails only on a malformed [...] class, or groups and classes nested
+// more than max_depth deep, anything else is checked when calculating the
+// postfix. the depth is checked here since nested classes are parsed by
+// recursing into them, and the derived Debug of an Ast recurses too
+pub fn parse_re_to_spanned_tokens(
+    re: &str,
+    max_depth: usize,
+) -> Result<Vec<(Token, Span)>, RegexError> {
+    let mut tokens: Vec<(Token, Span)> = vec![];
+    // how many groups are open
+    let mut depth: usize = 0;
 
     let mut escaped = false;
+    // whether the last char was an unescaped *, + or ? quantifier
+    let mut after_quantifier = false;
+    // whether the last token was a quantifier that a ? can make lazy
+    let mut can_be_lazy = false;
     let chars: Vec<char> = re.chars().collect();
-    for i in 0..chars.len() {
+    let mut i = 0;
+    while i < chars.len() {
+        // a + right after *, + or ? makes it possessive (a*+, a++, a?+),
+        // which means it never gives back what it matched when
+        // backtracking. there's no backtracking here so it is parsed as the
+        // plain greedy quantifier, which is NOT the same as PCRE for patterns
+        // like a*+a (never matches there, matches "aa" here)
+        let can_be_possessive = std::mem::take(&mut after_quantifier);
+        if can_be_possessive && !escaped && chars[i] == '+' {
+            if let Some((_, span)) = tokens.last_mut() {
+                span.1 = i + 1;
+            }
+            can_be_lazy = false;
+            i += 1;
+            continue;
+        }
+        if std::mem::take(&mut can_be_lazy) && !escaped && chars[i] == '?' {
+            tokens.push((Token::Lazy, (i, i + 1)));
+            i += 1;
+            continue;
+        }
+
+        let repetition = match (chars[i], escaped) {
+            ('{', false) => parse_repetition(&chars[i..]),
+            _ => None,
+        };
+
         // add implicit concat if no operators between characters,
         // ignore if escaped since it would get handled once before
         // also do not add after two operand operators and before
-        // other operators
-        if i > 0
-            && !escaped
-            && !TWO_OPERAND_OPERATORS.contains(&chars[i - 1])
+        // other operators. what came before is checked by token, not char,
+        // so an escaped \| or \( is a letter like any other
+        if !escaped
+            && !matches!(tokens.last(), None | Some((Token::Union, _)))
+            && !tokens
+                .last()
+                .is_some_and(|(token, _)| token.is_open_parenthesis())
             && !NONGROUPING_OPERATORS.contains(&chars[i])
-            && chars[i - 1] != '('
+            && repetition.is_none()
             && chars[i] != ')'
         {
-            tokens.push(Token::Concatenation);
+            tokens.push((Token::Concatenation, (i, i)));
+        }
+
+        if let Some((token, len)) = repetition {
+            tokens.push((token, (i, i + len)));
+            can_be_lazy = true;
+            i += len;
+            continue;
+        }
+
+        if (chars[i], escaped) == ('[', false) {
+            let (class, len) = parse_class(&chars[i..], max_depth - depth)?;
+            // e.g. [a&&b], which can never match
+            if class.is_empty() {
+                return Err(RegexError::Empty);
+            }
+            tokens.push((Token::Class(class), (i, i + len)));
+            i += len;
+            continue;
+        }
+
+        if escaped {
+            if let Some((c, len)) = parse_octal(&chars[i..]) {
+                tokens.push((Token::Letter(c), (i - 1, i + len)));
+                escaped = false;
+                i += len;
+                continue;
+            }
+            if let Some(property) = parse_property(&chars[i..]) {
+                let (class, len) = property?;
+                // \p{Cs}, since surrogates aren't chars
+                if class.is_empty() {
+                    return Err(RegexError::Empty);
+                }
+                tokens.push((Token::Class(class), (i - 1, i + len)));
+                escaped = false;
+                i += len;
+                continue;
+            }
         }
 
+        // an escaped char's span includes the backslash
+        let span = if escaped { (i - 1, i + 1) } else { (i, i + 1) };
         match (chars[i], escaped) {
             ('\\', false) => {
                 escaped = true;
             }
             ('(', false) => {
-                tokens.push(Token::OpenParenthesis);
+                depth += 1;
+                if depth > max_depth {
+                    return Err(RegexError::TooDeep);
+                }
+                if chars[i + 1..].starts_with(&['?', '>'])
+                    || chars[i + 1..].starts_with(&['?', ':'])
+                {
+                    tokens.push((Token::NonCapturingParenthesis, (i, i + 3)));
+                    i += 2;
+                } else {
+                    tokens.push((Token::OpenParenthesis, span));
+                }
             }
             (')', false) => {
-                tokens.push(Token::CloseParenthesis);
+                // an unmatched ) is caught when calculating the postfix
+                depth = depth.saturating_sub(1);
+                tokens.push((Token::CloseParenthesis, span));
             }
             ('|', false) => {
-                tokens.push(Token::Union);
+                tokens.push((Token::Union, span));
             }
             ('*', false) => {
-                tokens.push(Token::KleeneQuantifier);
+                tokens.push((Token::KleeneQuantifier, span));
+                after_quantifier = true;
+                can_be_lazy = true;
             }
             ('?', false) => {
-                tokens.push(Token::OptionalQuantifier);
+                tokens.push((Token::OptionalQuantifier, span));
+                after_quantifier = true;
+                can_be_lazy = true;
             }
             ('+', false) => {
-                tokens.push(Token::PositiveQuantifier);
+                tokens.push((Token::PositiveQuantifier, span));
+                after_quantifier = true;
+                can_be_lazy = true;
             }
             ('.', false) => {
-                tokens.push(Token::Wildcard);
+                tokens.push((Token::Wildcard, span));
                 escaped = false;
             }
+            ('^', false) => {
+                tokens.push((Token::StartAnchor, span));
+            }
+            ('$', false) => {
+                tokens.push((Token::EndAnchor, span));
+            }
             (c, _) => {
-                tokens.push(Token::Letter(c));
+                tokens.push((Token::Letter(c), span));
                 escaped = false;
             }
         }
+        i += 1;
     }
 
-    tokens
+    Ok(tokens)
+}
+
+// puts a Token::Empty wherever a union is missing an operand, so |abc is
+// the empty string or abc and (a|) is a or the empty string. a | is
+// missing its left operand at the start of the regex or a group or after
+// another |, and its right one at the end of them or before another |
+pub fn insert_empty_operands(tokens: Vec<Token>) -> Vec<Token> {
+    let mut inserted = Vec::with_capacity(tokens.len());
+    let mut tokens = tokens.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        let after_operand = !matches!(inserted.last(), None | Some(Token::Union))
+            && !inserted.last().is_some_and(Token::is_open_parenthesis);
+        if token == Token::Union && !after_operand {
+            inserted.push(Token::Empty);
+        }
+        let before_operand = !matches!(
+            tokens.peek(),
+            None | Some(Token::Union) | Some(Token::CloseParenthesis)
+        );
+        let missing_right = token == Token::Union && !before_operand;
+        inserted.push(token);
+        if missing_right {
+            inserted.push(Token::Empty);
+        }
+    }
+    inserted
 }
 
 fn str_count_diff(op: &Token) -> i32 {
@@ -112,6 +381,9 @@ fn str_count_diff(op: &Token) -> i32 {
         // increases count
         Token::Letter(_) => 1,
         Token::Wildcard => 1,
+        Token::Class(_) => 1,
+        Token::StartAnchor | Token::EndAnchor => 1,
+        Token::Empty => 1,
         Token::CloseParenthesis => 1, // should be 1 valid string if inside of () is regex
         // consumes 2, produces one
         Token::Concatenation => -1,
@@ -120,96 +392,219 @@ fn str_count_diff(op: &Token) -> i32 {
         Token::KleeneQuantifier => 0,
         Token::PositiveQuantifier => 0,
         Token::OptionalQuantifier => 0,
+        Token::RepetitionQuantifier(_, _) => 0,
+        Token::Lazy => 0,
         Token::OpenParenthesis => 0,
+        Token::NonCapturingParenthesis => 0,
+        Token::Group(_) => 0,
     }
 }
 
 // Modified Shunting Yard Algorithm
-// TODO: Validate regex
-pub fn calc_postfix(tokens: Vec<Token>) -> Option<Vec<Token>> {
-    let mut operators = vec![];
-    let mut postfix: Vec<Token> = vec![];
+pub fn calc_postfix(tokens: Vec<Token>) -> Result<Vec<Token>, RegexError> {
+    let tokens = tokens.into_iter().map(|token| (token, (0, 0))).collect();
+    let postfix = calc_spanned_postfix(tokens)?;
+    Ok(postfix.into_iter().map(|(token, _)| token).collect())
+}
+
+// calc_postfix, but each token keeps its span. a group marker's span covers
+// the whole group, parentheses included
+pub fn calc_spanned_postfix(tokens: Vec<(Token, Span)>) -> Result<Vec<(Token, Span)>, RegexError> {
+    let mut operators: Vec<(Token, Span)> = vec![];
+    let mut postfix: Vec<(Token, Span)> = vec![];
 
     let mut num_strs: i32 = 0;
     let mut preservation_stack: Vec<i32> = vec![];
 
-    for token in tokens.iter() {
+    // groups are numbered in the order of their open parenthesis
+    let mut num_groups: usize = 0;
+    // None for a group that doesn't capture
+    let mut group_stack: Vec<Option<(usize, Span)>> = vec![];
+
+    for (i, (token, span)) in tokens.iter().cloned().enumerate() {
+        let prev = if i > 0 { tokens.get(i - 1) } else { None }.map(|(prev, _)| prev);
         match token {
-            Token::OpenParenthesis => {
+            Token::OpenParenthesis | Token::NonCapturingParenthesis => {
                 // we need to perserve the num of strs before the parentheses
                 // to validate the larger regex and reset count to validate
                 // the regex inside of the parentheses
                 preservation_stack.push(num_strs);
                 num_strs = 0;
 
-                operators.push(Token::OpenParenthesis);
+                if token == Token::OpenParenthesis {
+                    group_stack.push(Some((num_groups, span)));
+                    num_groups += 1;
+                } else {
+                    group_stack.push(None);
+                }
+
+                operators.push((token, span));
             }
             Token::CloseParenthesis => {
                 // nothing to close, malformed parentheses group
-                if preservation_stack.len() == 0 {
-                    return None;
+                if preservation_stack.is_empty() {
+                    return Err(RegexError::UnmatchedParenthesis);
                 }
 
-                while operators.len() > 0 && *operators.last().unwrap() != Token::OpenParenthesis {
+                while !operators.is_empty() && !operators.last().unwrap().0.is_open_parenthesis() {
                     let op = operators.pop().unwrap();
+                    num_strs += str_count_diff(&op.0);
                     postfix.push(op);
-                    num_strs += str_count_diff(&op);
                 }
 
                 // a regex should only result in one string
+                if prev.is_some_and(|prev| prev.is_open_parenthesis()) {
+                    return Err(RegexError::Empty);
+                }
                 if num_strs != 1 {
-                    return None;
+                    return Err(RegexError::MissingOperand);
                 }
 
                 // pop off open parenthesis
                 operators.pop();
 
+                // mark the group so the NFA can record where it is, unless
+                // it doesn't capture
+                if let Some(Some((group, open_span))) = group_stack.pop() {
+                    postfix.push((Token::Group(group), (open_span.0, span.1)));
+                }
+
                 // we need to restore the prev string count
                 if let Some(s) = preservation_stack.pop() {
                     num_strs = s;
                 }
-                num_strs += str_count_diff(token);
+                num_strs += str_count_diff(&token);
+            }
+            // goes right after its quantifier, which is the only operator
+            // that can be on top of the stack (there are no operands
+            // between them) and binds tighter than anything that follows
+            Token::Lazy => {
+                if !prev.is_some_and(|prev| prev.is_quantifier()) {
+                    return Err(RegexError::MissingOperand);
+                }
+                let quantifier = operators.pop().unwrap();
+                num_strs += str_count_diff(&quantifier.0);
+                postfix.push(quantifier);
+                postfix.push((token, span));
             }
             // operators
             Token::Union
             | Token::Concatenation
             | Token::KleeneQuantifier
             | Token::OptionalQuantifier
-            | Token::PositiveQuantifier => {
+            | Token::PositiveQuantifier
+            | Token::RepetitionQuantifier(_, _) => {
                 // these operators require at least one str before them
                 if num_strs <= 0 {
-                    return None;
+                    return Err(RegexError::MissingOperand);
+                }
+
+                if let Token::RepetitionQuantifier(min, Some(max)) = token {
+                    if min > max {
+                        return Err(RegexError::InvalidRepetition);
+                    }
                 }
 
-                while operators.len() > 0
-                    && *operators.last().unwrap() != Token::OpenParenthesis
-                    && operators.last().unwrap().has_greater_precedence(*token)
+                // a quantifier on a quantifier (a**, a+*, a*?*) is redundant
+                // at best, a quantified group like (a*)* is fine since prev
+                // is the )
+                let after_quantifier =
+                    prev.is_some_and(|prev| prev.is_quantifier() || *prev == Token::Lazy);
+                if token.is_quantifier() && after_quantifier {
+                    return Err(RegexError::NestedQuantifier);
+                }
+
+                while !operators.is_empty()
+                    && !operators.last().unwrap().0.is_open_parenthesis()
+                    && operators.last().unwrap().0.has_greater_precedence(&token)
                 {
                     let op = operators.pop().unwrap();
+                    num_strs += str_count_diff(&op.0);
                     postfix.push(op);
-                    num_strs += str_count_diff(&op);
                 }
-                operators.push(*token);
+                operators.push((token, span));
             }
-            // char matches
-            Token::Letter(_) | Token::Wildcard => {
+            // group markers are only produced by this function
+            Token::Group(_) => unreachable!(),
+            // char matches, and anchors which match no chars
+            Token::Letter(_)
+            | Token::Wildcard
+            | Token::Class(_)
+            | Token::StartAnchor
+            | Token::EndAnchor
+            | Token::Empty => {
                 // for letters and wildcards it should increment by 1
-                num_strs += str_count_diff(token);
-                postfix.push(token.clone());
+                num_strs += str_count_diff(&token);
+                postfix.push((token, span));
             }
         }
     }
 
-    while operators.len() > 0 {
-        let op = operators.pop().unwrap();
+    while let Some(op) = operators.pop() {
+        num_strs += str_count_diff(&op.0);
         postfix.push(op);
-        num_strs += str_count_diff(&op);
     }
 
     // a regex should only result in one string and no malformed parenthesis should work
-    if preservation_stack.len() > 0 || num_strs != 1 {
-        return None;
+    if !preservation_stack.is_empty() {
+        return Err(RegexError::UnclosedParenthesis);
+    }
+    if tokens.is_empty() {
+        return Err(RegexError::Empty);
+    }
+    if num_strs != 1 {
+        return Err(RegexError::MissingOperand);
     }
 
-    Some(postfix)
+    Ok(postfix)
+}
+
+// checks a postfix that didn't come from calc_postfix, so building an NFA
+// from it can't panic: every operator needs its operands, exactly one
+// regex has to be left at the end, and the groups have to be numbered
+// 0, 1, 2, ... with each number marked once
+pub fn validate_postfix(postfix: &[Token]) -> Result<(), RegexError> {
+    if postfix.is_empty() {
+        return Err(RegexError::Empty);
+    }
+    let mut num_strs: i32 = 0;
+    let mut groups: Vec<usize> = vec![];
+    for (i, token) in postfix.iter().enumerate() {
+        match token {
+            Token::OpenParenthesis | Token::NonCapturingParenthesis | Token::CloseParenthesis => {
+                return Err(RegexError::InvalidPostfix);
+            }
+            Token::RepetitionQuantifier(min, Some(max)) if min > max => {
+                return Err(RegexError::InvalidRepetition);
+            }
+            Token::Group(n) => groups.push(*n),
+            // only a quantifier can be lazy
+            Token::Lazy if i == 0 || !postfix[i - 1].is_quantifier() => {
+                return Err(RegexError::InvalidPostfix);
+            }
+            _ => {}
+        }
+        // an operator's operands have to be there before it
+        let needed = match token {
+            Token::Concatenation | Token::Union => 2,
+            Token::KleeneQuantifier
+            | Token::PositiveQuantifier
+            | Token::OptionalQuantifier
+            | Token::RepetitionQuantifier(_, _)
+            | Token::Group(_) => 1,
+            _ => 0,
+        };
+        if num_strs < needed {
+            return Err(RegexError::MissingOperand);
+        }
+        num_strs += str_count_diff(token);
+    }
+    if num_strs != 1 {
+        return Err(RegexError::InvalidPostfix);
+    }
+    groups.sort_unstable();
+    if groups.iter().enumerate().any(|(i, &n)| i != n) {
+        return Err(RegexError::InvalidPostfix);
+    }
+    Ok(())
 }
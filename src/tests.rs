@@ -1,3 +1,5 @@
+use std::collections::BTreeSet;
+
 use super::*;
 
 #[test]
@@ -168,9 +170,9 @@ fn valid_regex_test() {
     ];
     for (re, cases) in valid_cases {
         println!("re: {}", re);
-        if let Some(nfa) = nfa::NFA::from_regex(&re.to_string()) {
+        if let Ok(nfa) = nfa::NFA::from_regex(re) {
             for (input, expected) in cases {
-                let result = nfa.is_match(&input.to_string());
+                let result = nfa.is_match(input);
                 if result != expected {
                     println!("re {re}, case: {input}, result: {result}, expected: {expected}");
                 }
@@ -209,8 +211,4051 @@ fn invalid_regex_test() {
     ];
     for re in invalid_cases {
         println!("re: {}", re);
-        if let Some(_) = nfa::NFA::from_regex(&re.to_string()) {
+        if nfa::NFA::from_regex(re).is_ok() {
             panic!("re {re} expected to be invalid, but NFA returned");
         }
     }
 }
+
+#[test]
+fn custom_wildcard_set_test() {
+    let options = nfa::CompileOptions {
+        wildcard_set: Some(['A', 'C', 'G', 'T'].into_iter().collect()),
+        ..Default::default()
+    };
+    let cases = [
+        (
+            "A.G",
+            vec![
+                ("AAG", true),
+                ("ACG", true),
+                ("AGG", true),
+                ("ATG", true),
+                ("AXG", false),
+                ("AaG", false),
+                ("AG", false),
+            ],
+        ),
+        (
+            ".*",
+            vec![
+                ("", true),
+                ("GATTACA", true),
+                ("GATXACA", false),
+                ("gattaca", false),
+            ],
+        ),
+    ];
+    for (re, cases) in cases {
+        let nfa = nfa::NFA::from_regex_with_options(re, &options).unwrap();
+        for (input, expected) in cases {
+            assert_eq!(nfa.is_match(input), expected, "re {re}, case: {input}");
+        }
+    }
+
+    // literals are not restricted by the wildcard set
+    let nfa = nfa::NFA::from_regex_with_options("X.", &options).unwrap();
+    assert!(nfa.is_match("XA"));
+    assert!(!nfa.is_match("XX"));
+}
+
+#[test]
+fn patch_group_test() {
+    // (original, group, replacement, fully recompiled equivalent)
+    let cases = [
+        ("a(b|c)d", 0, "x+", "a(x+)d"),
+        ("(ab)*c", 0, "d", "(d)*c"),
+        ("(ab)", 0, "c|d", "(c|d)"),
+        ("x(a(b)c)+y", 1, "q?r", "x(a(q?r)c)+y"),
+        ("x(a(b)c)+y", 0, "(m)(n)", "x((m)(n))+y"),
+        ("(a)|(b)|(c)", 1, "(d)e", "(a)|((d)e)|(c)"),
+    ];
+    let inputs = [
+        "",
+        "a",
+        "ab",
+        "abd",
+        "acd",
+        "axd",
+        "axxd",
+        "ad",
+        "c",
+        "dc",
+        "ddc",
+        "ababc",
+        "abab",
+        "cd",
+        "d",
+        "xaqrcy",
+        "xarcaqrcy",
+        "xabcy",
+        "xy",
+        "xmny",
+        "xmnmny",
+        "xmy",
+        "b",
+        "de",
+        "e",
+    ];
+    for (re, group, replacement, expected_re) in cases {
+        let mut patched = nfa::NFA::from_regex(re).unwrap();
+        assert!(patched.patch_group(group, replacement).is_ok());
+        let expected = nfa::NFA::from_regex(expected_re).unwrap();
+        for input in inputs {
+            assert_eq!(
+                patched.is_match(input),
+                expected.is_match(input),
+                "re {re}, patched group {group} with {replacement}, case: {input}"
+            );
+        }
+    }
+
+    // group numbers shift after patching in nested groups
+    let mut patched = nfa::NFA::from_regex("(a)(b)").unwrap();
+    assert!(patched.patch_group(0, "(c)(d)").is_ok());
+    assert!(patched.patch_group(3, "e").is_ok());
+    assert!(patched.is_match("cde"));
+    assert!(!patched.is_match("cdb"));
+
+    // patching a group that shares its start and out with the outer group
+    // keeps the outer group patchable
+    let mut patched = nfa::NFA::from_regex("x((a))y").unwrap();
+    assert!(patched.patch_group(1, "b").is_ok());
+    assert!(patched.is_match("xby"));
+    assert!(patched.patch_group(0, "c").is_ok());
+    assert!(patched.is_match("xcy"));
+    assert!(!patched.is_match("xby"));
+
+    // no such group, or an invalid replacement
+    let mut nfa = nfa::NFA::from_regex("a(b)").unwrap();
+    assert!(nfa.patch_group(1, "c").is_err());
+    assert!(nfa.patch_group(0, "c|").is_err());
+    assert!(nfa.is_match("ab"));
+}
+
+#[test]
+fn patch_group_options_test() {
+    // the patched group is compiled with the options the NFA was, so it
+    // matches the same as compiling the whole regex with them
+    let cases = [
+        (
+            nfa::CompileOptions {
+                fold: Some(nfa::case_fold),
+                ..Default::default()
+            },
+            "x(a)y",
+            "b",
+            "x(b)y",
+            ["XBY", "xby", "xBy", "XAY", "xy"],
+        ),
+        (
+            nfa::CompileOptions {
+                multiline: true,
+                ..Default::default()
+            },
+            "(a)",
+            "^b",
+            "(^b)",
+            ["a\nb", "b", "ab", "a\n", ""],
+        ),
+        (
+            nfa::CompileOptions {
+                dot_matches_newline: false,
+                ..Default::default()
+            },
+            "(a)",
+            ".",
+            "(.)",
+            ["\n", "a", "\r", "", "ab"],
+        ),
+        (
+            nfa::CompileOptions {
+                verbose: true,
+                ..Default::default()
+            },
+            "x(a)y",
+            " b  c # comment",
+            "x(bc)y",
+            ["xbcy", "xb cy", "xay", "xy", "xbc"],
+        ),
+    ];
+    for (options, re, replacement, expected_re, inputs) in cases {
+        let mut patched = nfa::NFA::from_regex_with_options(re, &options).unwrap();
+        assert!(patched.patch_group(0, replacement).is_ok());
+        let expected = nfa::NFA::from_regex_with_options(expected_re, &options).unwrap();
+        for input in inputs {
+            assert_eq!(
+                patched.find(input),
+                expected.find(input),
+                "re {re}, patched with {replacement}, case: {input:?}"
+            );
+        }
+    }
+
+    // patching in a lazy quantifier makes find pick the first match, and
+    // patching the last one out picks the longest again
+    let mut patched = nfa::NFA::from_regex("(b)").unwrap();
+    assert!(patched.patch_group(0, "a*?").is_ok());
+    assert_eq!(patched.find("aab"), Some((0, 0)));
+    assert_eq!(
+        patched.find("aab"),
+        nfa::NFA::from_regex("(a*?)").unwrap().find("aab")
+    );
+    let mut patched = nfa::NFA::from_regex("a|ab|(x*?)").unwrap();
+    assert_eq!(patched.find("ab"), Some((0, 1)));
+    assert!(patched.patch_group(0, "x").is_ok());
+    assert_eq!(patched.find("ab"), Some((0, 2)));
+    let mut patched = nfa::NFA::from_regex("a|ab|((x*?)(y*?))").unwrap();
+    assert!(patched.patch_group(1, "x").is_ok());
+    assert_eq!(patched.find("ab"), Some((0, 1)));
+    assert!(patched.patch_group(0, "y").is_ok());
+    assert_eq!(patched.find("ab"), Some((0, 2)));
+
+    // the same through the wasm bindings
+    let mut regex = Regex::newVerbose("x ( a ) y".to_string()).unwrap();
+    assert!(regex.patchGroup(0, " b c ".to_string()));
+    assert!(regex.isMatch("xbcy".to_string()));
+    assert!(!regex.isMatch("xb cy".to_string()));
+}
+
+#[test]
+fn clone_and_structural_eq_test() {
+    let nfa = nfa::NFA::from_regex("a(bb)+|c").unwrap();
+    let mut cloned = nfa.clone();
+    assert_eq!(nfa, cloned);
+    assert_eq!(nfa, nfa::NFA::from_regex("a(bb)+|c").unwrap());
+
+    // same language, different structure
+    assert_ne!(
+        nfa::NFA::from_regex("a|b").unwrap(),
+        nfa::NFA::from_regex("b|a").unwrap()
+    );
+
+    // the clone is independent of the original
+    assert!(cloned.patch_group(0, "d").is_ok());
+    assert_ne!(nfa, cloned);
+    assert!(cloned.is_match("ad"));
+    assert!(!cloned.is_match("abb"));
+    assert!(nfa.is_match("abb"));
+    assert!(!nfa.is_match("ad"));
+}
+
+#[test]
+fn find_overlapping_test() {
+    let cases = [
+        ("aa", "aaa", vec![(0, 2), (1, 3)]),
+        ("a+", "aa", vec![(0, 1), (0, 2), (1, 2)]),
+        ("ab|b", "abab", vec![(0, 2), (1, 2), (2, 4), (3, 4)]),
+        ("a?", "b", vec![(0, 0), (1, 1)]),
+        ("x", "abc", vec![]),
+    ];
+    for (re, input, expected) in cases {
+        let nfa = nfa::NFA::from_regex(re).unwrap();
+        assert_eq!(
+            nfa.find_overlapping(input),
+            expected,
+            "re {re}, case: {input}"
+        );
+    }
+
+    let nfa = nfa::NFA::from_regex("a+").unwrap();
+    assert_eq!(nfa.match_at("baaab", 1), Some(4));
+    assert_eq!(nfa.match_at("baaab", 0), None);
+    assert_eq!(nfa.match_at("baaab", 9), None);
+
+    // the output is bounded even when every span matches
+    let nfa = nfa::NFA::from_regex(".*").unwrap();
+    let input = "a".repeat(200);
+    assert_eq!(
+        nfa.find_overlapping(&input).len(),
+        nfa::MAX_OVERLAPPING_MATCHES
+    );
+}
+
+#[test]
+fn find_and_split_test() {
+    let nfa = nfa::NFA::from_regex("a+").unwrap();
+    assert_eq!(nfa.find("xaab aaa"), Some((1, 3)));
+    assert_eq!(nfa.find_iter("xaab aaa"), vec![(1, 3), (5, 8)]);
+    assert_eq!(nfa.find("xyz"), None);
+
+    // empty matches don't loop forever
+    let nfa = nfa::NFA::from_regex("a*").unwrap();
+    assert_eq!(nfa.find_iter("baaac"), vec![(0, 0), (1, 4), (4, 4), (5, 5)]);
+
+    let comma = Regex::new(",".to_string()).unwrap();
+    let split = |input: &str| Regex::split(&comma, input.to_string());
+    assert_eq!(split("a,b,c,d"), vec!["a", "b", "c", "d"]);
+    assert_eq!(split(""), vec![""]);
+    assert_eq!(split(",a,"), vec!["", "a", ""]);
+
+    let cases = [
+        (0, vec![]),
+        (1, vec!["a,b,c,d"]),
+        (2, vec!["a", "b,c,d"]),
+        (3, vec!["a", "b", "c,d"]),
+        (4, vec!["a", "b", "c", "d"]),
+        (9, vec!["a", "b", "c", "d"]),
+    ];
+    for (n, expected) in cases {
+        assert_eq!(comma.splitn("a,b,c,d".to_string(), n), expected, "n: {n}");
+    }
+
+    // spans are in chars, the pieces still slice the input correctly
+    let nfa = nfa::NFA::from_regex("é+").unwrap();
+    assert_eq!(nfa.find("aéé"), Some((1, 3)));
+    assert_eq!(nfa.splitn("日é本éé語", 2), vec!["日", "本éé語"]);
+}
+
+#[test]
+fn regex_error_test() {
+    let cases = [
+        ("", RegexError::Empty),
+        ("()", RegexError::Empty),
+        ("a()", RegexError::Empty),
+        (")", RegexError::UnmatchedParenthesis),
+        ("a)", RegexError::UnmatchedParenthesis),
+        ("(a", RegexError::UnclosedParenthesis),
+        ("a+(a", RegexError::UnclosedParenthesis),
+        ("*", RegexError::MissingOperand),
+        ("a|", RegexError::MissingOperand),
+        ("(a|)b", RegexError::MissingOperand),
+        ("a**", RegexError::NestedQuantifier),
+        ("a+*", RegexError::NestedQuantifier),
+        ("a?*", RegexError::NestedQuantifier),
+        // a ? makes a lazy quantifier, anything after it is nested
+        ("a*??", RegexError::NestedQuantifier),
+        ("a{2}?*", RegexError::NestedQuantifier),
+        // a second + makes a possessive quantifier, a third is nested
+        ("b(a*)+++", RegexError::NestedQuantifier),
+    ];
+    for (re, expected) in cases {
+        assert_eq!(nfa::NFA::from_regex(re).err(), Some(expected), "re {re}");
+    }
+
+    // quantifying a quantified group is fine
+    for re in ["(a*)*", "(a+)?", "((a?)+)*"] {
+        assert!(nfa::NFA::from_regex(re).is_ok(), "re {re}");
+    }
+    let nfa = nfa::NFA::from_regex("(a*)*b").unwrap();
+    assert!(nfa.is_match("b"));
+    assert!(nfa.is_match("aaab"));
+
+    let mut nfa = nfa::NFA::from_regex("a(b)").unwrap();
+    assert_eq!(nfa.patch_group(3, "c"), Err(RegexError::NoSuchGroup(3)));
+}
+
+#[test]
+fn compile_diagnostics_test() {
+    let cases = [
+        ("a.*b", vec![]),
+        ("a|b|c", vec![]),
+        (".*.*", vec![Diagnostic::RedundantWildcardStar]),
+        ("a.*.*b", vec![Diagnostic::RedundantWildcardStar]),
+        ("a|b|a", vec![Diagnostic::DuplicateAlternative]),
+        ("x(ab|cd|ab)y", vec![Diagnostic::DuplicateAlternative]),
+        ("(a|b)|(a|b)", vec![Diagnostic::DuplicateAlternative]),
+        // the same alternative in different unions is fine
+        ("(a|b)(a|c)", vec![]),
+        (
+            "(x|x).*.*",
+            vec![
+                Diagnostic::RedundantWildcardStar,
+                Diagnostic::DuplicateAlternative,
+            ],
+        ),
+    ];
+    for (re, expected) in cases {
+        let (nfa, diagnostics) = compile(re);
+        assert!(nfa.is_some(), "re {re}");
+        assert_eq!(diagnostics, expected, "re {re}");
+    }
+
+    // errors are reported along with any warnings
+    let (nfa, diagnostics) = compile("a|a|");
+    assert!(nfa.is_none());
+    assert_eq!(
+        diagnostics,
+        vec![
+            Diagnostic::Error(RegexError::MissingOperand),
+            Diagnostic::DuplicateAlternative,
+        ]
+    );
+    assert!(diagnostics[0].is_error());
+    assert!(!diagnostics[1].is_error());
+
+    assert_eq!(
+        Regex::diagnostics(".*.*".to_string()),
+        vec!["warning: `.*.*` is redundant, use a single `.*`"]
+    );
+}
+
+#[test]
+fn is_match_reader_test() {
+    use std::io::Cursor;
+
+    let nfa = nfa::NFA::from_regex("abc").unwrap();
+    assert!(nfa.is_match_reader(Cursor::new("abc")).unwrap());
+    assert!(!nfa.is_match_reader(Cursor::new("abcd")).unwrap());
+    assert!(!nfa.is_match_reader(Cursor::new("ab")).unwrap());
+    assert!(!nfa.is_match_reader(Cursor::new("")).unwrap());
+
+    // chars split across reads are put back together
+    let nfa = nfa::NFA::from_regex("(é|日)+").unwrap();
+    let input = "日é".repeat(5000);
+    assert!(nfa.is_match_reader(Cursor::new(input)).unwrap());
+
+    // not UTF-8
+    let nfa = nfa::NFA::from_regex(".*").unwrap();
+    assert!(nfa.is_match_reader(Cursor::new(vec![b'a', 0xff])).is_err());
+    assert!(nfa.is_match_reader(Cursor::new(vec![b'a', 0xe6])).is_err());
+
+    // the streaming matcher agrees with is_match
+    for (re, input) in [
+        ("a(bb)+|b", "abbbb"),
+        (".+@.+\\.com?", "hi@x.co"),
+        ("a*", "b"),
+    ] {
+        let nfa = nfa::NFA::from_regex(re).unwrap();
+        let mut matcher = nfa.matcher();
+        matcher.feed_str(input);
+        assert_eq!(
+            matcher.is_match(),
+            nfa.is_match(input),
+            "re {re}, case: {input}"
+        );
+        assert_eq!(matcher.position(), input.chars().count());
+    }
+}
+
+#[test]
+fn star_over_empty_matching_test() {
+    // every one of these can match the empty string inside the star,
+    // creating epsilon cycles that must not loop forever
+    let cases = [
+        (
+            "(a?)*",
+            vec![
+                ("", true),
+                ("a", true),
+                ("aaaa", true),
+                ("b", false),
+                ("ab", false),
+            ],
+        ),
+        (
+            "(a*)*",
+            vec![
+                ("", true),
+                ("a", true),
+                ("aaaa", true),
+                ("b", false),
+                ("ab", false),
+            ],
+        ),
+        (
+            "((a?)*)*b",
+            vec![
+                ("b", true),
+                ("aab", true),
+                ("", false),
+                ("a", false),
+                ("ba", false),
+            ],
+        ),
+        (
+            "(a?b?)*",
+            vec![
+                ("", true),
+                ("ab", true),
+                ("ba", true),
+                ("bbaab", true),
+                ("c", false),
+            ],
+        ),
+        ("(a*)+", vec![("", true), ("aaa", true), ("b", false)]),
+    ];
+    for (re, cases) in cases {
+        let nfa = nfa::NFA::from_regex(re).unwrap();
+        for (input, expected) in cases {
+            assert_eq!(nfa.is_match(input), expected, "re {re}, case: {input}");
+
+            let mut matcher = nfa.matcher();
+            matcher.feed_str(input);
+            assert_eq!(matcher.is_match(), expected, "re {re}, case: {input}");
+        }
+    }
+
+    let nfa = nfa::NFA::from_regex("(a?)*").unwrap();
+    assert_eq!(nfa.find_iter("aab"), vec![(0, 2), (2, 2), (3, 3)]);
+
+    // an empty alternative is not a valid operand
+    assert_eq!(
+        nfa::NFA::from_regex("(|a)*").err(),
+        Some(RegexError::MissingOperand)
+    );
+}
+
+#[test]
+fn bounded_input_test() {
+    let regex = Regex::newBounded("a*".to_string(), 4).unwrap();
+    assert!(regex.isMatch("aaaa".to_string()));
+    assert!(!regex.inputTooLong());
+
+    assert!(!regex.isMatch("aaaaa".to_string()));
+    assert!(regex.inputTooLong());
+
+    // the bound counts chars, not bytes
+    let regex = Regex::newBounded(".*".to_string(), 2).unwrap();
+    assert!(regex.isMatch("日本".to_string()));
+    assert!(!regex.inputTooLong());
+    assert!(Regex::splitn(&regex, "日本語".to_string(), 2).is_empty());
+    assert!(regex.inputTooLong());
+
+    // the flag only describes the last input
+    assert!(regex.isMatch("".to_string()));
+    assert!(!regex.inputTooLong());
+
+    // unbounded regexes never trigger it
+    let regex = Regex::new("a*".to_string()).unwrap();
+    assert!(regex.isMatch("a".repeat(1000)));
+    assert!(!regex.inputTooLong());
+
+    assert!(Regex::newBounded("a|".to_string(), 4).is_none());
+}
+
+#[test]
+fn shortest_match_test() {
+    let cases = [
+        ("a(bb)+", Some("abb")),
+        (".*a.*", Some("a")),
+        ("a*", Some("")),
+        ("abc|d", Some("d")),
+        ("(ab)?c+", Some("c")),
+        ("a.b", Some("a_b")),
+    ];
+    for (re, expected) in cases {
+        let nfa = nfa::NFA::from_regex(re).unwrap();
+        let shortest = nfa.shortest_match();
+        assert_eq!(shortest.as_deref(), expected, "re {re}");
+        assert!(nfa.is_match(&shortest.unwrap()), "re {re}");
+    }
+
+    // the placeholder respects a restricted wildcard
+    let options = nfa::CompileOptions {
+        wildcard_set: Some(['G', 'T'].into_iter().collect()),
+        ..Default::default()
+    };
+    let nfa = nfa::NFA::from_regex_with_options("A.", &options).unwrap();
+    assert_eq!(nfa.shortest_match().as_deref(), Some("AG"));
+
+    // a wildcard that matches nothing makes the language empty
+    let options = nfa::CompileOptions {
+        wildcard_set: Some(Default::default()),
+        ..Default::default()
+    };
+    let nfa = nfa::NFA::from_regex_with_options("a.", &options).unwrap();
+    assert_eq!(nfa.shortest_match(), None);
+    let nfa = nfa::NFA::from_regex_with_options("a.|b", &options).unwrap();
+    assert_eq!(nfa.shortest_match().as_deref(), Some("b"));
+}
+
+#[test]
+fn flattened_union_test() {
+    // grouping the unions stops them from being flattened, so it builds
+    // the same NFA as the naive binary construction
+    let flat = nfa::NFA::from_regex("a|b|c|d").unwrap();
+    let naive = nfa::NFA::from_regex("((a|b)|c)|d").unwrap();
+    // 2 states per letter, plus one start and out for the whole union vs
+    // one start and out per binary union
+    assert_eq!(flat.num_states(), 10);
+    assert_eq!(naive.num_states(), 14);
+
+    let flat_ab = nfa::NFA::from_regex("ab|cd|ef|g*").unwrap();
+    let naive_ab = nfa::NFA::from_regex("((ab|cd)|ef)|g*").unwrap();
+    assert!(flat_ab.num_states() < naive_ab.num_states());
+
+    let inputs = [
+        "", "a", "b", "c", "d", "e", "ab", "cd", "ef", "gg", "abcd", "ac", "dd",
+    ];
+    for input in inputs {
+        assert_eq!(flat.is_match(input), naive.is_match(input), "case: {input}");
+        assert_eq!(
+            flat_ab.is_match(input),
+            naive_ab.is_match(input),
+            "case: {input}"
+        );
+    }
+
+    // groups inside a flattened union can still be patched
+    let mut nfa = nfa::NFA::from_regex("(a)|b|(c)").unwrap();
+    nfa.patch_group(1, "x").unwrap();
+    assert!(nfa.is_match("x"));
+    assert!(nfa.is_match("b"));
+    assert!(!nfa.is_match("c"));
+}
+
+#[test]
+fn parse_with_spans_test() {
+    let ast = parse_with_spans("a(bc)d").unwrap();
+    assert_eq!(ast.span, (0, 6));
+
+    // (a(bc))d
+    let AstKind::Concatenation(left, right) = &ast.kind else {
+        panic!("expected concatenation, got {ast:?}");
+    };
+    assert_eq!(right.kind, AstKind::Letter('d'));
+    assert_eq!(right.span, (5, 6));
+    let AstKind::Concatenation(a, group) = &left.kind else {
+        panic!("expected concatenation, got {left:?}");
+    };
+    assert_eq!(a.span, (0, 1));
+    assert!(matches!(group.kind, AstKind::Group(0, _)));
+    assert_eq!(group.span, (1, 5));
+
+    // the node under the cursor
+    assert_eq!(ast.node_at(2).unwrap().kind, AstKind::Letter('b'));
+    assert_eq!(ast.node_at(1).unwrap().span, (1, 5));
+    assert_eq!(ast.node_at(4).unwrap().span, (1, 5));
+    assert_eq!(ast.node_at(6), None);
+
+    // quantifiers cover what they quantify, escapes cover the backslash
+    let ast = parse_with_spans("x(ab)*|\\.+").unwrap();
+    let AstKind::Union(left, right) = &ast.kind else {
+        panic!("expected union, got {ast:?}");
+    };
+    assert_eq!(left.span, (0, 6));
+    assert!(matches!(right.kind, AstKind::PositiveQuantifier(_)));
+    assert_eq!(right.span, (7, 10));
+    assert_eq!(right.children()[0].kind, AstKind::Letter('.'));
+    assert_eq!(right.children()[0].span, (7, 9));
+
+    assert_eq!(
+        parse_with_spans("a(b"),
+        Err(RegexError::UnclosedParenthesis)
+    );
+}
+
+#[test]
+fn bounded_repetition_test() {
+    let cases = [
+        (
+            "a{3}",
+            vec![("aaa", true), ("aa", false), ("aaaa", false), ("", false)],
+        ),
+        (
+            "a{2,4}",
+            vec![
+                ("a", false),
+                ("aa", true),
+                ("aaa", true),
+                ("aaaa", true),
+                ("aaaaa", false),
+            ],
+        ),
+        (
+            "a{2,}",
+            vec![
+                ("a", false),
+                ("aa", true),
+                ("aaaaaaa", true),
+                ("aab", false),
+            ],
+        ),
+        ("a{0,}", vec![("", true), ("aaa", true), ("b", false)]),
+        (
+            "a{0,2}",
+            vec![("", true), ("a", true), ("aa", true), ("aaa", false)],
+        ),
+        ("xa{0}y", vec![("xy", true), ("xay", false)]),
+        (
+            "(ab|c){1,2}d",
+            vec![
+                ("abd", true),
+                ("cd", true),
+                ("abcd", true),
+                ("ccd", true),
+                ("d", false),
+                ("ababcd", false),
+            ],
+        ),
+        (
+            "(a{2}){2}",
+            vec![("aaaa", true), ("aaa", false), ("aaaaa", false)],
+        ),
+        (
+            "a{1,2}b{2}",
+            vec![("abb", true), ("aabb", true), ("ab", false)],
+        ),
+    ];
+    for (re, cases) in cases {
+        let nfa = nfa::NFA::from_regex(re).unwrap();
+        for (input, expected) in cases {
+            assert_eq!(nfa.is_match(input), expected, "re {re}, case: {input}");
+        }
+    }
+
+    let invalid_cases = [
+        ("a{3,2}", RegexError::InvalidRepetition),
+        ("{2}", RegexError::MissingOperand),
+        ("a|{2}", RegexError::MissingOperand),
+        ("a*{2}", RegexError::NestedQuantifier),
+        ("a{2}+", RegexError::NestedQuantifier),
+        ("a{100000}", RegexError::TooManyStates),
+        ("(a{1000}){1000}", RegexError::TooManyStates),
+    ];
+    for (re, expected) in invalid_cases {
+        assert_eq!(nfa::NFA::from_regex(re).err(), Some(expected), "re {re}");
+    }
+
+    // the cap is configurable
+    let options = nfa::CompileOptions {
+        max_states: 10,
+        ..Default::default()
+    };
+    assert!(nfa::NFA::from_regex_with_options("a{5}", &options).is_ok());
+    assert_eq!(
+        nfa::NFA::from_regex_with_options("a{6}", &options).err(),
+        Some(RegexError::TooManyStates)
+    );
+
+    // braces that don't form a quantifier are literals
+    for (re, input) in [
+        ("a{", "a{"),
+        ("a{b}", "a{b}"),
+        ("a{,2}", "a{,2}"),
+        ("{}", "{}"),
+    ] {
+        let nfa = nfa::NFA::from_regex(re).unwrap();
+        assert!(nfa.is_match(input), "re {re}");
+    }
+
+    // a repeated group is copied, so it can't be patched on its own
+    let mut nfa = nfa::NFA::from_regex("(a){2}(b)").unwrap();
+    assert_eq!(nfa.patch_group(0, "c"), Err(RegexError::RepeatedGroup(0)));
+    assert!(nfa.patch_group(1, "c").is_ok());
+    assert!(nfa.is_match("aac"));
+}
+
+#[test]
+fn deterministic_trace_test() {
+    let trace = |re: &str, input: &str| {
+        let nfa = nfa::NFA::from_regex(re).unwrap();
+        format!("{:?}", nfa.trace(input))
+    };
+
+    // the same pattern and input always explore in the same order, even
+    // across separately compiled NFAs
+    let expected = trace("a(b|c)*d", "abcbd");
+    for _ in 0..20 {
+        assert_eq!(trace("a(b|c)*d", "abcbd"), expected);
+    }
+
+    // a small trace, spelled out: a is states 0 -> 1, b is 2 -> 3, concat
+    // links 1 -> 2 with an epsilon
+    assert_eq!(trace("ab", "ab"), "[(0, 0), (1, 1), (1, 2), (2, 3)]");
+
+    // the trace stops at the match (the union's out, state 7), and agrees
+    // with is_match
+    let nfa = nfa::NFA::from_regex("a|ab").unwrap();
+    let matched = nfa.trace("ab");
+    assert_eq!(matched.last(), Some(&(2, 7)));
+    assert!(nfa.is_match("ab"));
+}
+
+// also runs in the browser with wasm-pack test, since it's for JS
+#[cfg_attr(not(target_arch = "wasm32"), test)]
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen_test::wasm_bindgen_test)]
+fn captures_test() {
+    let captures = |re: &str, input: &str| nfa::NFA::from_regex(re).unwrap().captures(input);
+
+    assert_eq!(
+        captures("(a+)(b+)", "aaabb"),
+        Some(vec![Some((0, 3)), Some((3, 5))])
+    );
+    // quantifiers take as much as they can, unions prefer the left side
+    assert_eq!(
+        captures("(a*)(a*)", "aa"),
+        Some(vec![Some((0, 2)), Some((2, 2))])
+    );
+    assert_eq!(
+        captures("(a|ab)(c|bcd)", "abcd"),
+        Some(vec![Some((0, 1)), Some((1, 4))])
+    );
+    // a group in a loop keeps its last span, an unused group is None
+    assert_eq!(
+        captures("((a)|b)*", "ab"),
+        Some(vec![Some((1, 2)), Some((0, 1))])
+    );
+    assert_eq!(captures("(a)|(b)", "b"), Some(vec![None, Some((0, 1))]));
+    assert_eq!(captures("(a)b", "ac"), None);
+
+    // JS gets utf-16 offsets: 😀 is one char but two code units
+    let regex = Regex::new("😀(é+)(.)".to_string()).unwrap();
+    assert_eq!(
+        regex.capturesJs("😀éé😀".to_string()),
+        r#"[{"start":2,"end":4},{"start":4,"end":6}]"#
+    );
+    assert_eq!(regex.capturesJs("nope".to_string()), "null");
+    let regex = Regex::new("(a)|(b)".to_string()).unwrap();
+    assert_eq!(
+        regex.capturesJs("b".to_string()),
+        r#"[null,{"start":0,"end":1}]"#
+    );
+}
+
+#[test]
+fn remove_epsilon_states_test() {
+    let cases = [
+        ("pens?", vec!["", "pen", "pens", "penss", "pe"]),
+        ("(a|b)*abb", vec!["", "abb", "aabb", "babb", "ab", "abba"]),
+        ("(a*)*b?", vec!["", "a", "aab", "b", "bb", "ba"]),
+        ("a(b|c|d)+.", vec!["ab", "abx", "abcdx", "a", "aex"]),
+        ("(ab){2,3}", vec!["ab", "abab", "ababab", "abababab"]),
+        ("a{0}", vec!["", "a"]),
+    ];
+    for (re, inputs) in cases {
+        let nfa = nfa::NFA::from_regex(re).unwrap();
+        let mut simplified = nfa.clone();
+        simplified.remove_epsilon_states();
+
+        assert!(!nfa.is_epsilon_free());
+        assert!(simplified.is_epsilon_free(), "re {re}");
+        assert!(simplified.num_states() < nfa.num_states(), "re {re}");
+        for input in inputs {
+            assert_eq!(
+                simplified.is_match(input),
+                nfa.is_match(input),
+                "re {re}, input {input}"
+            );
+        }
+    }
+
+    // the groups are gone with the states that marked them
+    let mut nfa = nfa::NFA::from_regex("(a)b").unwrap();
+    nfa.remove_epsilon_states();
+    assert_eq!(nfa.captures("ab"), Some(vec![]));
+    assert_eq!(nfa.patch_group(0, "c"), Err(RegexError::NoSuchGroup(0)));
+}
+
+#[test]
+fn escaped_literals_test() {
+    let cases = [
+        // escaped braces and brackets are always letters
+        (
+            r"a\{b\}",
+            vec![("a{b}", true), ("ab", false), ("a{b", false)],
+        ),
+        (r"\{2\}", vec![("{2}", true), ("", false)]),
+        (r"a\{2}", vec![("a{2}", true), ("aa", false)]),
+        (r"\[ab\]", vec![("[ab]", true), ("a", false)]),
+        // a brace that doesn't make a quantifier is a letter too
+        ("{abc}", vec![("{abc}", true), ("abc", false)]),
+        ("a{b", vec![("a{b", true)]),
+        ("a}", vec![("a}", true)]),
+        // escaped operators followed by more regex
+        (r"\|a", vec![("|a", true), ("a", false)]),
+        (r"a\|b", vec![("a|b", true), ("a", false), ("b", false)]),
+        (r"\(a\)", vec![("(a)", true), ("a", false)]),
+        (r"\*+b", vec![("**b", true), ("b", false)]),
+        (r"(\|)|x", vec![("|", true), ("x", true)]),
+    ];
+    for (re, inputs) in cases {
+        let nfa = nfa::NFA::from_regex(re).unwrap();
+        for (input, expected) in inputs {
+            assert_eq!(nfa.is_match(input), expected, "re {re}, input {input}");
+        }
+    }
+
+    let tokens = parse::parse_re_to_tokens(r"\{\}\[\]", nfa::DEFAULT_MAX_DEPTH).unwrap();
+    assert_eq!(
+        tokens,
+        vec![
+            parse::Token::Letter('{'),
+            parse::Token::Concatenation,
+            parse::Token::Letter('}'),
+            parse::Token::Concatenation,
+            parse::Token::Letter('['),
+            parse::Token::Concatenation,
+            parse::Token::Letter(']'),
+        ]
+    );
+}
+
+#[test]
+fn full_and_contains_match_test() {
+    // the same inputs as valid_regex_test, where a substring is enough
+    let cases = [
+        (
+            "pens?",
+            vec![
+                ("", false, false),
+                ("p", false, false),
+                ("pen", true, true),
+                ("pens", true, true),
+                ("pens?", false, true),
+                ("spens", false, true),
+                ("pencil", false, true),
+            ],
+        ),
+        (
+            "a|b",
+            vec![
+                ("a", true, true),
+                ("ab", false, true),
+                ("cbc", false, true),
+                ("c", false, false),
+            ],
+        ),
+        (
+            "(ab)+c",
+            vec![
+                ("abc", true, true),
+                ("xababcx", false, true),
+                ("ac", false, false),
+            ],
+        ),
+        // a pattern that matches the empty string is in every input
+        ("a*", vec![("", true, true), ("bbb", false, true)]),
+    ];
+    for (re, inputs) in cases {
+        let nfa = nfa::NFA::from_regex(re).unwrap();
+        let regex = Regex::new(re.to_string()).unwrap();
+        for (input, full, contains) in inputs {
+            assert_eq!(nfa.full_match(input), full, "re {re}, input {input}");
+            assert_eq!(nfa.is_match(input), full, "re {re}, input {input}");
+            assert_eq!(
+                nfa.contains_match(input),
+                contains,
+                "re {re}, input {input}"
+            );
+            assert_eq!(regex.fullMatch(input.to_string()), full);
+            assert_eq!(regex.isMatch(input.to_string()), full);
+            assert_eq!(regex.containsMatch(input.to_string()), contains);
+        }
+    }
+}
+
+#[test]
+fn min_length_test() {
+    for (re, expected) in [
+        ("a(bb)+", 3),
+        ("a?", 0),
+        ("a*", 0),
+        ("abc|d", 1),
+        ("(ab|c){2,3}d", 3),
+        ("pens?", 3),
+        ("..", 2),
+    ] {
+        let nfa = nfa::NFA::from_regex(re).unwrap();
+        assert_eq!(nfa.min_length(), expected, "re {re}");
+    }
+
+    // a wildcard that matches nothing can't be in an accepted string
+    let options = nfa::CompileOptions {
+        wildcard_set: Some(Default::default()),
+        ..Default::default()
+    };
+    let nfa = nfa::NFA::from_regex_with_options("a.|bcd", &options).unwrap();
+    assert_eq!(nfa.min_length(), 3);
+    let nfa = nfa::NFA::from_regex_with_options(".", &options).unwrap();
+    assert_eq!(nfa.min_length(), usize::MAX);
+}
+
+#[test]
+fn max_length_test() {
+    for (re, expected) in [
+        ("a{2,4}", Some(4)),
+        ("a*", None),
+        ("a+b", None),
+        ("pens?", Some(4)),
+        ("abc|d", Some(3)),
+        ("(ab|c){2,3}d", Some(7)),
+        ("a?b?c?", Some(3)),
+        ("(a{0})*b", Some(1)),
+        ("a(b|c)*d|e", None),
+    ] {
+        let nfa = nfa::NFA::from_regex(re).unwrap();
+        assert_eq!(nfa.max_length(), expected, "re {re}");
+    }
+
+    // a loop that can't be taken doesn't make strings any longer
+    let options = nfa::CompileOptions {
+        wildcard_set: Some(Default::default()),
+        ..Default::default()
+    };
+    let nfa = nfa::NFA::from_regex_with_options("ab|.*c", &options).unwrap();
+    assert_eq!(nfa.max_length(), Some(2));
+}
+
+#[test]
+fn replace_test() {
+    let nfa = nfa::NFA::from_regex("a+").unwrap();
+    assert_eq!(nfa.replace("baab aa", "X"), "bXb aa");
+    assert_eq!(nfa.replace_all("baab aa", "X"), "bXb X");
+    assert_eq!(nfa.replace("bbb", "X"), "bbb");
+    // the replacement is literal, and offsets work past multibyte chars
+    assert_eq!(nfa.replace("éaé", "$1"), "é$1é");
+
+    // empty matches get replaced too, where find_iter finds them (like python)
+    let nfa = nfa::NFA::from_regex("a*").unwrap();
+    assert_eq!(nfa.replace("bab", "-"), "-bab");
+    assert_eq!(nfa.replace_all("bab", "-"), "-b--b-");
+
+    let regex = Regex::new("a+".to_string()).unwrap();
+    assert_eq!(
+        regex.replace("baab aa".to_string(), "X".to_string()),
+        "bXb aa"
+    );
+    assert_eq!(
+        regex.replaceAll("baab aa".to_string(), "X".to_string()),
+        "bXb X"
+    );
+    let bounded = Regex::newBounded("a+".to_string(), 3).unwrap();
+    assert_eq!(bounded.replace("baab".to_string(), "X".to_string()), "baab");
+    assert!(bounded.inputTooLong());
+}
+
+#[test]
+fn from_literals_test() {
+    let nfa = nfa::NFA::from_literals(&["cat", "car", "dog"]);
+    for (input, expected) in [
+        ("cat", true),
+        ("car", true),
+        ("dog", true),
+        ("", false),
+        ("ca", false),
+        ("cats", false),
+        ("cog", false),
+        ("do", false),
+    ] {
+        assert_eq!(nfa.is_match(input), expected, "input {input}");
+    }
+    // c and ca are shared, so 1 + 3 + 1 + 3 states
+    assert_eq!(nfa.num_states(), 8);
+    let union = nfa::NFA::from_regex("cat|car|dog").unwrap();
+    assert!(nfa.num_states() < union.num_states());
+
+    // chars are literal, and the empty word makes the start accepting
+    let nfa = nfa::NFA::from_literals(&["a.b", "", "a"]);
+    assert!(nfa.is_match("a.b"));
+    assert!(!nfa.is_match("axb"));
+    assert!(nfa.is_match(""));
+    assert!(nfa.is_match("a"));
+
+    let nfa = nfa::NFA::from_literals(&[]);
+    assert!(!nfa.is_match(""));
+    assert_eq!(nfa.find("anything"), None);
+}
+
+#[test]
+fn regex_from_str_test() {
+    let regex: Regex = "a|b".parse().unwrap();
+    assert!(regex.isMatch("a".to_string()));
+    assert!(!regex.isMatch("ab".to_string()));
+
+    assert_eq!(
+        "a||b".parse::<Regex>().err(),
+        Some(RegexError::MissingOperand)
+    );
+    assert_eq!(
+        "(a".parse::<Regex>().err(),
+        Some(RegexError::UnclosedParenthesis)
+    );
+}
+
+#[test]
+fn explain_test() {
+    let explain = |re: &str, input: &str| nfa::NFA::from_regex(re).unwrap().explain(input);
+
+    assert_eq!(
+        explain("abc", "abx"),
+        "no match at position 2: expected `c` but found `x`"
+    );
+    assert_eq!(
+        explain("abc", "ab"),
+        "no match at position 2: expected `c` but the input ended"
+    );
+    assert_eq!(
+        explain("a(b|c|d)", "ax"),
+        "no match at position 1: expected one of `b`, `c`, `d` but found `x`"
+    );
+    assert_eq!(
+        explain("a.", "a"),
+        "no match at position 1: expected any char but the input ended"
+    );
+    assert_eq!(
+        explain("ab", "abc"),
+        "no match at position 2: expected nothing but found `c`"
+    );
+    assert_eq!(explain("a*b", "aab"), "match");
+}
+
+#[test]
+fn possessive_quantifier_test() {
+    let inputs = ["", "b", "ab", "aaab", "a", "ba", "aabb"];
+    for (possessive, greedy) in [
+        ("a*+b", "a*b"),
+        ("a++b", "a+b"),
+        ("a?+b", "a?b"),
+        ("(ab)*+c|(a)++", "(ab)*c|(a)+"),
+    ] {
+        let possessive = nfa::NFA::from_regex(possessive).unwrap();
+        let greedy = nfa::NFA::from_regex(greedy).unwrap();
+        assert_eq!(possessive, greedy);
+        for input in inputs {
+            assert_eq!(possessive.is_match(input), greedy.is_match(input));
+        }
+    }
+
+    // only one + makes it possessive, an escaped one is a letter
+    assert_eq!(
+        nfa::NFA::from_regex("a+++").err(),
+        Some(RegexError::NestedQuantifier)
+    );
+    assert!(nfa::NFA::from_regex(r"a*\+").unwrap().is_match("aa+"));
+
+    // the possessive + is part of the quantifier's span
+    let ast = parse_with_spans("a*+b").unwrap();
+    assert_eq!(ast.node_at(2).map(|node| node.span), Some((0, 3)));
+}
+
+#[test]
+fn regex_set_test() {
+    let set = RegexSet::new(&["a+", "a|b", "(ab)*", ".b", "b+"]).unwrap();
+    assert_eq!(set.matching("a"), vec![0, 1]);
+    assert_eq!(set.matching("b"), vec![1, 4]);
+    assert_eq!(set.matching("ab"), vec![2, 3]);
+    assert_eq!(set.matching("bb"), vec![3, 4]);
+    assert_eq!(set.matching(""), vec![2]);
+    assert_eq!(set.matching("c"), Vec::<usize>::new());
+
+    // more patterns than fit in one word of the bitset
+    let words: Vec<String> = (0..100).map(|n| format!("x{n}|y")).collect();
+    let words: Vec<&str> = words.iter().map(String::as_str).collect();
+    let set = RegexSet::new(&words).unwrap();
+    assert_eq!(set.matching("x70"), vec![70]);
+    assert_eq!(set.matching("y").len(), 100);
+
+    assert_eq!(
+        RegexSet::new(&["a", "(b"]).err(),
+        Some(RegexError::UnclosedParenthesis)
+    );
+}
+
+#[test]
+fn whole_word_test() {
+    let options = nfa::CompileOptions {
+        whole_word: true,
+        ..Default::default()
+    };
+    let whole = nfa::NFA::from_regex_with_options("cat", &options).unwrap();
+    let part = nfa::NFA::from_regex("cat").unwrap();
+
+    for (input, whole_span, part_span) in [
+        ("a cat here", Some((2, 5)), Some((2, 5))),
+        ("category", None, Some((0, 3))),
+        ("concat", None, Some((3, 6))),
+        ("cat", Some((0, 3)), Some((0, 3))),
+        ("(cat)", Some((1, 4)), Some((1, 4))),
+        ("cat_", None, Some((0, 3))),
+        ("écat cat", Some((5, 8)), Some((1, 4))),
+    ] {
+        assert_eq!(whole.find(input), whole_span, "input {input}");
+        assert_eq!(part.find(input), part_span, "input {input}");
+    }
+    assert_eq!(
+        whole.replace_all("cat concat cat.", "dog"),
+        "dog concat dog."
+    );
+
+    // full matches only need the pattern to start and end on word chars
+    assert!(whole.is_match("cat"));
+    let spaced = nfa::NFA::from_regex_with_options(" cat", &options).unwrap();
+    assert!(!spaced.is_match(" cat"));
+    assert_eq!(spaced.find("a cat"), Some((1, 5)));
+    assert_eq!(spaced.find(" cat"), None);
+
+    // every matcher agrees on the boundaries
+    let words = nfa::NFA::from_regex_with_options("(c|d)a.", &options).unwrap();
+    for (input, expected) in [("cat", true), ("da_", true), ("ca-", false)] {
+        assert_eq!(words.is_match(input), expected, "input {input}");
+        assert_eq!(words.is_match_reader(input.as_bytes()).unwrap(), expected);
+        assert_eq!(words.captures(input).is_some(), expected);
+        let mut simplified = words.clone();
+        simplified.remove_epsilon_states();
+        assert_eq!(simplified.is_match(input), expected);
+    }
+    assert_eq!(words.captures("dab"), Some(vec![Some((0, 1))]));
+    assert_eq!(whole.min_length(), 3);
+    assert_eq!(whole.max_length(), Some(3));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_round_trip_test() {
+    let tokens = parse::parse_re_to_tokens(r"a(b|\.)*c{2,}", nfa::DEFAULT_MAX_DEPTH).unwrap();
+    let json = serde_json::to_string(&tokens).unwrap();
+    assert!(json.starts_with(r#"[{"Letter":"a"},"Concatenation","OpenParenthesis""#));
+    let back: Vec<parse::Token> = serde_json::from_str(&json).unwrap();
+    assert_eq!(back, tokens);
+
+    let ast = parse_with_spans("(a|b)+").unwrap();
+    let json = serde_json::to_string(&ast).unwrap();
+    let back: Ast = serde_json::from_str(&json).unwrap();
+    assert_eq!(back, ast);
+}
+
+#[test]
+fn folded_find_test() {
+    // ß folds to two chars, but the span still covers Straße in the input
+    let nfa = nfa::NFA::from_regex("strasse").unwrap();
+    let input = "Die Straße ist lang";
+    assert_eq!(nfa.find(input), None);
+    let (start, end) = nfa.find_folded(input, nfa::case_fold).unwrap();
+    assert_eq!((start, end), (4, 10));
+    let matched: String = input.chars().skip(start).take(end - start).collect();
+    assert_eq!(matched, "Straße");
+
+    // spans after a char that grew are shifted back correctly
+    let nfa = nfa::NFA::from_regex("ist").unwrap();
+    assert_eq!(nfa.find_folded(input, nfa::case_fold), Some((11, 14)));
+    let nfa = nfa::NFA::from_regex("(ss|a)+").unwrap();
+    assert_eq!(
+        nfa.find_iter_folded("ßAßxa", nfa::case_fold),
+        vec![(0, 3), (4, 5)]
+    );
+
+    // matching half of a folded char widens to the whole char
+    let nfa = nfa::NFA::from_regex("as").unwrap();
+    assert_eq!(nfa.find_folded("aß", nfa::case_fold), Some((0, 2)));
+
+    // any fold works, e.g. removing accents or dropping chars
+    let strip_accents = |c: char| match c {
+        'é' | 'è' => "e".to_string(),
+        '\u{301}' => String::new(),
+        c => c.to_string(),
+    };
+    let nfa = nfa::NFA::from_regex("cafe").unwrap();
+    assert_eq!(nfa.find_folded("un café", strip_accents), Some((3, 7)));
+    assert_eq!(nfa.find_folded("cafe\u{301}!", strip_accents), Some((0, 4)));
+}
+
+#[test]
+fn anchors_test() {
+    let anchored = |re: &str| {
+        let regex = Regex::new(re.to_string()).unwrap();
+        (regex.isAnchoredStart(), regex.isAnchoredEnd())
+    };
+    assert_eq!(anchored("^ab"), (true, false));
+    assert_eq!(anchored("ab$"), (false, true));
+    assert_eq!(anchored("ab"), (false, false));
+    assert_eq!(anchored("^ab$"), (true, true));
+    assert_eq!(anchored("(^a|^b)c*$"), (true, true));
+    assert_eq!(anchored("^a|b"), (false, false));
+    assert_eq!(anchored("^?a"), (false, false));
+    assert_eq!(anchored("a*$"), (false, true));
+
+    // anchors only matter when searching, a full match is anchored anyway
+    let nfa = nfa::NFA::from_regex("^ab").unwrap();
+    assert_eq!(nfa.find("abab"), Some((0, 2)));
+    assert_eq!(nfa.find("cab"), None);
+    assert!(nfa.is_match("ab"));
+    let nfa = nfa::NFA::from_regex("ab$").unwrap();
+    assert_eq!(nfa.find_iter("abab"), vec![(2, 4)]);
+    assert!(nfa.is_match_reader("ab".as_bytes()).unwrap());
+    let nfa = nfa::NFA::from_regex("a^b").unwrap();
+    assert!(!nfa.is_match("ab"));
+    assert!(!nfa.matcher().is_match());
+
+    // escaped, they are letters again
+    let nfa = nfa::NFA::from_regex(r"\^a\$").unwrap();
+    assert!(nfa.is_match("^a$"));
+    assert!(!nfa.is_anchored_start());
+
+    let ast = parse_with_spans("^a{2}$").unwrap();
+    assert_eq!(ast.node_at(0).unwrap().kind, AstKind::StartAnchor);
+    assert_eq!(ast.node_at(2).unwrap().span, (1, 5));
+}
+
+#[test]
+fn union_precedence_test() {
+    // concatenation binds tighter than union, so ab|cd is (ab)|(cd)
+    let cases = [
+        (
+            "ab|cd",
+            vec![
+                ("ab", true),
+                ("cd", true),
+                ("ac", false),
+                ("abcd", false),
+                ("abd", false),
+            ],
+        ),
+        (
+            "a|bc|d",
+            vec![
+                ("a", true),
+                ("bc", true),
+                ("d", true),
+                ("ac", false),
+                ("abcd", false),
+                ("b", false),
+            ],
+        ),
+        (
+            "ab|cd|ef",
+            vec![
+                ("ab", true),
+                ("cd", true),
+                ("ef", true),
+                ("ac", false),
+                ("abcd", false),
+                ("bc", false),
+            ],
+        ),
+    ];
+    for (re, inputs) in cases {
+        let nfa = nfa::NFA::from_regex(re).unwrap();
+        for (input, expected) in inputs {
+            assert_eq!(nfa.is_match(input), expected, "re {re}, input {input}");
+        }
+    }
+
+    // no implicit concatenation is placed next to the |
+    use parse::Token::*;
+    assert_eq!(
+        parse::calc_postfix(parse::parse_re_to_tokens("ab|cd", nfa::DEFAULT_MAX_DEPTH).unwrap())
+            .unwrap(),
+        vec![
+            Letter('a'),
+            Letter('b'),
+            Concatenation,
+            Letter('c'),
+            Letter('d'),
+            Concatenation,
+            Union
+        ]
+    );
+    let ast = parse_with_spans("ab|cd").unwrap();
+    assert!(matches!(ast.kind, AstKind::Union(_, _)));
+    assert_eq!(ast.children()[0].span, (0, 2));
+    assert_eq!(ast.children()[1].span, (3, 5));
+}
+
+#[test]
+fn simplify_test() {
+    // every string of up to 4 chars from the alphabet
+    let alphabet = ['a', 'b', 'c', '.'];
+    let mut inputs = vec![String::new()];
+    for len in 1..=4 {
+        let longer: Vec<String> = inputs
+            .iter()
+            .filter(|input| input.chars().count() == len - 1)
+            .flat_map(|input| alphabet.iter().map(move |c| format!("{input}{c}")))
+            .collect();
+        inputs.extend(longer);
+    }
+
+    for re in [
+        "pens?",
+        "a|b",
+        "(a|b)*abb",
+        "(a*)*b?",
+        "a(b|c|a)+.",
+        "(ab){2,3}|c",
+        "a.|ab|ac",
+        "a{0}b",
+        "^a*$",
+        "(a|b)c|(a|b)c",
+    ] {
+        let nfa = nfa::NFA::from_regex(re).unwrap();
+        let mut simplified = nfa.clone();
+        simplified.simplify();
+        assert!(simplified.num_states() <= nfa.num_states(), "re {re}");
+        assert!(simplified.is_epsilon_free(), "re {re}");
+        for input in &inputs {
+            assert_eq!(
+                simplified.is_match(input),
+                nfa.is_match(input),
+                "re {re}, input {input}"
+            );
+        }
+    }
+
+    // a wildcard covers the letters next to it, and nothing can't be
+    // simplified any further
+    let mut nfa = nfa::NFA::from_regex("a|.").unwrap();
+    nfa.simplify();
+    assert_eq!(nfa.num_states(), 2);
+    let options = nfa::CompileOptions {
+        wildcard_set: Some(Default::default()),
+        ..Default::default()
+    };
+    let mut nfa = nfa::NFA::from_regex_with_options(".a", &options).unwrap();
+    nfa.simplify();
+    assert_eq!(nfa.num_states(), 1);
+    assert!(!nfa.is_match("ba"));
+}
+
+#[test]
+fn match_stats_test() {
+    // ab walks straight through its 4 states, see deterministic_trace_test
+    let nfa = nfa::NFA::from_regex("ab").unwrap();
+    let (matched, stats) = nfa.match_with_stats("ab");
+    assert!(matched);
+    assert_eq!(
+        stats,
+        nfa::MatchStats {
+            states_dequeued: 4,
+            max_frontier_size: 1,
+            closures_computed: 3,
+        }
+    );
+    let (matched, stats) = nfa.match_with_stats("xb");
+    assert!(!matched);
+    assert_eq!(stats.states_dequeued, 0);
+    assert_eq!(stats.max_frontier_size, 0);
+
+    // each (idx, state) pair is dequeued at most once
+    let nfa = nfa::NFA::from_regex("(a|b)*abb").unwrap();
+    let input = "abababababbabb";
+    let (matched, stats) = nfa.match_with_stats(input);
+    assert!(matched);
+    assert!(stats.states_dequeued <= (input.len() + 1) * nfa.num_states());
+    assert!(stats.max_frontier_size > 1);
+    assert!(stats.max_frontier_size < nfa.num_states() * 2);
+    assert_eq!(stats.closures_computed, input.len() + 1);
+}
+
+#[test]
+fn first_and_next_chars_test() {
+    let chars = |s: &str| s.chars().collect::<BTreeSet<char>>();
+
+    let nfa = nfa::NFA::from_regex("ab|cd").unwrap();
+    assert_eq!(nfa.first_chars(), chars("ac"));
+    assert_eq!(nfa.next_chars("a"), chars("b"));
+    assert_eq!(nfa.next_chars("ab"), chars(""));
+    assert_eq!(nfa.next_chars("x"), chars(""));
+
+    let nfa = nfa::NFA::from_regex("(a|b)*c?d").unwrap();
+    assert_eq!(nfa.first_chars(), chars("abcd"));
+    assert_eq!(nfa.next_chars("abc"), chars("d"));
+
+    // a restricted wildcard lists its chars, an unrestricted one can't
+    let options = nfa::CompileOptions {
+        wildcard_set: Some(chars("ACGT")),
+        ..Default::default()
+    };
+    let nfa = nfa::NFA::from_regex_with_options("A.", &options).unwrap();
+    assert_eq!(nfa.next_chars("A"), chars("ACGT"));
+    let nfa = nfa::NFA::from_regex("x|.").unwrap();
+    assert_eq!(nfa.first_chars(), chars("x"));
+}
+
+#[test]
+fn full_match_len_test() {
+    let nfa = nfa::NFA::from_regex("a+b?").unwrap();
+    for input in ["a", "aaab", "ab"] {
+        assert_eq!(
+            nfa.full_match_len(input),
+            Some(input.len()),
+            "input {input}"
+        );
+    }
+    for input in ["", "b", "abb", "xab"] {
+        assert_eq!(nfa.full_match_len(input), None, "input {input}");
+    }
+    // like every other length here, it counts chars, not bytes
+    let nfa = nfa::NFA::from_regex("é+").unwrap();
+    assert_eq!(nfa.full_match_len("éé"), Some(2));
+    let nfa = nfa::NFA::from_regex("a*").unwrap();
+    assert_eq!(nfa.full_match_len(""), Some(0));
+}
+
+#[test]
+fn class_set_operations_test() {
+    let cases = [
+        (
+            "[a-e&&[^b]]",
+            vec![
+                ("a", true),
+                ("b", false),
+                ("c", true),
+                ("e", true),
+                ("f", false),
+            ],
+        ),
+        ("[a-z&&[^aeiou]]+", vec![("rhythm", true), ("bat", false)]),
+        ("[a-z--aeiou]+", vec![("rhythm", true), ("bat", false)]),
+        // left to right: (a-z minus a-m) and x-z
+        (
+            "[a-z--a-m&&x-z]",
+            vec![("x", true), ("n", false), ("a", false)],
+        ),
+        ("[^0-9]", vec![("a", true), ("5", false), ("日", true)]),
+        ("[-a]", vec![("-", true), ("a", true), ("b", false)]),
+        (r"[a\]]", vec![("]", true), ("a", true)]),
+        (r"x\[a]", vec![("x[a]", true), ("xa", false)]),
+    ];
+    for (re, inputs) in cases {
+        let nfa = nfa::NFA::from_regex(re).unwrap();
+        for (input, expected) in inputs {
+            assert_eq!(nfa.full_match(input), expected, "re {re}, input {input}");
+        }
+    }
+
+    for re in [
+        "[a", "[]", "[z-a]", "[a&&]", "[&&a]", "[a&&&&b]", "[a--]", "[a[b]",
+    ] {
+        assert_eq!(
+            nfa::NFA::from_regex(re).err(),
+            Some(RegexError::InvalidClass),
+            "re {re}"
+        );
+    }
+    // a class with nothing left in it can never match
+    assert_eq!(
+        nfa::NFA::from_regex("[a&&b]").err(),
+        Some(RegexError::Empty)
+    );
+}
+
+#[test]
+fn from_regex_over_test() {
+    let binary = ['0', '1'];
+    let nfa = nfa::NFA::from_regex_over(".*", &binary).unwrap();
+    let expected: Vec<String> = (0..=3)
+        .flat_map(|len| {
+            (0..1 << len).map(move |n: usize| {
+                (0..len)
+                    .rev()
+                    .map(|bit| if n >> bit & 1 == 1 { '1' } else { '0' })
+                    .collect()
+            })
+        })
+        .collect();
+    assert_eq!(nfa.accepted_strings(3), expected);
+    assert_eq!(expected.len(), 15);
+
+    // chars outside of the alphabet are gone
+    let nfa = nfa::NFA::from_regex_over("[0-9]a?", &binary).unwrap();
+    assert_eq!(nfa.accepted_strings(5), vec!["0", "1"]);
+
+    let over = |re| nfa::NFA::from_regex_over(re, &binary).unwrap();
+    assert!(over(".*").is_equivalent(&over("(0|1)*")));
+    assert!(over("(0*1*)*").is_equivalent(&over("[01]*")));
+    assert!(over("0(10)*").is_equivalent(&over("(01)*0")));
+    assert!(!over("0*1*").is_equivalent(&over("(0|1)*")));
+    assert!(!over(".+").is_equivalent(&over(".*")));
+
+    // exact without an alphabet too, wildcards and classes are told apart
+    // by the chars one of them takes and the other doesn't
+    let nfa = |re: &str| nfa::NFA::from_regex(re).unwrap();
+    assert!(!nfa(".").is_equivalent(&nfa("[^x]")));
+    assert!(!nfa("[^a]*").is_equivalent(&nfa("[^b]*")));
+    assert!(!nfa("[\u{100}-\u{300}]").is_equivalent(&nfa("[\u{100}-\u{2ff}]")));
+    assert!(nfa("[^x]|x").is_equivalent(&nfa(".")));
+    let split = nfa("([\u{100}-\u{200}]|[\u{201}-\u{300}])+");
+    assert!(nfa("[\u{100}-\u{300}]+").is_equivalent(&split));
+    // the word boundaries only take a.x with a word char for x
+    let options = nfa::CompileOptions {
+        whole_word: true,
+        ..Default::default()
+    };
+    let whole = |re: &str| nfa::NFA::from_regex_with_options(re, &options).unwrap();
+    assert!(whole("a.").is_equivalent(&whole("a[^ !]")));
+    assert!(!whole("a.").is_equivalent(&whole("a[^_]")));
+}
+
+#[test]
+fn atomic_group_test() {
+    let nfa = nfa::NFA::from_regex("(?>ab)+").unwrap();
+    assert!(nfa.is_match("abab"));
+    assert!(nfa.is_match("ab"));
+    assert!(!nfa.is_match("aba"));
+
+    // atomic groups don't capture, so (b) is still group 0
+    let nfa = nfa::NFA::from_regex("(?>a|c)(b)").unwrap();
+    assert_eq!(nfa.captures("cb"), Some(vec![Some((1, 2))]));
+
+    assert_eq!(nfa::NFA::from_regex("(?>)").err(), Some(RegexError::Empty));
+    assert_eq!(
+        nfa::NFA::from_regex("(?>a").err(),
+        Some(RegexError::UnclosedParenthesis)
+    );
+    // escaped, it's a literal ( followed by a ? quantifier
+    assert!(nfa::NFA::from_regex(r"\(?>").unwrap().is_match(">"));
+}
+
+#[test]
+fn dead_state_test() {
+    // without c in the alphabet, the state before c is a dead end
+    // without c in the alphabet, the state before c (4) is a dead end, but
+    // the states after it can still reach the accepting state
+    let nfa = nfa::NFA::from_regex_over("ab|cd", &['a', 'b', 'd']).unwrap();
+    assert_eq!(nfa.dead_states(), vec![4]);
+    assert!(nfa.is_dead_state(4));
+    for id in [0, 5, 6, 8, 9] {
+        assert!(!nfa.is_dead_state(id), "state {id}");
+    }
+
+    // a wildcard that matches nothing can't be taken either
+    let options = nfa::CompileOptions {
+        wildcard_set: Some(BTreeSet::new()),
+        ..nfa::CompileOptions::default()
+    };
+    let nfa = nfa::NFA::from_regex_with_options("a|.", &options).unwrap();
+    assert_eq!(nfa.dead_states().len(), 1);
+
+    assert!(nfa::NFA::from_regex("ab|cd")
+        .unwrap()
+        .dead_states()
+        .is_empty());
+}
+
+#[test]
+fn matcher_checkpoint_test() {
+    let nfa = nfa::NFA::from_regex("abd|abc").unwrap();
+    let mut matcher = nfa.matcher();
+    matcher.feed_str("ab");
+    let checkpoint = matcher.checkpoint();
+
+    matcher.feed('c');
+    assert!(matcher.is_match());
+    assert_eq!(matcher.position(), 3);
+    assert_ne!(matcher.checkpoint(), checkpoint);
+
+    // as if the c was deleted
+    matcher.restore(checkpoint.clone());
+    assert_eq!(matcher.checkpoint(), checkpoint);
+    assert_eq!(matcher.position(), 2);
+    assert!(!matcher.is_match());
+    matcher.feed('d');
+    assert!(matcher.is_match());
+
+    // the char before matters to word boundaries, so it is restored too
+    let options = nfa::CompileOptions {
+        whole_word: true,
+        ..nfa::CompileOptions::default()
+    };
+    let nfa = nfa::NFA::from_regex_with_options("a.*", &options).unwrap();
+    let mut matcher = nfa.matcher();
+    matcher.feed('a');
+    let checkpoint = matcher.checkpoint();
+    matcher.feed(' ');
+    assert!(!matcher.is_match());
+    matcher.restore(checkpoint);
+    assert!(matcher.is_match());
+}
+
+#[test]
+fn to_regex_test() {
+    for re in [
+        "ab|c",
+        "(ab)*c?",
+        "a[b-d]+|x",
+        "a(b|c)*d",
+        ".*x.",
+        r"\(\*\)|[^a\]]",
+        "^a|b$",
+        "a{0}",
+        "(a|b)*abb",
+    ] {
+        let nfa = nfa::NFA::from_regex(re).unwrap();
+        let decompiled = nfa.to_regex().unwrap();
+        let roundtrip = nfa::NFA::from_regex(&decompiled).unwrap();
+        assert!(
+            nfa.is_equivalent(&roundtrip),
+            "re {re}, decompiled {decompiled}"
+        );
+    }
+    let nfa = nfa::NFA::from_regex("ab|c").unwrap();
+    assert_eq!(nfa.to_regex().unwrap(), "c|ab");
+
+    // a long chain of states, which each take one elimination
+    let literal: String = (0..2000).map(|i| char::from(b'a' + i as u8 % 26)).collect();
+    let nfa = nfa::NFA::from_regex(&literal).unwrap();
+    assert_eq!(nfa.to_regex().unwrap(), literal);
+
+    // the empty language and word boundaries have no pattern
+    assert_eq!(
+        nfa::NFA::from_regex_over("c", &['a']).unwrap().to_regex(),
+        None
+    );
+    let options = nfa::CompileOptions {
+        whole_word: true,
+        ..nfa::CompileOptions::default()
+    };
+    let nfa = nfa::NFA::from_regex_with_options("a", &options).unwrap();
+    assert_eq!(nfa.to_regex(), None);
+}
+
+#[test]
+fn wildcard_quantifier_fragment_test() {
+    // .{0,} and .{0,1} are built the generic way, from a wildcard fragment
+    // and quantifiers around it
+    let compact = nfa::NFA::from_regex(".*a.?b").unwrap();
+    let generic = nfa::NFA::from_regex(".{0,}a.{0,1}b").unwrap();
+    assert_eq!(compact.num_states(), 7);
+    assert!(compact.num_states() < generic.num_states());
+    for input in ["ab", "axb", "xxab", "xxaxb", "axxb", "a", "b", ""] {
+        assert_eq!(compact.is_match(input), generic.is_match(input), "{input}");
+        assert_eq!(
+            compact.find_iter(input),
+            generic.find_iter(input),
+            "{input}"
+        );
+    }
+
+    // a group around the loop still captures where it started
+    let nfa = nfa::NFA::from_regex("(.*b)(.+)").unwrap();
+    assert_eq!(
+        nfa.captures("aabcc"),
+        Some(vec![Some((0, 3)), Some((3, 5))])
+    );
+    let mut nfa = nfa::NFA::from_regex("x(.*)y").unwrap();
+    nfa.patch_group(0, "a.+").unwrap();
+    assert!(nfa.is_match("xabby"));
+    assert!(!nfa.is_match("xay"));
+}
+
+#[test]
+fn captures_iter_test() {
+    let nfa = nfa::NFA::from_regex("(.)(.)").unwrap();
+    assert_eq!(
+        nfa.captures_iter("abcde"),
+        vec![
+            vec![Some((0, 1)), Some((1, 2))],
+            vec![Some((2, 3)), Some((3, 4))],
+        ]
+    );
+
+    let nfa = nfa::NFA::from_regex("([a-z]+)=([0-9]*)").unwrap();
+    let pairs = nfa.captures_iter("x=1, yy=, z=42");
+    assert_eq!(
+        pairs,
+        vec![
+            vec![Some((0, 1)), Some((2, 3))],
+            vec![Some((5, 7)), Some((8, 8))],
+            vec![Some((10, 11)), Some((12, 14))],
+        ]
+    );
+    assert!(nfa.captures_iter("no pairs").is_empty());
+}
+
+#[test]
+fn too_deep_test() {
+    let deep_groups = format!("{}a{}", "(".repeat(5000), ")".repeat(5000));
+    let deep_classes = format!("{}a{}", "[".repeat(5000), "]".repeat(5000));
+    for re in [&deep_groups, &deep_classes] {
+        assert_eq!(nfa::NFA::from_regex(re).err(), Some(RegexError::TooDeep));
+        assert_eq!(parse_with_spans(re).err(), Some(RegexError::TooDeep));
+        assert_eq!(compile(re).1, vec![Diagnostic::Error(RegexError::TooDeep)]);
+    }
+
+    // the limit counts groups and classes together, and is configurable
+    let options = nfa::CompileOptions {
+        max_depth: 3,
+        ..nfa::CompileOptions::default()
+    };
+    assert!(nfa::NFA::from_regex_with_options("((a))|(b)", &options).is_ok());
+    assert!(nfa::NFA::from_regex_with_options("((a[b]))", &options).is_ok());
+    assert_eq!(
+        nfa::NFA::from_regex_with_options("((a[[b]]))", &options).err(),
+        Some(RegexError::TooDeep)
+    );
+    assert_eq!(
+        nfa::NFA::from_regex_with_options("((((a))))", &options).err(),
+        Some(RegexError::TooDeep)
+    );
+    // long but flat is fine
+    let long = "(a)".repeat(5000);
+    assert!(nfa::NFA::from_regex(&long)
+        .unwrap()
+        .is_match(&"a".repeat(5000)));
+
+    // and so is much longer, even though the Ast is a chain of 60,000
+    // concatenations
+    let long = "ab?c".repeat(15_000);
+    let ast = parse_with_spans(&long).unwrap();
+    assert_eq!(ast.clone(), ast);
+    assert!(ast.to_json().ends_with(r#""value":"c","children":[]}]}"#));
+    assert!(
+        ast.derivative('a')
+            .derivative('c')
+            .derivative('a')
+            .derivative('b')
+            .derivative('c')
+            .kind
+            != AstKind::Nothing
+    );
+    assert!(!ast.matches_by_derivative("acab"));
+    assert_eq!(
+        compile(&long).1,
+        vec![Diagnostic::Error(RegexError::TooManyStates)]
+    );
+}
+
+#[test]
+fn full_match_trailing_input_test() {
+    // an accepting state is reachable before all of the input is consumed,
+    // but a full match has to consume every char
+    let cases = [
+        ("a", "ab"),
+        ("a*", "aab"),
+        ("a|ab", "abc"),
+        (".?", "ab"),
+        ("(ab)+", "aba"),
+        ("a[bc]*", "abcd"),
+        ("a$", "ab"),
+        ("x.*", "\nx"),
+    ];
+    for (re, input) in cases {
+        let nfa = nfa::NFA::from_regex(re).unwrap();
+        assert!(!nfa.full_match(input), "re {re}, input {input}");
+        assert!(!nfa.is_match(input), "re {re}, input {input}");
+        assert_eq!(nfa.full_match_len(input), None, "re {re}, input {input}");
+        assert_eq!(nfa.captures(input), None, "re {re}, input {input}");
+        let mut matcher = nfa.matcher();
+        matcher.feed_str(input);
+        assert!(!matcher.is_match(), "re {re}, input {input}");
+
+        let set = RegexSet::new(&[re]).unwrap();
+        assert!(set.matching(input).is_empty(), "re {re}, input {input}");
+        let regex = Regex::new(re.to_string()).unwrap();
+        assert!(
+            !regex.fullMatch(input.to_string()),
+            "re {re}, input {input}"
+        );
+    }
+
+    // searching still finds them, except for a$ which has to end there
+    for (re, input) in cases.into_iter().filter(|&(re, _)| re != "a$") {
+        let nfa = nfa::NFA::from_regex(re).unwrap();
+        assert!(nfa.contains_match(input), "re {re}, input {input}");
+    }
+}
+
+#[test]
+fn merge_bisimilar_states_test() {
+    for (re, merged_states) in [("ab|ab|ab", 6), ("(ab)*|(ab)*", 7), ("x(a|a)y", 8)] {
+        let nfa = nfa::NFA::from_regex(re).unwrap();
+        let mut merged = nfa.clone();
+        merged.merge_bisimilar_states();
+        assert_eq!(merged.num_states(), merged_states, "re {re}");
+        assert!(merged.num_states() < nfa.num_states(), "re {re}");
+        assert!(merged.is_equivalent(&nfa), "re {re}");
+    }
+
+    // nothing to merge
+    let mut nfa = nfa::NFA::from_regex("abc").unwrap();
+    let num_states = nfa.num_states();
+    nfa.merge_bisimilar_states();
+    assert_eq!(nfa.num_states(), num_states);
+}
+
+#[test]
+fn compile_strict_test() {
+    let cases = [
+        (
+            "a{b}",
+            vec![
+                Diagnostic::UnescapedLiteral('{'),
+                Diagnostic::UnescapedLiteral('}'),
+            ],
+        ),
+        (
+            "a{1,x}",
+            vec![
+                Diagnostic::UnescapedLiteral('{'),
+                Diagnostic::UnescapedLiteral('}'),
+            ],
+        ),
+        ("a]", vec![Diagnostic::UnescapedLiteral(']')]),
+        ("a*+b", vec![Diagnostic::PossessiveQuantifier]),
+        ("a|a", vec![Diagnostic::DuplicateAlternative]),
+    ];
+    for (re, expected) in cases {
+        // compile only warns, strict mode refuses
+        let (nfa, diagnostics) = compile(re);
+        assert!(nfa.is_some(), "re {re}");
+        assert_eq!(diagnostics, expected, "re {re}");
+        let (nfa, diagnostics) = compile_strict(re);
+        assert!(nfa.is_none(), "re {re}");
+        assert_eq!(diagnostics, expected, "re {re}");
+    }
+    assert_eq!(
+        Diagnostic::UnescapedLiteral('{').to_string(),
+        r"warning: `{` is matched literally, escape it as `\{`"
+    );
+
+    // spelled out, the same regexes are fine
+    for re in [r"a\{b\}", "a{1,2}", r"a\]", "[a]", "a*b"] {
+        let (nfa, diagnostics) = compile_strict(re);
+        assert!(nfa.is_some(), "re {re}");
+        assert!(diagnostics.is_empty(), "re {re}");
+    }
+}
+
+#[test]
+fn matches_count_test() {
+    let regex = Regex::new("a+".to_string()).unwrap();
+    assert_eq!(regex.matchesCount("aa b aaa".to_string()), 2);
+    assert_eq!(regex.matchesCount("bbb".to_string()), 0);
+
+    // agrees with find_iter, including empty matches
+    let cases = [
+        ("a+", "aa b aaa"),
+        ("a*", "bab"),
+        ("a*", ""),
+        ("b|ab", "abab"),
+    ];
+    for (re, input) in cases {
+        let nfa = nfa::NFA::from_regex(re).unwrap();
+        assert_eq!(
+            nfa.matches_count(input),
+            nfa.find_iter(input).len(),
+            "re {re} input {input}"
+        );
+    }
+    let regex = Regex::new("a*".to_string()).unwrap();
+    assert_eq!(regex.matchesCount("bab".to_string()), 4);
+
+    let regex = Regex::newBounded("a".to_string(), 2).unwrap();
+    assert_eq!(regex.matchesCount("aaa".to_string()), 0);
+    assert!(regex.inputTooLong());
+}
+
+#[test]
+fn find_line_col_test() {
+    let input = "first line\nsecond line\n  third: abc\n";
+    let nfa = nfa::NFA::from_regex("abc").unwrap();
+    assert_eq!(nfa.find_line_col(input), Some((2, 9)));
+    assert_eq!(nfa.find_line_col("no match\nhere"), None);
+    assert_eq!(nfa::line_col(input, 0), (0, 0));
+    // the \n is the end of its line, the next char starts a new one
+    assert_eq!(nfa::line_col(input, 10), (0, 10));
+    assert_eq!(nfa::line_col(input, 11), (1, 0));
+
+    let regex = Regex::new("line".to_string()).unwrap();
+    assert_eq!(
+        regex.findLineCol(input.to_string()),
+        r#"{"line":0,"column":6}"#
+    );
+    assert_eq!(
+        regex.findLineCol("\n\nline".to_string()),
+        r#"{"line":2,"column":0}"#
+    );
+    assert_eq!(regex.findLineCol("lin\ne".to_string()), "null");
+    // 😀 is two utf-16 code units, but only on its own line
+    assert_eq!(
+        regex.findLineCol("😀\n😀 line".to_string()),
+        r#"{"line":1,"column":3}"#
+    );
+}
+
+#[test]
+fn from_postfix_test() {
+    let postfix = vec![Token::Letter('a'), Token::Letter('b'), Token::Concatenation];
+    let nfa = nfa::NFA::from_postfix(postfix).unwrap();
+    assert!(nfa.is_match("ab"));
+    assert!(!nfa.is_match("a"));
+    assert!(!nfa.is_match("abb"));
+
+    // the same NFA as the parsed regex
+    for re in ["ab", "(a|b)*c", "a{2,3}(b)?", "^.+$"] {
+        let postfix = crate::parse::calc_postfix(
+            crate::parse::parse_re_to_tokens(re, nfa::DEFAULT_MAX_DEPTH).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            nfa::NFA::from_postfix(postfix),
+            nfa::NFA::from_regex(re),
+            "re {re}"
+        );
+    }
+
+    let invalid = [
+        (vec![], RegexError::Empty),
+        (vec![Token::Concatenation], RegexError::MissingOperand),
+        (
+            vec![Token::Letter('a'), Token::Union],
+            RegexError::MissingOperand,
+        ),
+        (
+            vec![Token::Letter('a'), Token::Letter('b')],
+            RegexError::InvalidPostfix,
+        ),
+        (
+            vec![
+                Token::OpenParenthesis,
+                Token::Letter('a'),
+                Token::CloseParenthesis,
+            ],
+            RegexError::InvalidPostfix,
+        ),
+        (
+            vec![Token::Letter('a'), Token::Group(1)],
+            RegexError::InvalidPostfix,
+        ),
+        (
+            vec![Token::Letter('a'), Token::RepetitionQuantifier(3, Some(2))],
+            RegexError::InvalidRepetition,
+        ),
+    ];
+    for (postfix, error) in invalid {
+        assert_eq!(
+            nfa::NFA::from_postfix(postfix.clone()),
+            Err(error),
+            "{postfix:?}"
+        );
+    }
+}
+
+#[test]
+fn astral_plane_test() {
+    // a wildcard is one code point, even one that takes two utf-16 units
+    let nfa = nfa::NFA::from_regex(".").unwrap();
+    assert!(nfa.is_match("😀"));
+    assert!(nfa.is_match("𝄞"));
+    assert!(!nfa.is_match("😀😀"));
+    assert!(nfa::NFA::from_regex("a.{2}b").unwrap().is_match("a😀𝄞b"));
+
+    // ranges of and around astral chars
+    let nfa = nfa::NFA::from_regex("[😀-😂]+").unwrap();
+    assert!(nfa.is_match("😀😁😂"));
+    assert!(!nfa.is_match("😃"));
+    assert_eq!(nfa.find_iter("a😁😁b😂"), vec![(1, 3), (4, 5)]);
+    let nfa = nfa::NFA::from_regex("[a-𝄞]").unwrap();
+    assert!(!nfa.is_match("😀"));
+    assert!(nfa.is_match("𝄞"));
+    assert!(nfa.is_match("\u{E000}"));
+    let nfa = nfa::NFA::from_regex("[^a]").unwrap();
+    assert!(nfa.is_match("😀"));
+    assert!(nfa.is_match("\u{10FFFF}"));
+    assert!(!nfa.is_match("a"));
+
+    // a range over the surrogates only holds the chars around them
+    let class = CharClass::new(vec![('\u{D000}', '\u{F000}')]);
+    assert_eq!(class.chars().count(), 0xF000 - 0xD000 + 1 - 0x800);
+    assert!(class.contains('\u{D7FF}'));
+    assert!(class.contains('\u{E000}'));
+    assert_eq!(
+        CharClass::new(vec![('\0', '\u{D7FF}'), ('\u{E000}', char::MAX)]),
+        CharClass::new(vec![('\0', char::MAX)])
+    );
+    assert_eq!(
+        CharClass::new(vec![('😀', '😀')]).complement().ranges(),
+        &[('\0', '\u{1F5FF}'), ('\u{1F601}', char::MAX)]
+    );
+
+    // the alphabet, captures and decompiling keep whole code points
+    let nfa = nfa::NFA::from_regex_over("[😀-😂]*", &['😀', '😃', 'a']).unwrap();
+    assert_eq!(
+        nfa.accepted_strings(2),
+        vec!["".to_string(), "😀".to_string(), "😀😀".to_string()]
+    );
+    let nfa = nfa::NFA::from_regex("(😀+)(.)").unwrap();
+    assert_eq!(
+        nfa.captures("😀😀𝄞"),
+        Some(vec![Some((0, 2)), Some((2, 3))])
+    );
+    let nfa = nfa::NFA::from_regex("[😀-😂𝄞]").unwrap();
+    let decompiled = nfa::NFA::from_regex(&nfa.to_regex().unwrap()).unwrap();
+    for c in ['😀', '😁', '😂', '𝄞', '😃', 'a'] {
+        assert_eq!(
+            decompiled.is_match(&c.to_string()),
+            nfa.is_match(&c.to_string()),
+            "char {c}"
+        );
+    }
+}
+
+#[test]
+fn split_inclusive_test() {
+    let newline = nfa::NFA::from_regex("\n").unwrap();
+    assert_eq!(
+        newline.split_inclusive("one\ntwo\nthree"),
+        vec!["one\n", "two\n", "three"]
+    );
+    // like str::split_inclusive, no empty piece after a trailing match
+    assert_eq!(
+        newline.split_inclusive("one\ntwo\n"),
+        vec!["one\n", "two\n"]
+    );
+    assert_eq!(newline.split_inclusive("\n\n"), vec!["\n", "\n"]);
+    assert_eq!(newline.split_inclusive(""), Vec::<&str>::new());
+    for input in ["one\ntwo\nthree", "one\ntwo\n", "\n\n", "x"] {
+        let expected: Vec<&str> = input.split_inclusive('\n').collect();
+        assert_eq!(newline.split_inclusive(input), expected, "input {input:?}");
+    }
+
+    let nfa = nfa::NFA::from_regex(", *").unwrap();
+    assert_eq!(nfa.split_inclusive("a, b,c"), vec!["a, ", "b,", "c"]);
+    // empty matches don't make empty pieces, and nothing is lost
+    let nfa = nfa::NFA::from_regex("a*").unwrap();
+    assert_eq!(nfa.split_inclusive("bab"), vec!["ba", "b"]);
+    assert_eq!(nfa.split_inclusive("bab").concat(), "bab");
+
+    let regex = Regex::new(";".to_string()).unwrap();
+    assert_eq!(
+        regex.splitInclusive("a;b".to_string()),
+        vec!["a;".to_string(), "b".to_string()]
+    );
+}
+
+#[test]
+fn dead_alternative_test() {
+    let cases = [
+        ("a|b$c", vec!["b$c"]),
+        ("x(a^|b)y", vec!["a^"]),
+        ("(a|b$c)|d", vec!["b$c"]),
+        ("a$.|^b|b^", vec!["a$.", "b^"]),
+    ];
+    for (re, dead) in cases {
+        let expected: Vec<Diagnostic> = dead
+            .into_iter()
+            .map(|alternative| Diagnostic::DeadAlternative(alternative.to_string()))
+            .collect();
+        let (nfa, diagnostics) = compile(re);
+        assert!(nfa.is_some(), "re {re}");
+        assert_eq!(diagnostics, expected, "re {re}");
+    }
+    assert_eq!(
+        Diagnostic::DeadAlternative("b$c".to_string()).to_string(),
+        "warning: alternative `b$c` can never match"
+    );
+
+    // anchors in the right places, and $ or ^ with nothing around them
+    for re in ["a|^b", "a|b$", "^a$|b", "a|$^", "a|$$", "(a|b)$c"] {
+        assert_eq!(compile(re).1, vec![], "re {re}");
+    }
+
+    let matches_nothing = |re: &str| nfa::NFA::from_regex(re).unwrap().matches_nothing();
+    assert!(matches_nothing("a$b"));
+    assert!(matches_nothing("a^"));
+    assert!(matches_nothing("(a$|$b)c"));
+    assert!(!matches_nothing("$^"));
+    assert!(!matches_nothing("a*$b*"));
+    assert!(!matches_nothing("(a$|b)c"));
+}
+
+#[test]
+fn regex_try_from_test() {
+    let regex = Regex::try_from("a|b").unwrap();
+    assert!(regex.isMatch("b".to_string()));
+    let regex = Regex::try_from("a+".to_string()).unwrap();
+    assert!(regex.isMatch("aaa".to_string()));
+
+    assert_eq!(
+        Regex::try_from("a|").err(),
+        Some(RegexError::MissingOperand)
+    );
+    assert_eq!(
+        Regex::try_from("a)".to_string()).err(),
+        Some(RegexError::UnmatchedParenthesis)
+    );
+
+    // usable wherever a TryFrom conversion is expected
+    fn compile_all<T: TryInto<Regex, Error = RegexError>>(
+        patterns: Vec<T>,
+    ) -> Result<Vec<Regex>, RegexError> {
+        patterns.into_iter().map(T::try_into).collect()
+    }
+    assert_eq!(
+        compile_all(vec!["a", "b*"]).map(|regexes| regexes.len()),
+        Ok(2)
+    );
+    assert_eq!(
+        compile_all(vec!["a".to_string(), "(".to_string()]).err(),
+        Some(RegexError::UnclosedParenthesis)
+    );
+}
+
+#[cfg(feature = "graphemes")]
+#[test]
+fn grapheme_spans_test() {
+    // e and a combining acute accent (3 bytes) is one grapheme
+    let accented = "e\u{301}";
+    let nfa = nfa::NFA::from_regex(".").unwrap();
+    assert_eq!(nfa.find_graphemes(accented), Some((0, 3)));
+    let input = format!("x{accented}y");
+    let nfa = nfa::NFA::from_regex("x.y").unwrap();
+    assert_eq!(nfa.find_graphemes(&input), Some((0, 5)));
+    // by chars, . is only the e and the accent is left over
+    assert_eq!(nfa.find(&input), None);
+
+    // a match can't end in the middle of a grapheme
+    let nfa = nfa::NFA::from_regex("e").unwrap();
+    assert_eq!(nfa.find_graphemes(accented), None);
+    assert_eq!(nfa.find_graphemes(&format!("{accented}e")), Some((3, 4)));
+    let nfa = nfa::NFA::from_regex("e\u{301}+").unwrap();
+    assert_eq!(nfa.find_graphemes(&input), Some((1, 4)));
+
+    // a thumbs up with a skin tone is 2 chars and 8 bytes
+    let input = "a👍🏽b";
+    let nfa = nfa::NFA::from_regex("[^ab]").unwrap();
+    let spans = nfa.find_iter_graphemes(input);
+    assert_eq!(spans, vec![(1, 9)]);
+    assert_eq!(&input[spans[0].0..spans[0].1], "👍🏽");
+    let nfa = nfa::NFA::from_regex(".").unwrap();
+    assert_eq!(
+        nfa.find_iter_graphemes(input),
+        vec![(0, 1), (1, 9), (9, 10)]
+    );
+    let nfa = nfa::NFA::from_regex("a*").unwrap();
+    assert_eq!(nfa.find_iter_graphemes("👍🏽a"), vec![(0, 0), (8, 9), (9, 9)]);
+}
+
+#[test]
+fn accepts_empty_test() {
+    for (re, accepts) in [
+        ("a*", true),
+        ("a?", true),
+        ("a+", false),
+        ("a", false),
+        ("(a|b*)c?", true),
+        ("a{0}", true),
+        ("a{0,2}b{1,}", false),
+        ("^$", true),
+        ("^a*$", true),
+        ("$a*^", true),
+        ("^a$", false),
+    ] {
+        let nfa = nfa::NFA::from_regex(re).unwrap();
+        assert_eq!(nfa.accepts_empty(), accepts, "re {re}");
+        assert_eq!(nfa.accepts_empty(), nfa.is_match(""), "re {re}");
+    }
+    let options = nfa::CompileOptions {
+        whole_word: true,
+        ..nfa::CompileOptions::default()
+    };
+    let nfa = nfa::NFA::from_regex_with_options("a*", &options).unwrap();
+    assert!(!nfa.accepts_empty());
+}
+
+#[test]
+fn repeat_strategy_test() {
+    let counter = nfa::CompileOptions {
+        repeat_strategy: nfa::RepeatStrategy::Counter,
+        ..nfa::CompileOptions::default()
+    };
+    let eager = nfa::NFA::from_regex("a{2,5}").unwrap();
+    let compact = nfa::NFA::from_regex_with_options("a{2,5}", &counter).unwrap();
+    assert!(compact.num_states() < eager.num_states());
+    assert!(compact.has_counters());
+    assert!(!eager.has_counters());
+    for len in 0..8 {
+        let input = "a".repeat(len);
+        assert_eq!(
+            compact.is_match(&input),
+            (2..=5).contains(&len),
+            "len {len}"
+        );
+        assert_eq!(
+            compact.is_match(&input),
+            eager.is_match(&input),
+            "len {len}"
+        );
+    }
+    assert_eq!(compact.find_iter("aaaaaaa b a"), vec![(0, 5), (5, 7)]);
+
+    // everything else sees the counters expanded, and gets the same answers
+    for re in ["(ab){2,3}c", "(a|b{1,2}){3}", "x(y){2,}", "^a{0,3}$"] {
+        let eager = nfa::NFA::from_regex(re).unwrap();
+        let compact = nfa::NFA::from_regex_with_options(re, &counter).unwrap();
+        assert!(compact.num_states() < eager.num_states(), "re {re}");
+        let expanded = compact.expand_counters();
+        assert!(!expanded.has_counters());
+        for input in [
+            "", "ababc", "abababc", "abbab", "bbbbbb", "xyyy", "aaa", "aaaa",
+        ] {
+            assert_eq!(compact.is_match(input), eager.is_match(input), "re {re}");
+            assert_eq!(expanded.is_match(input), eager.is_match(input), "re {re}");
+            assert_eq!(compact.captures(input), eager.captures(input), "re {re}");
+        }
+        assert_eq!(compact.shortest_match(), eager.shortest_match(), "re {re}");
+        assert_eq!(compact.max_length(), eager.max_length(), "re {re}");
+        assert!(compact.is_equivalent(&eager), "re {re}");
+    }
+}
+
+#[test]
+fn acceptance_map_test() {
+    let nfa = nfa::NFA::from_regex("ab").unwrap();
+    assert_eq!(
+        nfa.acceptance_map("xabab"),
+        vec![false, true, false, true, false]
+    );
+    assert_eq!(nfa.acceptance_map(""), Vec::<bool>::new());
+
+    // a pattern that matches empty fires everywhere
+    let nfa = nfa::NFA::from_regex("a*").unwrap();
+    assert_eq!(nfa.acceptance_map("bab"), vec![true, true, true]);
+
+    // anchors still apply at each start
+    let nfa = nfa::NFA::from_regex("^a").unwrap();
+    assert_eq!(nfa.acceptance_map("aaa"), vec![true, false, false]);
+}
+
+#[test]
+fn single_transition_state_test() {
+    // a trie for one word is a chain of states with one edge each, and
+    // the edges of each are exactly that one, with no phantom second
+    let nfa = nfa::NFA::from_literals(&["ab"]);
+    let edges = |id: usize| nfa.edges_from(id);
+    let first = (0..nfa.num_states())
+        .find(|&id| edges(id).iter().any(|(label, _)| label == "a"))
+        .unwrap();
+    let after_a = edges(first);
+    assert_eq!(after_a.len(), 1);
+    let after_b = edges(after_a[0].1);
+    assert_eq!(after_b.len(), 1);
+    assert_eq!(after_b[0].0, "b");
+    assert!(edges(after_b[0].1).is_empty());
+
+    // same for the letter and epsilon states of a thompson NFA
+    let nfa = nfa::NFA::from_regex("a(b|c)*d").unwrap();
+    let edges: Vec<Vec<(String, usize)>> =
+        (0..nfa.num_states()).map(|id| nfa.edges_from(id)).collect();
+    for label in ["a", "b", "c", "d"] {
+        let state = edges.iter().find(|e| e.iter().any(|(l, _)| l == label));
+        assert_eq!(state.unwrap().len(), 1, "{label}");
+    }
+    assert!(edges.iter().any(|e| e.len() == 1 && e[0].0 == "ε"));
+    assert_eq!(
+        edges.iter().map(Vec::len).sum::<usize>(),
+        nfa.num_transitions()
+    );
+}
+
+#[test]
+fn dfa_bytes_test() {
+    let inputs = [
+        "", "a", "ab", "abb", "aabb", "babb", "abab", "ab ba", "x", "cat", "the cat", "a-b", "aaa",
+        "ÿé", "abcabc",
+    ];
+    for re in [
+        "(a|b)*abb",
+        "[a-c]+|x",
+        ".*b",
+        "^a|b$",
+        "(ab){2,}|[^a]",
+        "a?b?c?",
+    ] {
+        let nfa = nfa::NFA::from_regex(re).unwrap();
+        let dfa = nfa.to_dfa().unwrap();
+        for input in inputs {
+            assert_eq!(
+                dfa.is_match(input),
+                nfa.is_match(input),
+                "re {re} input {input}"
+            );
+        }
+        let bytes = dfa.to_bytes();
+        assert_eq!(nfa::DFA::from_bytes(&bytes), Ok(dfa), "re {re}");
+    }
+
+    // word boundaries need to know whether the last char was a word char
+    let options = nfa::CompileOptions {
+        whole_word: true,
+        ..Default::default()
+    };
+    for re in ["cat", "[a-z]+", "a.*", "a.*b| "] {
+        let nfa = nfa::NFA::from_regex_with_options(re, &options).unwrap();
+        let dfa = nfa.to_dfa().unwrap();
+        for input in inputs.iter().chain(&["a ", "a b", " ", "ab_", "a é"]) {
+            assert_eq!(
+                dfa.is_match(input),
+                nfa.is_match(input),
+                "re {re} input {input}"
+            );
+        }
+    }
+
+    // minimized: the textbook DFA for (a|b)*abb has 4 states
+    let dfa = nfa::NFA::from_regex("(a|b)*abb").unwrap().to_dfa().unwrap();
+    assert_eq!(dfa.num_states(), 4);
+    let bytes = dfa.to_bytes();
+    assert_eq!(&bytes[..5], b"NFAD\x01");
+
+    // the same DFA from counted loops
+    let options = nfa::CompileOptions {
+        repeat_strategy: nfa::RepeatStrategy::Counter,
+        ..Default::default()
+    };
+    let counted = nfa::NFA::from_regex_with_options("a{2,4}", &options).unwrap();
+    let eager = nfa::NFA::from_regex("a{2,4}").unwrap();
+    assert_eq!(counted.to_dfa(), eager.to_dfa());
+
+    // loading checks the bytes
+    let mut newer = bytes.clone();
+    newer[4] = 2;
+    assert_eq!(
+        nfa::DFA::from_bytes(&newer),
+        Err(RegexError::UnsupportedDfaVersion(2))
+    );
+    for cut in 0..bytes.len() {
+        assert_eq!(
+            nfa::DFA::from_bytes(&bytes[..cut]),
+            Err(RegexError::InvalidDfa)
+        );
+    }
+    let mut longer = bytes.clone();
+    longer.push(0);
+    assert_eq!(nfa::DFA::from_bytes(&longer), Err(RegexError::InvalidDfa));
+    let mut bad_state = bytes.clone();
+    // the table is 4 states by 2 classes (a and b), before the 1 byte bitset
+    let first_entry = bytes.len() - 1 - 4 * 2;
+    bad_state[first_entry] = 4;
+    assert_eq!(
+        nfa::DFA::from_bytes(&bad_state),
+        Err(RegexError::InvalidDfa)
+    );
+    assert_eq!(nfa::DFA::from_bytes(b"NFA"), Err(RegexError::InvalidDfa));
+}
+
+// also in the browser, the counts are for the playground
+#[cfg_attr(not(target_arch = "wasm32"), test)]
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen_test::wasm_bindgen_test)]
+fn regex_size_test() {
+    // a and b are two states each, joined by an epsilon
+    let regex = Regex::new("ab".to_string()).unwrap();
+    assert_eq!(regex.numStates(), 4);
+    assert_eq!(regex.numTransitions(), 3);
+
+    let small = Regex::new("a{10}".to_string()).unwrap();
+    let large = Regex::new("(a{10}){10}".to_string()).unwrap();
+    assert!(large.numStates() >= 10 * small.numStates());
+    assert!(large.numTransitions() >= 10 * small.numTransitions());
+}
+
+#[test]
+fn match_failure_point_test() {
+    let failure =
+        |re: &str, input: &str| nfa::NFA::from_regex(re).unwrap().match_failure_point(input);
+
+    assert_eq!(failure("abc", "abx"), Some(2));
+    assert_eq!(failure("abc", "xbc"), Some(0));
+    // the input ending too soon fails at its end
+    assert_eq!(failure("abc", "ab"), Some(2));
+    assert_eq!(failure("ab", "abc"), Some(2));
+    assert_eq!(failure("abc", "abc"), None);
+    assert_eq!(failure("a*b", "aaab"), None);
+    // a path is still alive as long as any branch is
+    assert_eq!(failure("ab*c|abd", "abbx"), Some(3));
+    assert_eq!(failure("[0-9]+-[0-9]+", "12-3a"), Some(4));
+}
+
+#[test]
+fn replace_preserving_case_test() {
+    let nfa = nfa::NFA::from_regex("[cC]olor|COLOR").unwrap();
+    assert_eq!(
+        nfa.replace_all_preserving_case("color, Color and COLOR", "colour"),
+        "colour, Colour and COLOUR"
+    );
+    // the replacement is recased too when it isn't lowercase
+    assert_eq!(nfa.replace_all_preserving_case("Color", "HUE"), "Hue");
+
+    // mixed case, and text without cased chars, keep the replacement as is
+    let nfa = nfa::NFA::from_regex("[a-zA-Z0-9]+").unwrap();
+    assert_eq!(nfa.replace_all_preserving_case("cOlOr", "Hue"), "Hue");
+    assert_eq!(nfa.replace_all_preserving_case("42", "x"), "x");
+    // one capital letter is title case
+    assert_eq!(nfa.replace_all_preserving_case("A b", "word"), "Word word");
+
+    let regex = Regex::new("[cC]olor|COLOR".to_string()).unwrap();
+    assert_eq!(
+        regex.replaceAllPreservingCase("COLOR me".to_string(), "colour".to_string()),
+        "COLOUR me"
+    );
+}
+
+#[test]
+fn for_each_accepted_test() {
+    let nfa = nfa::NFA::from_regex("(a|b)*c").unwrap();
+    let mut first = vec![];
+    nfa.for_each_accepted(10, &['a', 'b', 'c'], |s| {
+        first.push(s.to_string());
+        first.len() < 3
+    });
+    assert_eq!(first, vec!["c", "ac", "bc"]);
+
+    // the same strings as accepted_strings, in the same order
+    let nfa = nfa::NFA::from_regex_over("0(1|0)*|1?", &['0', '1']).unwrap();
+    let mut all = vec![];
+    nfa.for_each_accepted(3, &['0', '1'], |s| {
+        all.push(s.to_string());
+        true
+    });
+    assert_eq!(all, nfa.accepted_strings(3));
+
+    // a finite language stops deepening once nothing is left
+    let nfa = nfa::NFA::from_regex("ab|c").unwrap();
+    let mut calls = 0;
+    nfa.for_each_accepted(usize::MAX, &['a', 'b', 'c'], |_| {
+        calls += 1;
+        true
+    });
+    assert_eq!(calls, 2);
+}
+
+#[test]
+fn canonical_hash_test() {
+    let hash = |re: &str| nfa::NFA::from_regex(re).unwrap().canonical_hash();
+
+    for (left, right) in [
+        ("a|b", "b|a"),
+        ("a|a", "a"),
+        ("aa*", "a+"),
+        ("(ab)", "ab"),
+        ("a{2}", "aa"),
+        ("ab|ab|ab", "ab"),
+    ] {
+        assert_eq!(hash(left), hash(right), "{left} and {right}");
+    }
+    // the same language with a class instead of letters isn't the same
+    // automaton
+    for (left, right) in [
+        ("a", "b"),
+        ("a*", "a+"),
+        ("ab", "ba"),
+        ("a.", "a[^x]"),
+        ("[ab]c", "(a|b)c"),
+    ] {
+        assert_ne!(hash(left), hash(right), "{left} and {right}");
+    }
+
+    // Hash and Eq are structural, so a cache keyed by NFA tells the two
+    // spellings apart while the canonical hash doesn't
+    let mut cache = std::collections::HashSet::new();
+    cache.insert(nfa::NFA::from_regex("a|b").unwrap());
+    assert!(cache.contains(&nfa::NFA::from_regex("a|b").unwrap()));
+    assert!(!cache.contains(&nfa::NFA::from_regex("b|a").unwrap()));
+}
+
+#[test]
+fn first_char_set_test() {
+    let set = |re: &str| {
+        nfa::NFA::from_regex(re)
+            .unwrap()
+            .first_char_set()
+            .map(|set| set.iter().collect::<String>())
+    };
+    assert_eq!(set("(cat|dog)s?"), Some("cd".to_string()));
+    assert_eq!(set("a*b"), Some("ab".to_string()));
+    // a match could be empty, or start with anything
+    assert_eq!(set("a*"), None);
+    assert_eq!(set("^$"), None);
+    assert_eq!(set(".b"), None);
+    assert_eq!(set("[^a]x"), None);
+    // the word boundaries come before the first char
+    let options = nfa::CompileOptions {
+        whole_word: true,
+        ..Default::default()
+    };
+    let nfa = nfa::NFA::from_regex_with_options("foo", &options).unwrap();
+    assert_eq!(nfa.first_char_set(), Some(&BTreeSet::from(['f'])));
+    assert!(nfa.contains_match("a foo"));
+    assert!(!nfa.contains_match("afoo"));
+
+    // the early out never changes an answer
+    let inputs = [
+        "", "cat", "cats", "dog", "xcat", "a", "b", "ab", "aab", "foo", "xx foo",
+    ];
+    for re in ["(cat|dog)s?", "a*b", "foo", "a*", "c{2,3}|d"] {
+        let nfa = nfa::NFA::from_regex(re).unwrap();
+        for input in inputs {
+            let mut matcher = nfa.matcher();
+            matcher.feed_str(input);
+            assert_eq!(nfa.is_match(input), matcher.is_match(), "{re} {input}");
+            assert_eq!(
+                nfa.contains_match(input),
+                nfa.find(input).is_some(),
+                "{re} {input}"
+            );
+        }
+    }
+
+    // an input that doesn't start with any of them is turned down without
+    // a search, so nothing is dequeued
+    let nfa = nfa::NFA::from_regex("(cat|dog)s?").unwrap();
+    let (matched, stats) = nfa.match_with_stats(&"x".repeat(20_000));
+    assert!(!matched);
+    assert_eq!(stats, nfa::MatchStats::default());
+    let (matched, stats) = nfa.match_with_stats("cab");
+    assert!(!matched);
+    assert!(stats.states_dequeued > 0);
+}
+
+#[test]
+fn is_valid_prefix_test() {
+    let nfa = nfa::NFA::from_regex("[0-9]{3}-[0-9]{4}").unwrap();
+    for prefix in ["", "5", "555", "555-", "555-12", "555-1234"] {
+        assert!(nfa.is_valid_prefix(prefix), "{prefix}");
+    }
+    for wrong in ["a", "55a", "5555", "555-12345", "555--"] {
+        assert!(!nfa.is_valid_prefix(wrong), "{wrong}");
+    }
+    // only a full match is a match
+    assert!(!nfa.is_match("555-"));
+
+    // a path that is still alive but can never finish doesn't count: after
+    // b, the ^ in b^c can't hold anymore
+    let nfa = nfa::NFA::from_regex("a|b^c").unwrap();
+    let mut matcher = nfa.matcher();
+    matcher.feed('b');
+    assert!(!matcher.is_dead());
+    assert!(!matcher.can_still_match());
+    assert!(!nfa.is_valid_prefix("b"));
+    assert!(nfa.is_valid_prefix(""));
+
+    let regex = Regex::new("[0-9]{3}-[0-9]{4}".to_string()).unwrap();
+    assert!(regex.isValidPrefix("12".to_string()));
+    assert!(!regex.isValidPrefix("12x".to_string()));
+}
+
+#[test]
+fn baked_fold_test() {
+    let options = nfa::CompileOptions {
+        fold: Some(nfa::case_fold),
+        ..Default::default()
+    };
+    let nfa = nfa::NFA::from_regex_with_options("hello", &options).unwrap();
+    for input in ["hello", "HELLO", "HeLlO"] {
+        assert!(nfa.is_match(input), "{input}");
+    }
+    assert!(!nfa.is_match("help"));
+    // nothing is folded at match time, the plain NFA still cares
+    assert!(!nfa::NFA::from_regex("hello").unwrap().is_match("HELLO"));
+
+    // classes take the other case too, and letters without one stay put
+    let nfa = nfa::NFA::from_regex_with_options("[a-c]+-1", &options).unwrap();
+    assert!(nfa.is_match("aBC-1"));
+    assert!(!nfa.is_match("aBD-1"));
+    let nfa = nfa::NFA::from_regex_with_options("straße", &options).unwrap();
+    assert!(nfa.is_match("STRAẞE"));
+
+    // any fold works, e.g. one that strips accents
+    fn strip_accents(c: char) -> String {
+        match c {
+            'é' | 'è' | 'ê' | 'É' => "e".to_string(),
+            _ => c.to_string(),
+        }
+    }
+    let options = nfa::CompileOptions {
+        fold: Some(strip_accents),
+        ..Default::default()
+    };
+    let nfa = nfa::NFA::from_regex_with_options("cafe", &options).unwrap();
+    assert!(nfa.is_match("café"));
+    assert!(nfa.is_match("cafÉ"));
+    assert!(!nfa.is_match("CAFE"));
+    let nfa = nfa::NFA::from_regex_with_options("caf[é]", &options).unwrap();
+    assert!(nfa.is_match("cafe"));
+    assert!(nfa.is_match("cafè"));
+
+    // the groups of each fold are kept for the next compile, without
+    // mixing up the folds
+    let case_insensitive = nfa::CompileOptions {
+        fold: Some(nfa::case_fold),
+        ..Default::default()
+    };
+    let nfa = nfa::NFA::from_regex_with_options("cafe", &case_insensitive).unwrap();
+    assert!(nfa.is_match("CAFE"));
+    assert!(!nfa.is_match("café"));
+    let nfa = nfa::NFA::from_regex_with_options("cafe", &options).unwrap();
+    assert!(nfa.is_match("café"));
+    assert!(!nfa.is_match("CAFE"));
+}
+
+#[test]
+fn multiline_test() {
+    let multiline = nfa::CompileOptions {
+        multiline: true,
+        ..Default::default()
+    };
+    let compile = |re: &str, options: &nfa::CompileOptions| {
+        nfa::NFA::from_regex_with_options(re, options).unwrap()
+    };
+
+    assert!(compile("^b", &multiline).contains_match("a\nb"));
+    assert!(!compile("^b", &Default::default()).contains_match("a\nb"));
+    assert_eq!(compile("a$", &multiline).find("a\nb"), Some((0, 1)));
+    assert_eq!(compile("a$", &Default::default()).find("a\nb"), None);
+    // every line, and the input's own start and end still count
+    assert_eq!(
+        compile("^[a-z]+$", &multiline).find_iter("ab\ncd\n\nef"),
+        vec![(0, 2), (3, 5), (7, 9)]
+    );
+    assert!(compile("^a$\n^b$", &multiline).is_match("a\nb"));
+    assert!(!compile("^a$b", &multiline).is_match("ab"));
+    // line anchors can't anchor a match to the start of the input
+    assert!(!compile("^a", &multiline).is_anchored_start());
+    assert_eq!(compile("^a", &multiline).to_regex(), None);
+
+    let nfa = compile("x|^b$", &multiline);
+    let dfa = nfa.to_dfa().unwrap();
+    for input in ["b", "x", "a\nb", "b\n", "\nb\n", "ab"] {
+        let mut matcher = nfa.matcher();
+        matcher.feed_str(input);
+        assert_eq!(matcher.is_match(), nfa.is_match(input), "{input:?}");
+        assert_eq!(dfa.is_match(input), nfa.is_match(input), "{input:?}");
+    }
+}
+
+#[test]
+fn verbose_test() {
+    let verbose = nfa::CompileOptions {
+        verbose: true,
+        ..Default::default()
+    };
+    let compile = |re: &str| nfa::NFA::from_regex_with_options(re, &verbose).unwrap();
+
+    assert!(compile("a b c").is_match("abc"));
+    assert!(!compile("a b c").is_match("a b c"));
+    assert!(compile("a # comment\n b").is_match("ab"));
+    assert!(compile("a{2, 3}  # two or three\n").is_match("aaa"));
+    // escaped or in a class, whitespace and # are still matched
+    assert!(compile(r"a\ b\#").is_match("a b#"));
+    assert!(compile("a[ #]b").is_match("a b"));
+    assert!(compile("a[ #]b").is_match("a#b"));
+    assert!(compile("[a[ ]]+ # a run\n x").is_match("a ax"));
+    assert_eq!(parse::strip_verbose("( a | b ) * # end\n c"), "(a|b)*c");
+    assert_eq!(parse::strip_verbose(r"[ a ] \  # x"), r"[ a ]\ ");
+    // without the flag, whitespace is literal
+    assert!(nfa::NFA::from_regex("a b").unwrap().is_match("a b"));
+    assert!(Regex::newVerbose("a b".to_string())
+        .unwrap()
+        .isMatch("ab".to_string()));
+}
+
+#[test]
+fn match_slice_test() {
+    let nfa = nfa::NFA::from_regex("(é+)(x?)").unwrap();
+    let input = String::from("caféé!");
+    let found = nfa.find_match(&input).unwrap();
+    assert_eq!(found.as_str(), "éé");
+    assert_eq!((found.start(), found.end(), found.len()), (3, 5, 2));
+    assert_eq!(found.byte_range(), 3..7);
+    // the text is a slice of the input itself, not a copy
+    assert!(std::ptr::eq(found.as_str(), &input[3..7]));
+    // and outlives the Match it came from
+    let text: &str = {
+        let found = nfa.find_match(&input).unwrap();
+        found.as_str()
+    };
+    assert_eq!(text, "éé");
+
+    let all = nfa::NFA::from_regex("[a-z]+").unwrap();
+    let words: Vec<&str> = all
+        .find_iter_matches("one two  three")
+        .iter()
+        .map(|m| m.as_str())
+        .collect();
+    assert_eq!(words, vec!["one", "two", "three"]);
+    assert_eq!(nfa.find_match("abc"), None);
+
+    let captures = nfa.captures_matches("éé").unwrap();
+    assert_eq!(captures[0].unwrap().as_str(), "éé");
+    assert!(captures[1].unwrap().is_empty());
+    let optional = nfa::NFA::from_regex("a(b)?").unwrap();
+    assert_eq!(optional.captures_matches("a"), Some(vec![None]));
+}
+
+#[test]
+fn reachable_from_test() {
+    // simplified, the states are numbered in the order a BFS from the
+    // start finds them: 0 -a-> 1 -b-> 2
+    let mut nfa = nfa::NFA::from_regex("ab").unwrap();
+    nfa.simplify();
+    assert_eq!(nfa.num_states(), 3);
+    assert_eq!(nfa.reachable_from(0), BTreeSet::from([0, 1, 2]));
+    assert_eq!(nfa.reachable_from(1), BTreeSet::from([1, 2]));
+    assert_eq!(nfa.reachable_from(2), BTreeSet::from([2]));
+
+    // 0 loops on a and goes to 1 on b, loops lead back to where they start
+    let mut nfa = nfa::NFA::from_regex("a*b").unwrap();
+    nfa.simplify();
+    assert_eq!(nfa.num_states(), 2);
+    assert_eq!(nfa.reachable_from(0), BTreeSet::from([0, 1]));
+    assert_eq!(nfa.reachable_from(1), BTreeSet::from([1]));
+}
+
+// also runs in the browser with wasm-pack test, since it's for JS
+#[cfg_attr(not(target_arch = "wasm32"), test)]
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen_test::wasm_bindgen_test)]
+fn ast_json_test() {
+    // ab|c is a union of the concatenation ab and the letter c
+    assert_eq!(
+        Regex::astJson("ab|c".to_string()),
+        concat!(
+            r#"{"type":"union","span":[0,4],"children":["#,
+            r#"{"type":"concatenation","span":[0,2],"children":["#,
+            r#"{"type":"letter","span":[0,1],"value":"a","children":[]},"#,
+            r#"{"type":"letter","span":[1,2],"value":"b","children":[]}]},"#,
+            r#"{"type":"letter","span":[3,4],"value":"c","children":[]}]}"#,
+        )
+    );
+    assert_eq!(
+        Regex::astJson(r#"(["\\]){2,}"#.to_string()),
+        concat!(
+            r#"{"type":"repetitionQuantifier","span":[0,11],"min":2,"max":null,"children":["#,
+            r#"{"type":"group","span":[0,7],"index":0,"children":["#,
+            r#"{"type":"class","span":[1,6],"ranges":[["\"","\""],["\\","\\"]],"children":[]}]}]}"#,
+        )
+    );
+    // spans are in utf-16 code units, 😀 takes two
+    assert_eq!(
+        Regex::astJson("😀*".to_string()),
+        concat!(
+            r#"{"type":"kleeneQuantifier","span":[0,3],"children":["#,
+            r#"{"type":"letter","span":[0,2],"value":"😀","children":[]}]}"#,
+        )
+    );
+    assert_eq!(Regex::astJson("a|".to_string()), "null");
+    // and it's valid JSON
+    let json: serde_json::Value =
+        serde_json::from_str(&parse_with_spans("a\n").unwrap().to_json()).unwrap();
+    assert_eq!(json["children"][1]["value"], "\n");
+
+    // a long literal is a deep chain of concatenations, which is written
+    // without recursing down it
+    let json = Regex::astJson("a".repeat(30_000));
+    assert!(json.starts_with(r#"{"type":"concatenation","span":[0,30000],"children":["#));
+    assert_eq!(json.matches(r#""type":"letter""#).count(), 30_000);
+    let ast = parse_with_spans(&"a".repeat(30_000)).unwrap();
+    assert_eq!(ast.node_at(0).unwrap().kind, AstKind::Letter('a'));
+}
+
+#[test]
+fn large_repetition_test() {
+    // 10,000 copies of a, two states each
+    let (nfa, diagnostics) = compile("(((a{10}){10}){10}){10}");
+    assert!(nfa.is_some());
+    assert_eq!(diagnostics, vec![Diagnostic::LargeRepetition(20000)]);
+    assert_eq!(
+        diagnostics[0].to_string(),
+        "warning: repetitions expand to about 20000 states, consider RepeatStrategy::Counter"
+    );
+    // too big to compile, and the counter would still fit
+    let (nfa, diagnostics) = compile("(a{1000}){1000}");
+    assert!(nfa.is_none());
+    assert_eq!(
+        diagnostics,
+        vec![
+            Diagnostic::Error(RegexError::TooManyStates),
+            Diagnostic::LargeRepetition(2_000_000)
+        ]
+    );
+    // modest repetitions, and big patterns the counter doesn't shrink
+    for re in ["a{3}", "((a{10}){10}){10}", "(a|b){2,5}c{100}"] {
+        let (nfa, diagnostics) = compile(re);
+        assert!(nfa.is_some(), "re {re}");
+        assert_eq!(diagnostics, vec![], "re {re}");
+    }
+    let long = "a".repeat(6000);
+    assert_eq!(compile(&long).1, vec![]);
+
+    // strict mode refuses them with an error that suggests the counter,
+    // before building anything
+    let (nfa, diagnostics) = compile_strict("(((a{10}){10}){10}){10}");
+    assert!(nfa.is_none());
+    let error = RegexError::LargeRepetition(20000);
+    assert_eq!(diagnostics, vec![Diagnostic::Error(error.clone())]);
+    assert_eq!(
+        error.to_string(),
+        "repetitions expand to about 20000 states, use RepeatStrategy::Counter"
+    );
+    // instead of TooManyStates, along with the other warnings
+    let (nfa, diagnostics) = compile_strict("(a{1000}){1000}|b|b");
+    assert!(nfa.is_none());
+    assert_eq!(
+        diagnostics,
+        vec![
+            Diagnostic::Error(RegexError::LargeRepetition(2_000_008)),
+            Diagnostic::DuplicateAlternative,
+        ]
+    );
+    let (nfa, diagnostics) = compile_strict("((a{10}){10}){10}");
+    assert!(nfa.is_some());
+    assert_eq!(diagnostics, vec![]);
+}
+
+#[test]
+fn match_ends_at_test() {
+    let nfa = nfa::NFA::from_regex("a|ab").unwrap();
+    assert_eq!(nfa.match_ends_at("ab", 0), vec![1, 2]);
+    assert!(nfa.match_ends_at("ab", 1).is_empty());
+    assert!(nfa.match_ends_at("ab", 3).is_empty());
+    // shortest and longest are the ends of the list
+    let nfa = nfa::NFA::from_regex("x(ab)*").unwrap();
+    let ends = nfa.match_ends_at("zxababa", 1);
+    assert_eq!(ends, vec![2, 4, 6]);
+    assert_eq!(nfa.match_at("zxababa", 1), ends.last().copied());
+    // an empty match ends where it starts
+    assert_eq!(
+        nfa::NFA::from_regex("a*").unwrap().match_ends_at("aab", 2),
+        vec![2]
+    );
+    // counters are followed like any other transition
+    let counter = nfa::CompileOptions {
+        repeat_strategy: nfa::RepeatStrategy::Counter,
+        ..Default::default()
+    };
+    let nfa = nfa::NFA::from_regex_with_options("a{2,4}", &counter).unwrap();
+    assert_eq!(nfa.match_ends_at("aaaaa", 0), vec![2, 3, 4]);
+}
+
+#[test]
+fn lazy_quantifier_test() {
+    let find = |re: &str, input: &str| nfa::NFA::from_regex(re).unwrap().find(input);
+
+    assert_eq!(find("a+?", "aaa"), Some((0, 1)));
+    assert_eq!(find("a+", "aaa"), Some((0, 3)));
+    assert_eq!(find("a*?", "aaa"), Some((0, 0)));
+    assert_eq!(find("a??", "a"), Some((0, 0)));
+    assert_eq!(find("a{2,4}?", "aaaa"), Some((0, 2)));
+    assert_eq!(find("a{2,}?", "aaaa"), Some((0, 2)));
+    // lazy stops at the first end that lets the rest match
+    assert_eq!(find("a*?b", "aab"), Some((0, 3)));
+    assert_eq!(find("x.*?y", "xaybyy"), Some((0, 3)));
+    assert_eq!(find("x.*y", "xaybyy"), Some((0, 6)));
+    assert_eq!(
+        nfa::NFA::from_regex("<.+?>").unwrap().find_iter("<a><bc>"),
+        vec![(0, 3), (3, 7)]
+    );
+    // whether the whole input matches doesn't change
+    let nfa = nfa::NFA::from_regex("a+?").unwrap();
+    assert!(nfa.is_match("aaa"));
+    assert_eq!(nfa.match_kind(), nfa::MatchKind::LeftmostFirst);
+    assert_eq!(nfa.match_at("aaa", 1), Some(2));
+    assert_eq!(nfa.match_ends_at("aaa", 1), vec![2, 3]);
+    // captures prefer the same way
+    assert_eq!(
+        nfa::NFA::from_regex("(a+?)(a*)").unwrap().captures("aaa"),
+        Some(vec![Some((0, 1)), Some((1, 3))])
+    );
+
+    // with a lazy quantifier, unions prefer their left side too
+    assert_eq!(find("(a|ab)c*?", "ab"), Some((0, 1)));
+    assert_eq!(find("a|ab", "ab"), Some((0, 2)));
+    let first = nfa::CompileOptions {
+        match_kind: nfa::MatchKind::LeftmostFirst,
+        ..Default::default()
+    };
+    let nfa = nfa::NFA::from_regex_with_options("a|ab", &first).unwrap();
+    assert_eq!(nfa.find("ab"), Some((0, 1)));
+    let nfa = nfa::NFA::from_regex_with_options("(a|ab)(c|bcd)", &first).unwrap();
+    assert_eq!(nfa.find("abcd"), Some((0, 4)));
+    // the one state of .* still prefers to go on
+    let nfa = nfa::NFA::from_regex_with_options("x.*", &first).unwrap();
+    assert_eq!(nfa.find("xab"), Some((0, 3)));
+
+    // counted loops are lazy the same way
+    let counter = nfa::CompileOptions {
+        repeat_strategy: nfa::RepeatStrategy::Counter,
+        ..Default::default()
+    };
+    let nfa = nfa::NFA::from_regex_with_options("a{2,4}?", &counter).unwrap();
+    assert!(nfa.has_counters());
+    assert_eq!(nfa.find_iter("aaaaa"), vec![(0, 2), (2, 4)]);
+    let nfa = nfa::NFA::from_regex_with_options("(ab){1,3}?b", &counter).unwrap();
+    assert_eq!(nfa.find("ababb"), Some((0, 5)));
+
+    assert_eq!(
+        parse::parse_re_to_tokens("a*?", nfa::DEFAULT_MAX_DEPTH).unwrap(),
+        vec![
+            parse::Token::Letter('a'),
+            parse::Token::KleeneQuantifier,
+            parse::Token::Lazy
+        ]
+    );
+    assert_eq!(
+        nfa::NFA::from_postfix(vec![parse::Token::Letter('a'), parse::Token::Lazy]).err(),
+        Some(RegexError::InvalidPostfix)
+    );
+}
+
+#[test]
+fn escape_test() {
+    assert_eq!(Regex::escape("a.b*"), r"a\.b\*");
+    let regex = Regex::new(Regex::escape("a.b*")).unwrap();
+    assert!(regex.isMatch("a.b*".to_string()));
+    for input in ["axb", "a.bbb", "a.b", "ab*", ""] {
+        assert!(!regex.isMatch(input.to_string()), "input {input}");
+    }
+
+    // every reserved char, and text that isn't reserved stays as it is
+    let text = r"\()|*.?+^$[]{}";
+    let nfa = nfa::NFA::from_regex(&nfa::escape(text)).unwrap();
+    assert!(nfa.is_match(text));
+    assert_eq!(nfa.accepted_strings(text.chars().count()), vec![text]);
+    assert_eq!(nfa::escape("héllo wörld-#&"), "héllo wörld-#&");
+    assert_eq!(
+        nfa::NFA::from_regex(&format!("({})+", nfa::escape("1+1")))
+            .unwrap()
+            .find("x1+11+1"),
+        Some((1, 7))
+    );
+}
+
+#[test]
+fn program_test() {
+    let regexes = [
+        "a",
+        "abc",
+        "a|b|c",
+        "(ab)*c",
+        "a+b?",
+        "(a|ab)(c|bcd)",
+        ".*x.*",
+        "[a-c]+[^a-c]",
+        "a{2,4}",
+        "(a{2}){1,3}",
+        "(a*)*",
+        "a?b?",
+        "^ab$",
+        "a*?b",
+        "(x|y)++z",
+        "héllo|wörld",
+    ];
+    let inputs = [
+        "", "a", "b", "c", "ab", "abc", "abcd", "aab", "aaaa", "aaaaa", "abbcd", "xx", "yxz", "cd",
+        "abd", "aaaaaa", "héllo", "wörld", "xyz",
+    ];
+    let check = |nfa: &nfa::NFA, re: &str| {
+        let program = nfa.to_program();
+        assert!(!program.is_empty(), "re {re}");
+        for input in inputs {
+            assert_eq!(
+                program.is_match(input),
+                nfa.is_match(input),
+                "re {re} input {input}"
+            );
+        }
+    };
+    for re in regexes {
+        check(&nfa::NFA::from_regex(re).unwrap(), re);
+        // counted loops get expanded before they become instructions
+        let options = nfa::CompileOptions {
+            repeat_strategy: nfa::RepeatStrategy::Counter,
+            ..Default::default()
+        };
+        check(
+            &nfa::NFA::from_regex_with_options(re, &options).unwrap(),
+            re,
+        );
+    }
+
+    // assertions, and a wildcard restricted to its set
+    let options = nfa::CompileOptions {
+        whole_word: true,
+        ..Default::default()
+    };
+    let nfa = nfa::NFA::from_regex_with_options("ab|a", &options).unwrap();
+    check(&nfa, "ab|a whole word");
+    let options = nfa::CompileOptions {
+        wildcard_set: Some(['a', 'b'].into_iter().collect()),
+        ..Default::default()
+    };
+    let nfa = nfa::NFA::from_regex_with_options("a.*", &options).unwrap();
+    check(&nfa, "a.* over ab");
+    assert!(nfa.to_program().is_match("abba"));
+    assert!(!nfa.to_program().is_match("abc"));
+    // a wildcard that matches nothing leaves states with no way out
+    let options = nfa::CompileOptions {
+        wildcard_set: Some(Default::default()),
+        ..Default::default()
+    };
+    check(
+        &nfa::NFA::from_regex_with_options("a.|b", &options).unwrap(),
+        "a.|b over nothing",
+    );
+
+    // an input that keeps many threads alive, see benches/throughput.rs
+    // for how the two compare in time
+    let nfa = nfa::NFA::from_regex("(a|aa|aaa)*b").unwrap();
+    let input = "a".repeat(2_000);
+    assert!(!nfa.to_program().is_match(&input));
+    assert!(nfa.to_program().is_match(&(input + "b")));
+}
+
+#[test]
+fn program_captures_test() {
+    let program = nfa::NFA::from_regex("(a+)(b+)").unwrap().to_program();
+    assert_eq!(
+        program.captures("aaabb"),
+        Some(vec![Some((0, 3)), Some((3, 5))])
+    );
+    assert_eq!(program.captures("aaa"), None);
+
+    // the same priorities as NFA::captures: left sides of unions first,
+    // greedy and lazy quantifiers, the last span of a repeated group, and
+    // groups that took no part in the match
+    let cases = [
+        ("(a|ab)(c|bcd)(d*)", "abcd"),
+        ("(a*)(a*)", "aaa"),
+        ("(a*?)(a*)", "aaa"),
+        ("(a)*", "aaa"),
+        ("(a)|(b)", "b"),
+        ("((a)|b)+", "ab"),
+        ("(a{2,3})(a*)", "aaaaa"),
+        ("(.*)(x)(.*)", "axbxc"),
+        ("(h(é)llo)", "héllo"),
+        ("(^a)(b$)", "ab"),
+    ];
+    for (re, input) in cases {
+        let nfa = nfa::NFA::from_regex(re).unwrap();
+        assert_eq!(
+            nfa.to_program().captures(input),
+            nfa.captures(input),
+            "re {re}"
+        );
+    }
+
+    // a backtracking engine tries every way to split the a's between the
+    // groups before failing, the VM steps each instruction at most once
+    // per char, so four times the input is four times the steps
+    let program = nfa::NFA::from_regex("((a|a)*)*(a*)c").unwrap().to_program();
+    let steps = |len: usize| {
+        let (captures, stats) = program.captures_with_stats(&"a".repeat(len));
+        assert_eq!(captures, None);
+        assert!(stats.max_frontier_size <= program.len());
+        assert_eq!(stats.closures_computed, len + 1);
+        stats.states_dequeued
+    };
+    let short = steps(5_000);
+    assert!(short <= 5_001 * program.len());
+    assert_eq!(steps(20_000) - steps(0), 4 * (short - steps(0)));
+    assert_eq!(
+        program.captures_with_stats("aac").0,
+        program.captures("aac")
+    );
+}
+
+#[test]
+fn unanchored_program_test() {
+    let nfa = nfa::NFA::from_regex("b+c").unwrap();
+    let program = nfa.to_unanchored_program();
+    assert!(!program.is_anchored());
+    assert_eq!(program.find("aaabbbcaa"), Some((3, 7)));
+    // is_match and captures still match the whole input
+    assert!(!program.is_match("aaabbbc"));
+    assert!(program.is_match("bbbc"));
+    // an anchored program only finds matches at the start
+    let anchored = nfa.to_program();
+    assert!(anchored.is_anchored());
+    assert_eq!(anchored.find("aaabbbcaa"), None);
+    assert_eq!(anchored.find("bcaa"), Some((0, 2)));
+
+    // the same spans as NFA::find for either match kind
+    let regexes = [
+        "a|ab",
+        "(a|ab)(c|bcd)",
+        "a*",
+        "b*?",
+        "a+?b",
+        "(a|b)*?b",
+        "x",
+        "^a|b$",
+        "a{2}",
+        ".",
+    ];
+    let inputs = ["", "a", "ab", "xabcd", "bbab", "aaab", "cab", "bab", "aaaa"];
+    for re in regexes {
+        let nfa = nfa::NFA::from_regex(re).unwrap();
+        let program = nfa.to_unanchored_program();
+        for input in inputs {
+            assert_eq!(
+                program.find(input),
+                nfa.find(input),
+                "re {re} input {input}"
+            );
+        }
+    }
+    let options = nfa::CompileOptions {
+        whole_word: true,
+        ..Default::default()
+    };
+    let nfa = nfa::NFA::from_regex_with_options("ab|a", &options).unwrap();
+    assert_eq!(nfa.to_unanchored_program().find("xab ab"), Some((4, 6)));
+
+    // NFA::find starts a search at every position that each reads to the
+    // end, the program reads the input once, see benches/throughput.rs
+    let nfa = nfa::NFA::from_regex("a*b").unwrap();
+    let program = nfa.to_unanchored_program();
+    let input = "a".repeat(500);
+    assert_eq!(program.find(&input), None);
+    assert_eq!(program.find(&(input.clone() + "b")), Some((0, 501)));
+}
+
+#[test]
+fn validate_invariants_test() {
+    // every NFA is checked as it's built in debug builds, but check the
+    // corpus explicitly too, along with the ways of changing an NFA
+    let regexes = [
+        "a",
+        "a|b|c",
+        "(ab)*c",
+        "a{2,4}",
+        "(a{2}){1,3}",
+        "^ab$",
+        "a*?b",
+        "(x|y)++z",
+        ".*x",
+    ];
+    for re in regexes {
+        let mut nfa = nfa::NFA::from_regex(re).unwrap();
+        assert_eq!(nfa.validate_invariants(), Ok(()), "re {re}");
+        nfa.simplify();
+        assert_eq!(nfa.validate_invariants(), Ok(()), "re {re}");
+        let options = nfa::CompileOptions {
+            repeat_strategy: nfa::RepeatStrategy::Counter,
+            ..Default::default()
+        };
+        let nfa = nfa::NFA::from_regex_with_options(re, &options).unwrap();
+        assert_eq!(nfa.validate_invariants(), Ok(()), "re {re}");
+    }
+    let nfa = nfa::NFA::from_literals(&["cat", "car"]);
+    assert_eq!(nfa.validate_invariants(), Ok(()));
+    // the empty language trims down to a lone start state
+    let options = nfa::CompileOptions {
+        wildcard_set: Some(Default::default()),
+        ..Default::default()
+    };
+    let mut nfa = nfa::NFA::from_regex_with_options("a.", &options).unwrap();
+    nfa.simplify();
+    assert_eq!(nfa.validate_invariants(), Ok(()));
+
+    // a transition to a state that doesn't exist
+    let mut nfa = nfa::NFA::from_regex("ab").unwrap();
+    let num_states = nfa.num_states();
+    nfa.redirect_start_transition(num_states + 3);
+    let broken = nfa.validate_invariants().unwrap_err();
+    assert!(
+        broken.ends_with(&format!(
+            "has a transition to state {}, but there are only {num_states} states",
+            num_states + 3
+        )),
+        "{broken}"
+    );
+}
+
+#[test]
+fn empty_alternatives_test() {
+    let options = nfa::CompileOptions {
+        empty_alternatives: true,
+        ..Default::default()
+    };
+    let compile = |re: &str| nfa::NFA::from_regex_with_options(re, &options).unwrap();
+    for re in ["|abc", "abc|"] {
+        let nfa = compile(re);
+        assert!(nfa.is_match(""), "re {re}");
+        assert!(nfa.is_match("abc"), "re {re}");
+        assert!(!nfa.is_match("ab"), "re {re}");
+        assert!(!nfa.is_match("abcabc"), "re {re}");
+    }
+    // in groups, between two |s, and on both sides
+    let nfa = compile("x(|a|)y");
+    assert!(nfa.is_match("xy"));
+    assert!(nfa.is_match("xay"));
+    assert!(!nfa.is_match("xaay"));
+    let nfa = compile("a||b");
+    assert_eq!(nfa.accepted_strings(1), vec!["", "a", "b"]);
+    assert!(compile("|").is_match(""));
+    assert_eq!(compile("(?>|a)b").find("ab"), Some((0, 2)));
+    // the empty side is a group like any other
+    assert_eq!(
+        compile("(|a)(b)").captures("b"),
+        Some(vec![Some((0, 0)), Some((0, 1))])
+    );
+
+    // off by default
+    for re in ["|abc", "abc|", "a||b", "(|a)"] {
+        assert_eq!(
+            nfa::NFA::from_regex(re).err(),
+            Some(RegexError::MissingOperand),
+            "re {re}"
+        );
+    }
+    // only unions get an empty operand, an empty group is still empty
+    assert_eq!(
+        nfa::NFA::from_regex_with_options("a()", &options).err(),
+        Some(RegexError::Empty)
+    );
+}
+
+#[test]
+fn octal_escape_test() {
+    let nfa = nfa::NFA::from_regex(r"\101").unwrap();
+    assert!(nfa.is_match("A"));
+    assert!(!nfa.is_match("101"));
+    assert!(nfa::NFA::from_regex(r"a\012b").unwrap().is_match("a\nb"));
+    assert!(nfa::NFA::from_regex(r"\0").unwrap().is_match("\0"));
+    assert!(nfa::NFA::from_regex(r"\377").unwrap().is_match("\u{ff}"));
+    // quantifiers and classes work on the whole escape
+    assert!(nfa::NFA::from_regex(r"\101+").unwrap().is_match("AAA"));
+    assert!(nfa::NFA::from_regex(r"[\101-\103]").unwrap().is_match("B"));
+    let ast = ast::parse_with_spans(r"x\101").unwrap();
+    assert_eq!(ast.children()[1].kind, ast::AstKind::Letter('A'));
+    assert_eq!(ast.children()[1].span, (1, 5));
+
+    // \0 stops at the first char that isn't an octal digit
+    let nfa = nfa::NFA::from_regex(r"\08").unwrap();
+    assert!(nfa.is_match("\08"));
+    // what would be a backreference stays an escaped digit, as does a
+    // three digit escape past \377
+    assert!(nfa::NFA::from_regex(r"(a)\1").unwrap().is_match("a1"));
+    assert!(nfa::NFA::from_regex(r"\12").unwrap().is_match("12"));
+    assert!(nfa::NFA::from_regex(r"\400").unwrap().is_match("400"));
+}
+
+#[test]
+fn derivative_test() {
+    let ast = parse_with_spans("ab*").unwrap();
+    assert!(!ast.nullable());
+    let derived = ast.derivative('a');
+    assert!(derived.nullable());
+    // what is left of ab* after an a is b*
+    assert!(matches!(derived.kind, AstKind::KleeneQuantifier(_)));
+    assert_eq!(derived.children()[0].kind, AstKind::Letter('b'));
+    assert_eq!(ast.derivative('b').kind, AstKind::Nothing);
+    assert_eq!(derived.derivative('a').kind, AstKind::Nothing);
+
+    // agrees with the NFA
+    let regexes = [
+        "a",
+        "abc",
+        "a|b|c",
+        "(ab)*c",
+        "a+b?",
+        "(a|ab)(c|bcd)",
+        ".*x.*",
+        "[a-c]+[^a-c]",
+        "a{2,4}",
+        "(a{2}){1,3}",
+        "a{2,}",
+        "(a*)*",
+        "a?b?",
+        "^ab$",
+        "a^b",
+        "a$|b",
+        "(^a)*",
+        "a*?b",
+        "(x|y)++z",
+        "héllo|wörld",
+        "(a|b)*abb",
+    ];
+    let inputs = [
+        "", "a", "b", "c", "ab", "abc", "abcd", "aab", "aaaa", "aaaaa", "abbcd", "xx", "yxz", "cd",
+        "abd", "aaaaaa", "héllo", "wörld", "xyz", "babb", "aaabb",
+    ];
+    for re in regexes {
+        let ast = parse_with_spans(re).unwrap();
+        let nfa = nfa::NFA::from_regex(re).unwrap();
+        for input in inputs {
+            assert_eq!(
+                ast.matches_by_derivative(input),
+                nfa.is_match(input),
+                "re {re} input {input}"
+            );
+        }
+    }
+
+    // unions are kept flat and without repeats, so a loop's derivatives
+    // don't keep growing
+    let ast = parse_with_spans("(a|b)*abb").unwrap();
+    let mut derived = ast.clone();
+    let mut sizes = vec![];
+    for c in "abababbaabbbab".chars() {
+        derived = derived.derivative(c);
+        sizes.push(derived.to_json().len());
+    }
+    assert!(sizes.iter().max() < Some(&(ast.to_json().len() * 4)));
+
+    // a long literal is a deep chain of concatenations, which none of
+    // this recurses down
+    let literal = "ab".repeat(15_000);
+    let ast = parse_with_spans(&literal).unwrap();
+    assert!(!ast.nullable());
+    let derived = ast.derivative('a');
+    assert_eq!(derived.clone(), derived);
+    assert_ne!(derived, ast);
+    assert_eq!(ast.derivative('b').kind, AstKind::Nothing);
+    assert!(!ast.matches_by_derivative(&literal[..10]));
+    assert!(!ast.matches_by_derivative("abb"));
+    let ast = parse_with_spans(&format!("{literal}|x")).unwrap();
+    assert!(ast.matches_by_derivative("x"));
+}
+
+#[test]
+fn derivative_dfa_test() {
+    let regexes = [
+        "(a|b)*abb",
+        "abc",
+        "a|b|c",
+        "(ab)*c",
+        "a+b?",
+        "(a|ab)(c|bcd)",
+        ".*x.*",
+        "[a-c]+[^a-c]",
+        "a{2,4}",
+        "(a{2}){1,3}",
+        "(a*)*",
+        "^ab$",
+        "a$|b",
+        "(^a)*b",
+        "(x|y)++z",
+        "héllo|wörld",
+    ];
+    let inputs = [
+        "", "a", "b", "c", "ab", "abc", "abb", "aabb", "babb", "abcd", "aaaa", "aaaaa", "xx",
+        "yxz", "cd", "abd", "héllo", "wörld", "xyz",
+    ];
+    for re in regexes {
+        let by_derivatives = parse_with_spans(re).unwrap().to_dfa().unwrap();
+        let by_subsets = nfa::NFA::from_regex(re).unwrap().to_dfa().unwrap();
+        // both are minimal, so they have the same number of states
+        assert_eq!(
+            by_derivatives.num_states(),
+            by_subsets.num_states(),
+            "re {re}"
+        );
+        for input in inputs {
+            assert_eq!(
+                by_derivatives.is_match(input),
+                by_subsets.is_match(input),
+                "re {re} input {input}"
+            );
+        }
+    }
+    assert_eq!(
+        parse_with_spans("(a|b)*abb")
+            .unwrap()
+            .to_dfa()
+            .unwrap()
+            .num_states(),
+        4
+    );
+}
+
+#[test]
+fn literal_prefix_test() {
+    let prefix = |re: &str| {
+        nfa::NFA::from_regex(re)
+            .unwrap()
+            .literal_prefix()
+            .to_string()
+    };
+    assert_eq!(prefix("abc.*"), "abc");
+    assert_eq!(prefix("abc"), "abc");
+    assert_eq!(prefix("a+b"), "a");
+    assert_eq!(prefix("(ab)+c"), "ab");
+    assert_eq!(prefix("ab|ac"), "a");
+    assert_eq!(prefix("héllo|hé"), "hé");
+    assert_eq!(prefix("^abc"), "abc");
+    assert_eq!(prefix("a?b"), "");
+    assert_eq!(prefix("[ab]c"), "");
+    assert_eq!(prefix(".abc"), "");
+    assert_eq!(
+        nfa::NFA::from_literals(&["cat", "car"]).literal_prefix(),
+        "ca"
+    );
+
+    // the same answers as searching from every position
+    let regexes = [
+        "abc.*",
+        "aa",
+        "a+b",
+        "(ab)+c",
+        "ab|ac",
+        "héllo|hé",
+        "^ab",
+        "ab$",
+        "aba",
+    ];
+    let inputs = [
+        "", "abc", "xxabcxx", "aaa", "xaab", "ababc", "abac", "xhé", "xab", "abx", "xababa", "ab",
+        "a",
+    ];
+    for re in regexes {
+        let nfa = nfa::NFA::from_regex(re).unwrap();
+        for input in inputs {
+            assert_eq!(
+                nfa.contains_match(input),
+                nfa.find(input).is_some(),
+                "re {re} input {input}"
+            );
+        }
+    }
+    let options = nfa::CompileOptions {
+        whole_word: true,
+        ..Default::default()
+    };
+    let nfa = nfa::NFA::from_regex_with_options("cat", &options).unwrap();
+    assert_eq!(nfa.literal_prefix(), "cat");
+    assert!(!nfa.contains_match("concatenate"));
+    assert!(nfa.contains_match("concatenate cat"));
+
+    // skipping to where the prefix occurs instead of searching from every
+    // position, when the prefix's first char is everywhere. see
+    // benches/throughput.rs for how much that saves
+    let nfa = nfa::NFA::from_regex("abc.*x").unwrap();
+    let input = "ab".repeat(5_000);
+    assert!(nfa.contains_match(&(input.clone() + "abcx")));
+    assert!(!nfa.contains_match(&(input + "abc")));
+}
+
+#[test]
+fn group_count_test() {
+    let count = |re: &str| Regex::new(re.to_string()).unwrap().groupCount();
+    assert_eq!(count("(a)(b(c))"), 3);
+    assert_eq!(count("(?:a)b"), 0);
+    assert_eq!(count("(?>a)(b)"), 1);
+    assert_eq!(count("abc"), 0);
+    // the length of what captures returns
+    let nfa = nfa::NFA::from_regex("(?:(a)|b)(c)").unwrap();
+    assert_eq!(nfa.group_count(), 2);
+    assert_eq!(nfa.captures("ac"), Some(vec![Some((0, 1)), Some((1, 2))]));
+    assert_eq!(nfa.captures("bc"), Some(vec![None, Some((1, 2))]));
+
+    // (?: groups like any other group, and doesn't capture
+    let nfa = nfa::NFA::from_regex("(?:ab)+").unwrap();
+    assert!(nfa.is_match("abab"));
+    assert!(!nfa.is_match("aba"));
+    assert_eq!(nfa::NFA::from_regex("(?:)").err(), Some(RegexError::Empty));
+    assert!(nfa::NFA::from_regex(r"\(?:").unwrap().is_match(":"));
+}
+
+#[test]
+fn never_and_always_test() {
+    let never = nfa::NFA::never();
+    let always = nfa::NFA::always(&['0', '1']);
+    let mut inputs = vec![String::new()];
+    for _ in 0..4 {
+        let longer: Vec<String> = inputs
+            .iter()
+            .flat_map(|s| [format!("{s}0"), format!("{s}1")])
+            .collect();
+        inputs.extend(longer);
+    }
+    for input in &inputs {
+        assert!(!never.is_match(input), "{input}");
+        assert!(!never.contains_match(input), "{input}");
+        assert!(always.is_match(input), "{input}");
+    }
+    assert!(!always.is_match("012"));
+    assert!(!always.is_match("a"));
+    assert_eq!(always.accepted_strings(1), vec!["", "0", "1"]);
+    assert!(never.accepted_strings(3).is_empty());
+
+    let any = nfa::NFA::always_any();
+    for input in ["", "a", "héllo\n", "012"] {
+        assert!(any.is_match(input));
+    }
+
+    for input in ["", "x", "anything at all"] {
+        assert!(!Regex::never().isMatch(input.to_string()));
+        assert!(Regex::always().fullMatch(input.to_string()));
+    }
+}
+
+#[test]
+fn symbol_nfa_test() {
+    use nfa::{Label, SymbolNFA};
+
+    // 1 (2 | 3)* 4 over u32 symbols, with any one symbol allowed after it
+    let mut nfa = SymbolNFA::<u32>::new();
+    let start = nfa.start_id();
+    let looping = nfa.add_state();
+    let four = nfa.add_state();
+    let out = nfa.add_state();
+    nfa.add_transition(start, Label::Exact(1), looping);
+    nfa.add_transition(looping, Label::Exact(2), looping);
+    nfa.add_transition(looping, Label::Exact(3), looping);
+    nfa.add_transition(looping, Label::Exact(4), four);
+    nfa.add_transition(four, Label::Epsilon, out);
+    nfa.add_transition(four, Label::Any, out);
+    nfa.set_accepting(out, true);
+    assert_eq!(nfa.num_states(), 4);
+
+    assert!(nfa.is_match(&[1, 4]));
+    assert!(nfa.is_match(&[1, 2, 3, 3, 2, 4]));
+    assert!(nfa.is_match(&[1, 3, 4, 99]));
+    assert!(!nfa.is_match(&[1, 3, 4, 99, 99]));
+    assert!(!nfa.is_match(&[1, 5, 4]));
+    assert!(!nfa.is_match(&[]));
+    assert!(!SymbolNFA::<u32>::new().is_match(&[]));
+
+    // regexes are automata over chars, and match the same as a SymbolNFA
+    let regex = nfa::NFA::from_regex("a(b|c)*.?d").unwrap();
+    let symbols = regex.to_symbol_nfa().unwrap();
+    for input in ["ad", "abcbd", "axd", "abxd", "ab", "abcxyd", ""] {
+        let chars: Vec<char> = input.chars().collect();
+        assert_eq!(symbols.is_match(&chars), regex.is_match(input), "{input}");
+    }
+    assert!(nfa::NFA::from_regex("[ab]")
+        .unwrap()
+        .to_symbol_nfa()
+        .is_none());
+    assert!(nfa::NFA::from_regex("^a")
+        .unwrap()
+        .to_symbol_nfa()
+        .is_none());
+}
+
+#[test]
+fn find_from_test() {
+    let nfa = nfa::NFA::from_regex("ab").unwrap();
+    assert_eq!(nfa.find_from("abxab", 0), Some((0, 2)));
+    assert_eq!(nfa.find_from("abxab", 1), Some((3, 5)));
+    assert_eq!(nfa.find_from("abxab", 2), Some((3, 5)));
+    assert_eq!(nfa.find_from("abxab", 4), None);
+    assert_eq!(nfa.find_from("abxab", 6), None);
+
+    // resuming at the end of each match gives what find_iter does
+    for (re, input) in [
+        ("a+", "aaxaxxaa"),
+        ("x*", "axxb"),
+        ("é|ü+", "éaüüé"),
+        ("^a", "aaa"),
+    ] {
+        let nfa = nfa::NFA::from_regex(re).unwrap();
+        let mut spans = vec![];
+        let mut from = 0;
+        while let Some((start, end)) = nfa.find_from(input, from) {
+            spans.push((start, end));
+            from = if end == start { end + 1 } else { end };
+        }
+        assert_eq!(spans, nfa.find_iter(input), "{re} over {input}");
+    }
+
+    // what comes before from still counts for assertions
+    let nfa = nfa::NFA::from_regex("^a").unwrap();
+    assert_eq!(nfa.find_from("aa", 1), None);
+    let options = nfa::CompileOptions {
+        whole_word: true,
+        ..Default::default()
+    };
+    let nfa = nfa::NFA::from_regex_with_options("b", &options).unwrap();
+    assert_eq!(nfa.find_from("ab b", 1), Some((3, 4)));
+}
+
+#[test]
+fn graph_json_test() {
+    for re in ["a|b", "(ab)*c", "[a-z]+\\.", "^a$", "a{2,3}", "é\"\\\\"] {
+        let regex = Regex::new(re.to_string()).unwrap();
+        let graph: serde_json::Value = serde_json::from_str(&regex.graphJson()).unwrap();
+        let nodes = graph["nodes"].as_array().unwrap();
+        let links = graph["links"].as_array().unwrap();
+        assert_eq!(nodes.len(), regex.numStates(), "{re}");
+        assert_eq!(links.len(), regex.numTransitions(), "{re}");
+        let starts = nodes.iter().filter(|node| node["start"] == true).count();
+        assert_eq!(starts, 1, "{re}");
+        assert!(nodes.iter().any(|node| node["accepting"] == true), "{re}");
+        for (id, node) in nodes.iter().enumerate() {
+            assert_eq!(node["id"], id, "{re}");
+        }
+        for link in links {
+            assert!(link["source"].as_u64().unwrap() < nodes.len() as u64);
+            assert!(link["target"].as_u64().unwrap() < nodes.len() as u64);
+            assert!(link["label"].is_string());
+        }
+    }
+
+    let graph = nfa::NFA::from_literals(&["a."]).to_graph_json();
+    assert_eq!(
+        graph,
+        r#"{"nodes":[{"id":0,"accepting":false,"start":true},{"id":1,"accepting":false,"start":false},{"id":2,"accepting":true,"start":false}],"links":[{"source":0,"target":1,"label":"a"},{"source":1,"target":2,"label":"\\."}]}"#
+    );
+}
+
+#[test]
+fn factor_literals_test() {
+    let factored = nfa::CompileOptions {
+        factor_literals: true,
+        ..Default::default()
+    };
+    let build = |re: &str, options: &nfa::CompileOptions| {
+        nfa::NFA::from_regex_with_options(re, options).unwrap()
+    };
+    let inputs = [
+        "", "cat", "cot", "cut", "cit", "ca", "xcatx", "catcut", "abd", "acd", "ad", "foo",
+        "foobar", "fooba", "bar", "abc", "tea", "ten", "teal", "ea",
+    ];
+    for re in [
+        "cat|cot|cut",
+        "a(?:b|c)d",
+        "foo|foobar|bar",
+        "tea|ten|teal|ea",
+        "a|b|c",
+        "(?:c|t)(?:a|e)(?:t|n)",
+    ] {
+        let naive = build(re, &nfa::CompileOptions::default());
+        let nfa = build(re, &factored);
+        assert!(nfa.num_states() < naive.num_states(), "{re}");
+        for input in inputs {
+            assert_eq!(nfa.is_match(input), naive.is_match(input), "{re} {input}");
+            assert_eq!(nfa.find_iter(input), naive.find_iter(input), "{re} {input}");
+        }
+    }
+    // c, a single shared t and the accepting state after it
+    assert_eq!(build("cat|cot|cut", &factored).num_states(), 4);
+
+    // anything more than words is built as usual
+    for re in ["(cat|cot)", "ca.|cot", "c[ao]t|cut", "a*|b", "abc"] {
+        let naive = build(re, &nfa::CompileOptions::default());
+        assert_eq!(build(re, &factored), naive, "{re}");
+    }
+    let first = nfa::CompileOptions {
+        match_kind: nfa::MatchKind::LeftmostFirst,
+        ..factored.clone()
+    };
+    let nfa = build("a|ab", &first);
+    assert_eq!(nfa.find("ab"), Some((0, 1)));
+
+    let empty = nfa::CompileOptions {
+        empty_alternatives: true,
+        ..factored
+    };
+    let nfa = build("|abc", &empty);
+    assert!(nfa.is_match(""));
+    assert!(nfa.is_match("abc"));
+    assert!(!nfa.is_match("ab"));
+}
+
+#[test]
+fn sample_test() {
+    // xorshift, so the samples are the same on every run
+    let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut rng = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed
+    };
+    let alphabet = ['a', 'b', 'c', 'x', '1', ' '];
+    for re in [
+        "abc",
+        "a*b+",
+        "(a|bc)*x?",
+        "a.c",
+        "[a-c]+[0-9]",
+        "^(ab|c)*$",
+        "a{2,4}",
+        "x|(a|b)*c",
+        ".*1.*",
+    ] {
+        let nfa = nfa::NFA::from_regex(re).unwrap();
+        let mut sampled = BTreeSet::new();
+        for _ in 0..200 {
+            if let Some(sample) = nfa.sample(&mut rng, 50, &alphabet) {
+                assert!(nfa.is_match(&sample), "{re} sampled {sample:?}");
+                sampled.insert(sample);
+            }
+        }
+        assert!(!sampled.is_empty(), "{re}");
+        if re != "abc" {
+            assert!(sampled.len() > 1, "{re} only sampled {sampled:?}");
+        }
+    }
+
+    // wildcards only take chars from the alphabet
+    let nfa = nfa::NFA::from_regex(".+").unwrap();
+    for _ in 0..50 {
+        let sample = nfa.sample(&mut rng, 20, &['x', 'y']).unwrap();
+        assert!(sample.chars().all(|c| c == 'x' || c == 'y'), "{sample}");
+    }
+    assert_eq!(nfa.sample(&mut rng, 20, &[]), None);
+    assert_eq!(nfa::NFA::never().sample(&mut rng, 20, &alphabet), None);
+    // too few steps to get through the letters
+    let nfa = nfa::NFA::from_regex("abcdef").unwrap();
+    assert_eq!(nfa.sample(&mut rng, 3, &alphabet), None);
+}
+
+#[test]
+fn line_terminators_test() {
+    let multiline = nfa::CompileOptions {
+        multiline: true,
+        ..Default::default()
+    };
+    let compile = |re: &str| nfa::NFA::from_regex_with_options(re, &multiline).unwrap();
+
+    // $ holds before the \r of a \r\n, not between the \r and the \n
+    let nfa = compile("[a-z]+$");
+    assert_eq!(nfa.find_iter("ab\r\ncd\r\n"), vec![(0, 2), (4, 6)]);
+    assert_eq!(compile("a$").find("a\r\nb"), Some((0, 1)));
+    assert_eq!(compile("\r$").find("a\r\nb"), None);
+    assert_eq!(compile("\r\n$").find("a\r\n"), Some((1, 3)));
+    // and ^ after the \n
+    assert_eq!(compile("^b").find("a\r\nb"), Some((3, 4)));
+    assert_eq!(compile("^\n").find("a\r\nb"), None);
+    assert_eq!(
+        compile("^[a-z]+$").find_iter("ab\r\ncd\ref\u{2028}gh\u{2029}ij\nkl"),
+        vec![(0, 2), (4, 6), (7, 9), (10, 12), (13, 15), (16, 18)]
+    );
+    // there's no empty line inside a \r\n, but a lone \r or \n, and \n\r,
+    // are line breaks on their own
+    assert!(!compile("^a$\r^$\n^b$").is_match("a\r\nb"));
+    assert!(compile("^a$\n\r^b$").is_match("a\n\rb"));
+    assert!(compile("^a$\r^b$").is_match("a\rb"));
+
+    // every way of matching agrees on where the lines are
+    let nfa = compile("x$|^y|^$");
+    let dfa = nfa.to_dfa().unwrap();
+    let program = nfa.to_unanchored_program();
+    for input in [
+        "x\r\ny",
+        "x\ry",
+        "\r\n",
+        "\r",
+        "\n\r",
+        "x\u{2028}",
+        "\u{2029}y",
+        "a\r\n\r\n",
+    ] {
+        let mut matcher = nfa.matcher();
+        matcher.feed_str(input);
+        assert_eq!(matcher.is_match(), nfa.is_match(input), "{input:?}");
+        assert_eq!(dfa.is_match(input), nfa.is_match(input), "{input:?}");
+        assert_eq!(program.is_match(input), nfa.is_match(input), "{input:?}");
+        assert_eq!(program.find(input), nfa.find(input), "{input:?}");
+    }
+
+    assert_eq!(nfa::line_col("ab\r\ncd", 4), (1, 0));
+    assert_eq!(nfa::line_col("ab\r\ncd", 5), (1, 1));
+    assert_eq!(nfa::line_col("a\rb\u{2028}c\u{2029}d", 6), (3, 0));
+    assert_eq!(nfa::line_col("a\n\rb", 3), (2, 0));
+}
+
+#[test]
+fn is_match_owned_test() {
+    for re in [
+        "abc",
+        "a*b",
+        "(a|bc)+$",
+        "[a-z]+@[a-z]+\\.com",
+        "a{2,3}",
+        "^$",
+    ] {
+        let regex = Regex::new(re.to_string()).unwrap();
+        for input in [
+            "", "abc", "aab", "b", "abcbc", "x@y.com", "aaa", "aaaa", "héllo",
+        ] {
+            assert_eq!(
+                regex.isMatchOwned(input.to_string()),
+                regex.isMatch(input.to_string()),
+                "{re} {input}"
+            );
+        }
+    }
+    // a long input and then a short one, reusing the same buffer
+    let regex = Regex::new("a*".to_string()).unwrap();
+    assert!(regex.isMatchOwned("a".repeat(100_000)));
+    assert!(!regex.isMatchOwned("ab".to_string()));
+    assert!(regex.isMatchOwned(String::new()));
+
+    let regex = Regex::newBounded("a*".to_string(), 3).unwrap();
+    assert!(!regex.isMatchOwned("aaaa".to_string()));
+    assert!(regex.inputTooLong());
+    assert!(regex.isMatchOwned("aaa".to_string()));
+}
+
+#[test]
+fn unicode_property_test() {
+    // without braces \p is still a p, and the names are checked either way
+    assert!(nfa::NFA::from_regex(r"\p+").unwrap().is_match("pp"));
+    assert_eq!(
+        nfa::NFA::from_regex(r"\p{Foo}").err(),
+        Some(RegexError::UnknownProperty("Foo".to_string()))
+    );
+    assert_eq!(
+        nfa::NFA::from_regex(r"\p{L").err(),
+        Some(RegexError::InvalidClass)
+    );
+    if !cfg!(feature = "unicode-classes") {
+        assert_eq!(
+            nfa::NFA::from_regex(r"\p{L}").err(),
+            Some(RegexError::PropertiesDisabled)
+        );
+        return;
+    }
+
+    let nfa = nfa::NFA::from_regex(r"\p{L}+").unwrap();
+    assert!(nfa.is_match("café"));
+    assert!(nfa.is_match("Ωμέγα"));
+    assert!(!nfa.is_match("12"));
+    assert!(!nfa.is_match("a b"));
+    let nfa = nfa::NFA::from_regex(r"\p{N}+").unwrap();
+    assert!(nfa.is_match("12"));
+    assert!(nfa.is_match("١٢½"));
+    assert!(!nfa.is_match("café"));
+    let nfa = nfa::NFA::from_regex(r"\p{Lu}\p{Ll}+").unwrap();
+    assert!(nfa.is_match("Éclair"));
+    assert!(!nfa.is_match("éclair"));
+    // negated, and inside classes
+    let nfa = nfa::NFA::from_regex(r"\P{L}+").unwrap();
+    assert!(nfa.is_match("12 !"));
+    assert!(!nfa.is_match("1a"));
+    let nfa = nfa::NFA::from_regex(r"[\p{Lu}\p{Nd}_]+").unwrap();
+    assert!(nfa.is_match("AB_12"));
+    assert!(!nfa.is_match("Ab"));
+    let nfa = nfa::NFA::from_regex(r"[^\p{Z}]+").unwrap();
+    assert!(nfa.is_match("no-spaces"));
+    assert!(!nfa.is_match("a\u{a0}b"));
+    assert_eq!(
+        nfa::NFA::from_regex(r"\p{Cs}").err(),
+        Some(RegexError::Empty)
+    );
+    for name in PROPERTY_NAMES {
+        assert!(
+            nfa::NFA::from_regex(&format!(r"[\p{{{name}}}a]")).is_ok(),
+            "{name}"
+        );
+    }
+}
+
+#[test]
+fn lazy_product_test() {
+    let over = |re: &str| nfa::NFA::from_regex_over(re, &['a', 'b']).unwrap();
+    // every string over {a, b} up to 10 chars
+    let mut strings = vec![String::new()];
+    for len in 0..10 {
+        let longer: Vec<String> = strings
+            .iter()
+            .filter(|s| s.len() == len)
+            .flat_map(|s| [format!("{s}a"), format!("{s}b")])
+            .collect();
+        strings.extend(longer);
+    }
+    let patterns = [
+        "a*",
+        "b*",
+        "(ab)*",
+        "a(a|b)*",
+        "(a|b)*b",
+        "aa|bb",
+        "a*b*",
+        "(a|b)(a|b)",
+        "ba*",
+        "(aa)+",
+        "a(ba)*",
+        "b?a?",
+        "(a|b)*aa(a|b)*",
+        "a|ab",
+        "(ab|b)*",
+    ];
+    let mut checked = 0;
+    for left in patterns {
+        for right in patterns {
+            let (left, right) = (over(left), over(right));
+            let left_states = left.to_dfa().unwrap().num_states() + 1;
+            let right_states = right.to_dfa().unwrap().num_states() + 1;
+            // a shortest string in the product is shorter than its number
+            // of states, so the strings above are enough to be exact
+            if left_states * right_states > 11 {
+                continue;
+            }
+            checked += 1;
+            let both = strings
+                .iter()
+                .any(|s| left.is_match(s) && right.is_match(s));
+            let only_left = strings
+                .iter()
+                .any(|s| left.is_match(s) && !right.is_match(s));
+            assert_eq!(left.intersects(&right), both, "{left:?} {right:?}");
+            assert_eq!(left.is_subset_of(&right), !only_left);
+            assert_eq!(
+                left.is_subset_of(&right) && right.is_subset_of(&left),
+                left.is_equivalent(&right)
+            );
+        }
+    }
+    assert!(checked > 50, "{checked}");
+
+    let nfa = |re: &str| nfa::NFA::from_regex(re).unwrap();
+    assert!(nfa("a+").is_subset_of(&nfa("a*")));
+    assert!(!nfa("a*").is_subset_of(&nfa("a+")));
+    assert!(nfa("cat|dog").is_subset_of(&nfa("[a-z]+")));
+    assert!(nfa("^ab$").intersects(&nfa("a.")));
+    assert!(!nfa("a$b").intersects(&nfa("a.*")));
+
+    // wildcards and classes over every char are exact too, not only over
+    // an alphabet
+    assert!(!nfa(".").is_subset_of(&nfa("[^x]")));
+    assert!(nfa("[^x]").is_subset_of(&nfa(".")));
+    assert!(!nfa("[^a]*").is_subset_of(&nfa("[^b]*")));
+    assert!(nfa("[^ab]*").is_subset_of(&nfa("[^b]*")));
+    assert!(nfa("[^a]").intersects(&nfa("[^_]")));
+    assert!(!nfa("[^a]").intersects(&nfa("a")));
+    assert!(!nfa("[\u{100}-\u{300}]").is_subset_of(&nfa("[\u{200}-\u{400}]")));
+    assert!(nfa("[\u{100}-\u{300}]").intersects(&nfa("[\u{200}-\u{400}]")));
+    assert!(!nfa("[\u{100}-\u{1ff}]").intersects(&nfa("[\u{200}-\u{400}]")));
+    let options = nfa::CompileOptions {
+        whole_word: true,
+        ..Default::default()
+    };
+    let whole = nfa::NFA::from_regex_with_options("a.", &options).unwrap();
+    assert!(whole.intersects(&nfa("a[^a-z]")));
+    assert!(!whole.intersects(&nfa("a[ !?]")));
+
+    // disjoint from the first char, so there's nothing to explore, even
+    // though the minimal DFA of the large one has thousands of states
+    let small = nfa("a(a|b)*");
+    let large = nfa("b(a|b)*a(a|b){10}");
+    assert!(!small.intersects(&large));
+    assert!(!large.intersects(&small));
+    // "a" is already only in the small one
+    assert!(!small.is_subset_of(&large));
+    assert!(!large.is_subset_of(&small));
+}
+
+#[test]
+fn dot_matches_newline_test() {
+    let no_newline = nfa::CompileOptions {
+        dot_matches_newline: false,
+        ..Default::default()
+    };
+    let compile = |re: &str, options: &nfa::CompileOptions| {
+        nfa::NFA::from_regex_with_options(re, options).unwrap()
+    };
+
+    // on by default, which is how . has always been
+    assert!(nfa::CompileOptions::default().dot_matches_newline);
+    assert!(compile("a.b", &Default::default()).is_match("a\nb"));
+    assert!(nfa::NFA::from_regex("a.b").unwrap().is_match("a\nb"));
+    assert!(Regex::new("a.b".to_string())
+        .unwrap()
+        .isMatch("a\nb".to_string()));
+
+    let nfa = compile("a.b", &no_newline);
+    assert!(!nfa.is_match("a\nb"));
+    assert!(nfa.is_match("axb"));
+    assert!(nfa.is_match("a😀b"));
+    for terminator in nfa::LINE_TERMINATORS {
+        assert!(!nfa.is_match(&format!("a{terminator}b")), "{terminator:?}");
+    }
+    // the quantified fragments too
+    assert_eq!(
+        compile(".+", &no_newline).find_iter("ab\ncd"),
+        vec![(0, 2), (3, 5)]
+    );
+    assert_eq!(
+        compile(".+", &Default::default()).find_iter("ab\ncd"),
+        vec![(0, 5)]
+    );
+    assert!(!compile("a.*b", &no_newline).is_match("a\r\nb"));
+    assert!(compile("a.?b", &no_newline).is_match("ab"));
+
+    // and within the wildcard set
+    let restricted = nfa::CompileOptions {
+        wildcard_set: Some(BTreeSet::from(['x', '\n'])),
+        ..no_newline
+    };
+    let nfa = compile("a.b", &restricted);
+    assert!(nfa.is_match("axb"));
+    assert!(!nfa.is_match("a\nb"));
+    assert!(!nfa.is_match("ayb"));
+}
@@ -0,0 +1,93 @@
+// =================
+// UNICODE PROPERTIES
+// =================
+
+use crate::class::CharClass;
+use crate::error::RegexError;
+
+// the general categories \p{...} takes. a one letter name is every category
+// starting with it, e.g. L for Lu, Ll, Lt, Lm and Lo, and Lc is the cased
+// letters Lu, Ll and Lt
+pub const PROPERTY_NAMES: [&str; 38] = [
+    "C", "Cc", "Cf", "Cn", "Co", "Cs", "L", "Lc", "Ll", "Lm", "Lo", "Lt", "Lu", "M", "Mc", "Me",
+    "Mn", "N", "Nd", "Nl", "No", "P", "Pc", "Pd", "Pe", "Pf", "Pi", "Po", "Ps", "S", "Sc", "Sk",
+    "Sm", "So", "Z", "Zl", "Zp", "Zs",
+];
+
+// parses a \p{name} or \P{name} property class at the start of chars, the
+// chars right after a \, returning the chars it matches (every other char
+// for \P) and how many chars it takes up. None if chars don't start with
+// p{ or P{, so a \p on its own still matches a p
+pub(crate) fn parse_property(chars: &[char]) -> Option<Result<(CharClass, usize), RegexError>> {
+    let negated = match chars {
+        ['p', '{', ..] => false,
+        ['P', '{', ..] => true,
+        _ => return None,
+    };
+    let Some(close) = chars.iter().position(|&c| c == '}') else {
+        return Some(Err(RegexError::InvalidClass));
+    };
+    let name: String = chars[2..close].iter().collect();
+    let class = match property_class(&name) {
+        Ok(class) => class,
+        Err(error) => return Some(Err(error)),
+    };
+    let class = if negated { class.complement() } else { class };
+    Some(Ok((class, close + 1)))
+}
+
+// every char in the general category. the categories of all chars are
+// looked up once (see category_runs), so only the first \p{...} is slow
+#[cfg(feature = "unicode-classes")]
+fn property_class(name: &str) -> Result<CharClass, RegexError> {
+    if !PROPERTY_NAMES.contains(&name) {
+        return Err(RegexError::UnknownProperty(name.to_string()));
+    }
+    let in_category = |abbreviation: &str| match name {
+        // cased letters
+        "Lc" => ["Lu", "Ll", "Lt"].contains(&abbreviation),
+        _ if name.len() == 1 => abbreviation.starts_with(name),
+        _ => abbreviation == name,
+    };
+    let ranges = category_runs()
+        .iter()
+        .filter(|(_, _, abbreviation)| in_category(abbreviation))
+        .map(|&(low, high, _)| (low, high))
+        .collect();
+    Ok(CharClass::new(ranges))
+}
+
+// every char in runs of the same general category, as (low, high,
+// abbreviation of the category). looking up all of the chars takes a
+// while, so it's only done once
+#[cfg(feature = "unicode-classes")]
+fn category_runs() -> &'static [(char, char, &'static str)] {
+    use std::sync::OnceLock;
+
+    use crate::class::next_char;
+    use unicode_general_category::get_general_category;
+
+    static RUNS: OnceLock<Vec<(char, char, &'static str)>> = OnceLock::new();
+    RUNS.get_or_init(|| {
+        let mut runs: Vec<(char, char, &'static str)> = vec![];
+        for c in '\0'..=char::MAX {
+            let abbreviation = get_general_category(c).abbreviation();
+            match runs.last_mut() {
+                Some((_, high, last)) if *last == abbreviation && next_char(*high) == Some(c) => {
+                    *high = c
+                }
+                _ => runs.push((c, c, abbreviation)),
+            }
+        }
+        runs
+    })
+}
+
+// without the tables every property is an error, even a known one
+#[cfg(not(feature = "unicode-classes"))]
+fn property_class(name: &str) -> Result<CharClass, RegexError> {
+    match PROPERTY_NAMES.contains(&name) {
+        true => Err(RegexError::PropertiesDisabled),
+        false => Err(RegexError::UnknownProperty(name.to_string())),
+    }
+}